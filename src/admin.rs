@@ -0,0 +1,543 @@
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{collections::BTreeMap, sync::Mutex};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    cache::RefreshReport,
+    config::AdminConfig,
+    error::Error,
+    parse::RefreshScope,
+    server::{new_cache, CACHE, PAUSE},
+};
+
+pub const TIMESTAMP_HEADER: &str = "x-admin-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-admin-signature";
+/// A value the client never reuses, so that a signed request can't be sent again
+pub const NONCE_HEADER: &str = "x-admin-nonce";
+
+// signed requests carry no state, so the nonces are only remembered by this instance, and only
+// while their timestamps are within the skew
+static SEEN_NONCES: Mutex<BTreeMap<String, i64>> = Mutex::new(BTreeMap::new());
+const MAX_NONCE_LEN: usize = 128;
+
+// signed bodies are buffered to check them, and admin requests are all small
+const MAX_SIGNED_BODY: usize = 64 * 1024;
+
+/// Set on requests which carried valid admin credentials
+#[derive(Debug, Clone, Copy)]
+pub struct Admin;
+
+/// Marks the request as coming from an admin if it has a bearer token or an HMAC signature which
+/// matches `config`. Requests without credentials are let through unmarked, while requests with
+/// wrong credentials are rejected so that a misconfigured client finds out.
+pub async fn authenticate(
+    State(config): State<&'static AdminConfig>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let headers = request.headers();
+    let admin = if let Some(token) = bearer_token(headers) {
+        let valid = config
+            .token
+            .as_ref()
+            .is_some_and(|expected| bool::from(expected.as_bytes().ct_eq(token.as_bytes())));
+        if !valid {
+            return unauthorized("invalid bearer token");
+        }
+        true
+    } else if headers.contains_key(SIGNATURE_HEADER) {
+        let (parts, body) = request.into_parts();
+        let Ok(body) = to_bytes(body, MAX_SIGNED_BODY).await else {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "the signed body is too large",
+            )
+                .into_response();
+        };
+        let path = parts.uri.path_and_query().map_or("/", |x| x.as_str());
+        let checked =
+            config
+                .hmac_secret
+                .as_ref()
+                .map_or(Err("signed requests are not enabled"), |secret| {
+                    verify_signature(
+                        secret,
+                        config.max_skew_secs,
+                        &parts.headers,
+                        parts.method.as_str(),
+                        path,
+                        &body,
+                    )
+                });
+        if let Err(e) = checked {
+            return unauthorized(e);
+        }
+        request = Request::from_parts(parts, Body::from(body));
+        true
+    } else {
+        false
+    };
+    if admin {
+        request.extensions_mut().insert(Admin);
+    }
+    next.run(request).await
+}
+
+/// Rejects requests which weren't marked by `authenticate`
+pub async fn require_admin(request: Request, next: Next) -> Response {
+    if request.extensions().get::<Admin>().is_none() {
+        return unauthorized("admin credentials are required");
+    }
+    next.run(request).await
+}
+
+fn unauthorized(reason: &str) -> Response {
+    (StatusCode::UNAUTHORIZED, reason.to_string()).into_response()
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// What a signed request signs: its timestamp, nonce, method, path with the query, and body
+#[derive(Debug, Clone, Copy)]
+struct Signed<'r> {
+    timestamp: &'r str,
+    nonce: &'r str,
+    method: &'r str,
+    path: &'r str,
+    body: &'r [u8],
+}
+
+impl Signed<'_> {
+    fn message(&self) -> Vec<u8> {
+        let Self {
+            timestamp,
+            nonce,
+            method,
+            path,
+            body,
+        } = self;
+        let mut message = format!("{timestamp}\n{nonce}\n{method}\n{path}\n").into_bytes();
+        message.extend_from_slice(body);
+        message
+    }
+
+    fn mac(&self, secret: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC takes keys of any length");
+        mac.update(&self.message());
+        mac
+    }
+
+    /// The hex encoded HMAC-SHA256 of the request, which clients send in `X-Admin-Signature`
+    #[cfg(test)]
+    fn sign(&self, secret: &str) -> String {
+        hex::encode(self.mac(secret).finalize().into_bytes())
+    }
+}
+
+/// Remembers `nonce` until `signed_at` is out of the skew, and fails if it was already used
+fn use_nonce(nonce: &str, signed_at: i64, max_skew_secs: u64) -> Result<(), &'static str> {
+    let now = Utc::now().timestamp();
+    let mut seen = SEEN_NONCES
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    seen.retain(|_, at| (now - *at).unsigned_abs() <= max_skew_secs);
+    let reused = seen.insert(nonce.to_string(), signed_at).is_some();
+    drop(seen);
+    if reused {
+        return Err("the signature nonce was already used");
+    }
+    Ok(())
+}
+
+fn verify_signature(
+    secret: &str,
+    max_skew_secs: u64,
+    headers: &HeaderMap,
+    method: &str,
+    path: &str,
+    body: &[u8],
+) -> Result<(), &'static str> {
+    let header = |name| headers.get(name).and_then(|x| x.to_str().ok());
+    let timestamp = header(TIMESTAMP_HEADER).ok_or("the signature timestamp is missing")?;
+    let signed_at: i64 = timestamp
+        .parse()
+        .map_err(|_| "the signature timestamp is not a unix timestamp")?;
+    if (Utc::now().timestamp() - signed_at).unsigned_abs() > max_skew_secs {
+        return Err("the signature timestamp is too old or in the future");
+    }
+    let nonce = header(NONCE_HEADER)
+        .filter(|x| !x.is_empty() && x.len() <= MAX_NONCE_LEN)
+        .ok_or("the signature nonce is missing or too long")?;
+    let signature = header(SIGNATURE_HEADER)
+        .and_then(|x| hex::decode(x).ok())
+        .ok_or("the signature is not hex")?;
+    let signed = Signed {
+        timestamp,
+        nonce,
+        method,
+        path,
+        body,
+    };
+    signed
+        .mac(secret)
+        .verify_slice(&signature)
+        .map_err(|_| "the signature does not match")?;
+    // only after the signature is checked, so that forged requests can't use up nonces
+    use_nonce(nonce, signed_at, max_skew_secs)
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Self::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            Self::CircuitOpen => StatusCode::SERVICE_UNAVAILABLE,
+            Self::LeaseLost => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// The routes under `/admin`, which all need admin credentials
+pub fn routes() -> Router {
+    Router::new()
+        .route("/refresh", post(refresh))
+        .route("/invalidate", post(invalidate))
+        .route("/report", get(report))
+        .route("/scheduler", get(scheduler))
+        .route("/scheduler/pause", post(pause))
+        .route("/scheduler/resume", post(resume))
+        .route_layer(axum::middleware::from_fn(require_admin))
+}
+
+/// Refreshes the menus in the scope right away, even if they were refreshed recently. An empty
+/// body refreshes everything.
+async fn refresh(body: Bytes) -> Result<Json<Option<RefreshReport>>, Error> {
+    Ok(Json(force_refresh(&parse_scope(&body)?).await?))
+}
+
+// a body which isn't a valid scope is rejected rather than taken as everything, since that is
+// the most expensive refresh there is
+fn parse_scope(body: &[u8]) -> Result<RefreshScope, Error> {
+    if body.trim_ascii().is_empty() {
+        return Ok(RefreshScope::default());
+    }
+    serde_json::from_slice(body)
+        .map_err(|e| Error::InvalidInput(format!("the refresh scope is invalid: {e}")))
+}
+
+/// Throws away the cached menus and fetches all of them again
+async fn invalidate() -> Result<Json<Option<RefreshReport>>, Error> {
+    Ok(Json(invalidate_cache().await?))
+}
+
+async fn report() -> Json<Option<RefreshReport>> {
+    let cache = CACHE.get_or_init(new_cache).await;
+    Json(cache.get().await.report().cloned())
+}
+
+#[derive(Debug, serde::Serialize)]
+struct SchedulerStatus {
+    paused: bool,
+}
+
+async fn scheduler() -> Json<SchedulerStatus> {
+    Json(SchedulerStatus {
+        paused: PAUSE.is_paused(),
+    })
+}
+
+async fn pause() -> Json<SchedulerStatus> {
+    set_paused(true);
+    scheduler().await
+}
+
+async fn resume() -> Json<SchedulerStatus> {
+    set_paused(false);
+    scheduler().await
+}
+
+// shared with the GraphQL mutations
+
+pub async fn force_refresh(scope: &RefreshScope) -> Result<Option<RefreshReport>, Error> {
+    tracing::info!("Admin forced a refresh of {scope:?}");
+    let cache = CACHE.get_or_init(new_cache).await;
+    cache.force_refresh(scope).await?;
+    Ok(cache.get().await.report().cloned())
+}
+
+pub async fn invalidate_cache() -> Result<Option<RefreshReport>, Error> {
+    tracing::info!("Admin invalidated the cache");
+    let cache = CACHE.get_or_init(new_cache).await;
+    cache.invalidate().await?;
+    Ok(cache.get().await.report().cloned())
+}
+
+pub fn set_paused(paused: bool) {
+    tracing::info!(
+        "Admin {} the refresh scheduler",
+        if paused { "paused" } else { "resumed" }
+    );
+    PAUSE.set(paused);
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{middleware::from_fn_with_state, routing::put, Extension};
+    use reqwest::RequestBuilder;
+    use tokio::net::TcpListener;
+
+    use std::sync::{Arc, Mutex};
+
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::{
+        cache::{AnyStore, Multithreaded},
+        config::{CacheConfig, StoreKind},
+        fetch::{examples_dir, make_client, mock_upstream, Fetcher},
+    };
+
+    static DISABLED: AdminConfig = AdminConfig {
+        token: None,
+        hmac_secret: None,
+        max_skew_secs: 60,
+    };
+
+    fn enabled() -> &'static AdminConfig {
+        Box::leak(Box::new(AdminConfig {
+            token: Some("a-very-secret-token".to_string()),
+            hmac_secret: Some("a-very-secret-hmac-key".to_string()),
+            ..DISABLED.clone()
+        }))
+    }
+
+    // answers whether the request was marked as coming from an admin, and echoes the body
+    async fn serve(config: &'static AdminConfig) -> String {
+        let app = Router::new()
+            .route(
+                "/echo",
+                put(|admin: Option<Extension<Admin>>, body: String| async move {
+                    format!("{} {body}", admin.is_some())
+                }),
+            )
+            .nest("/admin", routes())
+            .layer(from_fn_with_state(config, authenticate));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    async fn send(request: RequestBuilder) -> (StatusCode, String) {
+        let response = request.send().await.unwrap();
+        (
+            StatusCode::from_u16(response.status().as_u16()).unwrap(),
+            response.text().await.unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_bearer_token() {
+        let url = format!("{}/echo", serve(enabled()).await);
+        let client = make_client();
+        assert_eq!(
+            send(
+                client
+                    .put(&url)
+                    .bearer_auth("a-very-secret-token")
+                    .body("{}")
+            )
+            .await,
+            (StatusCode::OK, "true {}".to_string())
+        );
+        assert_eq!(
+            send(client.put(&url).bearer_auth("a-guess")).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        // without credentials the request goes through, just not as an admin
+        assert_eq!(
+            send(client.put(&url)).await,
+            (StatusCode::OK, "false ".to_string())
+        );
+        // a token is never accepted when none is configured
+        let url = format!("{}/echo", serve(&DISABLED).await);
+        assert_eq!(
+            send(client.put(&url).bearer_auth("a-very-secret-token"))
+                .await
+                .0,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_signature() {
+        let config = enabled();
+        let secret = config.hmac_secret.as_deref().unwrap();
+        let url = format!("{}/echo?x=1", serve(config).await);
+        let client = make_client();
+        let body = r#"{"locationId":"40"}"#;
+        let signed =
+            |url: &str, timestamp: i64, nonce: &str, signed_body: &str, sent_body: &str| {
+                let timestamp = timestamp.to_string();
+                let signature = Signed {
+                    timestamp: &timestamp,
+                    nonce,
+                    method: "PUT",
+                    path: "/echo?x=1",
+                    body: signed_body.as_bytes(),
+                }
+                .sign(secret);
+                client
+                    .put(url)
+                    .header(TIMESTAMP_HEADER, timestamp)
+                    .header(NONCE_HEADER, nonce)
+                    .header(SIGNATURE_HEADER, signature)
+                    .body(sent_body.to_string())
+            };
+        let now = Utc::now().timestamp();
+        // the body is still readable after being checked
+        assert_eq!(
+            send(signed(&url, now, "nonce-1", body, body)).await,
+            (StatusCode::OK, format!("true {body}"))
+        );
+        // the same request sent again within the skew
+        assert_eq!(
+            send(signed(&url, now, "nonce-1", body, body)).await,
+            (
+                StatusCode::UNAUTHORIZED,
+                "the signature nonce was already used".to_string()
+            )
+        );
+        // a tampered body, which doesn't use up its nonce
+        assert_eq!(
+            send(signed(&url, now, "nonce-2", body, "{}")).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(signed(&url, now, "nonce-2", body, body)).await.0,
+            StatusCode::OK
+        );
+        // a request replayed after the skew
+        assert_eq!(
+            send(signed(&url, now - 61, "nonce-3", body, body)).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        // without a nonce
+        assert_eq!(
+            send(signed(&url, now, "", body, body)).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        let url = format!("{}/echo?x=1", serve(&DISABLED).await);
+        assert_eq!(
+            send(signed(&url, now, "nonce-4", body, body)).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes_need_credentials() {
+        let url = serve(enabled()).await;
+        let client = make_client();
+        assert_eq!(
+            send(client.get(format!("{url}/admin/scheduler"))).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(
+                client
+                    .get(format!("{url}/admin/scheduler"))
+                    .bearer_auth("a-very-secret-token")
+            )
+            .await,
+            (StatusCode::OK, r#"{"paused":false}"#.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scoped_refresh() {
+        // the upstream, which remembers the pages it served
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&served);
+        let upstream = mock_upstream::router(examples_dir().to_path_buf()).layer(
+            axum::middleware::from_fn(move |request: Request, next: Next| {
+                log.lock().unwrap().push(request.uri().to_string());
+                next.run(request)
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, upstream).await });
+        let fetcher = Fetcher::live()
+            .with_base(base.parse().unwrap())
+            .with_today(NaiveDate::from_ymd_opt(2024, 4, 6).unwrap());
+        let config = CacheConfig {
+            store: StoreKind::Memory,
+            ..CacheConfig::default()
+        };
+        let store = AnyStore::from_config(&config).await.unwrap();
+        let cache = Multithreaded::new(store, fetcher, config).await.unwrap();
+        assert!(
+            CACHE.set(cache).is_ok(),
+            "only this test should set up the cache"
+        );
+
+        let url = format!("{}/admin/refresh", serve(enabled()).await);
+        let client = make_client();
+        let refresh = |body: &'static str| {
+            client
+                .post(&url)
+                .bearer_auth("a-very-secret-token")
+                .body(body)
+        };
+        let (status, _) = send(refresh(r#"{"locationId":"40"}"#)).await;
+        assert_eq!(status, StatusCode::OK);
+        let served = std::mem::take(&mut *served.lock().unwrap());
+        let menus: Vec<_> = served.iter().filter(|x| x.contains(".aspx")).collect();
+        assert!(!menus.is_empty());
+        assert!(
+            menus.iter().all(|x| x.contains("locationNum=40")),
+            "{menus:?}"
+        );
+        assert!(CACHE
+            .get()
+            .unwrap()
+            .get()
+            .await
+            .locations()
+            .iter()
+            .any(|x| x.id() == "40" && x.daily_menus().next().is_some()));
+
+        // rather than refreshing everything
+        for body in [
+            r#"{"location_id":"40"}"#,
+            r#"{"locationId":40}"#,
+            "not json",
+        ] {
+            let (status, message) = send(refresh(body)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+            assert!(
+                message.contains("the refresh scope is invalid"),
+                "{message}"
+            );
+        }
+    }
+}
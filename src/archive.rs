@@ -0,0 +1,420 @@
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use chrono::{NaiveDate, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    error::Error,
+    parse::{ChangeSet, DailyMenu, Location, Locations},
+};
+
+static ARCHIVE: OnceLock<Archive> = OnceLock::new();
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+// each daily menu is kept whole as json so it can be served back exactly as it was parsed,
+// the other tables are a normalized copy of it for querying the history with sql
+const SCHEMA: &str = "
+    PRAGMA foreign_keys = ON;
+    CREATE TABLE IF NOT EXISTS locations (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS daily_menus (
+        id INTEGER PRIMARY KEY,
+        location_id TEXT NOT NULL REFERENCES locations(id),
+        date TEXT NOT NULL,
+        archived_at TEXT NOT NULL,
+        menu TEXT NOT NULL,
+        UNIQUE (location_id, date)
+    );
+    CREATE TABLE IF NOT EXISTS sections (
+        id INTEGER PRIMARY KEY,
+        daily_menu_id INTEGER NOT NULL REFERENCES daily_menus(id) ON DELETE CASCADE,
+        meal_type TEXT NOT NULL,
+        name TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS food_items (
+        id INTEGER PRIMARY KEY,
+        section_id INTEGER NOT NULL REFERENCES sections(id) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        recipe_id TEXT
+    );
+    CREATE TABLE IF NOT EXISTS food_item_allergens (
+        food_item_id INTEGER NOT NULL REFERENCES food_items(id) ON DELETE CASCADE,
+        allergen TEXT NOT NULL,
+        PRIMARY KEY (food_item_id, allergen)
+    );
+    CREATE INDEX IF NOT EXISTS food_items_name ON food_items(name);
+    CREATE INDEX IF NOT EXISTS food_items_recipe_id ON food_items(recipe_id);
+";
+
+/// Every daily menu which has been seen, kept in a `SQLite` database so that menus which have
+/// fallen out of the cache's window can still be queried. `SQLite` blocks, so the queries run
+/// on tokio's blocking thread pool rather than holding up the runtime.
+#[derive(Debug, Clone)]
+pub struct Archive(Arc<Mutex<Connection>>);
+
+/// Sets the archive which is written to on every refresh and read from by `Location.menus`.
+/// Only the first call has an effect.
+pub fn init(archive: Archive) {
+    if ARCHIVE.set(archive).is_err() {
+        tracing::warn!("The menu archive was already initialized");
+    }
+}
+
+/// Returns `None` if the archive is disabled
+pub fn get() -> Option<&'static Archive> {
+    ARCHIVE.get()
+}
+
+// a daily menu as it is written to the archive. It doesn't borrow from the locations, so that
+// it can be written on another thread.
+struct MenuRow {
+    location_id: String,
+    date: String,
+    menu: String,
+    sections: Vec<SectionRow>,
+}
+
+struct SectionRow {
+    meal_type: String,
+    name: String,
+    food_items: Vec<FoodItemRow>,
+}
+
+struct FoodItemRow {
+    name: String,
+    recipe_id: Option<String>,
+    allergens: Vec<&'static str>,
+}
+
+impl Archive {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self(Arc::new(Mutex::new(conn))))
+    }
+
+    /// Saves the daily menus of `locations` which are in `changes` or aren't archived yet,
+    /// replacing the copies previously saved for the same location and date. The rest are
+    /// already archived as they are, so they aren't written again.
+    pub async fn save_changes(
+        &self,
+        locations: &Locations<'_>,
+        changes: &ChangeSet,
+    ) -> Result<(), Error> {
+        let Some(start) = locations
+            .iter()
+            .flat_map(Location::daily_menus)
+            .map(DailyMenu::date)
+            .min()
+        else {
+            return Ok(());
+        };
+        let archived = self
+            .blocking(move |conn| Ok(archived_since(conn, start)?))
+            .await?;
+        let modified: HashSet<_> = changes
+            .menus
+            .iter()
+            .map(|x| (x.location_id.as_str(), x.date))
+            .collect();
+        let menus = locations
+            .iter()
+            .flat_map(|location| {
+                location
+                    .daily_menus()
+                    .filter(|menu| {
+                        let date = menu.date().format(DATE_FORMAT).to_string();
+                        modified.contains(&(location.id(), menu.date()))
+                            || !archived.contains(&(location.id().to_string(), date))
+                    })
+                    .map(|menu| MenuRow::new(location.id(), menu))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if menus.is_empty() {
+            return Ok(());
+        }
+        let names: Vec<_> = locations
+            .iter()
+            .map(|x| (x.id().to_string(), x.name().to_string()))
+            .collect();
+        self.blocking(move |conn| write_menus(conn, &names, &menus))
+            .await
+    }
+
+    /// The menus of a location from `start` through `end`, or through the latest archived menu
+    /// if `end` is `None`. Sorted by date.
+    pub async fn daily_menus(
+        &self,
+        location_id: &str,
+        start: NaiveDate,
+        end: Option<NaiveDate>,
+    ) -> Result<Vec<DailyMenu<'static>>, Error> {
+        let location_id = location_id.to_string();
+        self.blocking(move |conn| {
+            query_menus(conn, &location_id, start, end)?
+                .iter()
+                .map(|menu| Ok(serde_json::from_str(menu)?))
+                .collect()
+        })
+        .await
+    }
+
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        let conn = Arc::clone(&self.0);
+        tokio::task::spawn_blocking(move || {
+            f(&mut conn.lock().expect("lock should not be poisoned"))
+        })
+        .await
+        .expect("the archive task should not panic")
+    }
+}
+
+fn write_menus(
+    conn: &mut Connection,
+    names: &[(String, String)],
+    menus: &[MenuRow],
+) -> Result<(), Error> {
+    let archived_at = Utc::now().to_rfc3339();
+    let tx = conn.transaction()?;
+    for (id, name) in names {
+        tx.execute(
+            "INSERT INTO locations (id, name) VALUES (?1, ?2)
+             ON CONFLICT (id) DO UPDATE SET name = excluded.name",
+            params![id, name],
+        )?;
+    }
+    for menu in menus {
+        save_daily_menu(&tx, menu, &archived_at)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// the location and date of every menu archived on or after `start`
+fn archived_since(
+    conn: &Connection,
+    start: NaiveDate,
+) -> rusqlite::Result<HashSet<(String, String)>> {
+    let mut statement =
+        conn.prepare_cached("SELECT location_id, date FROM daily_menus WHERE date >= ?1")?;
+    let rows = statement.query_map(params![start.format(DATE_FORMAT).to_string()], |row| {
+        Ok((row.get(0)?, row.get(1)?))
+    })?;
+    rows.collect()
+}
+
+fn query_menus(
+    conn: &Connection,
+    location_id: &str,
+    start: NaiveDate,
+    end: Option<NaiveDate>,
+) -> rusqlite::Result<Vec<String>> {
+    let mut statement = conn.prepare_cached(
+        "SELECT menu FROM daily_menus
+         WHERE location_id = ?1 AND date >= ?2 AND (?3 IS NULL OR date <= ?3)
+         ORDER BY date",
+    )?;
+    let rows = statement.query_map(
+        params![
+            location_id,
+            start.format(DATE_FORMAT).to_string(),
+            end.map(|end| end.format(DATE_FORMAT).to_string()),
+        ],
+        |row| row.get(0),
+    )?;
+    rows.collect()
+}
+
+impl MenuRow {
+    fn new(location_id: &str, menu: &DailyMenu) -> Result<Self, Error> {
+        let sections = menu
+            .all_meals()
+            .iter()
+            .flat_map(|meal| {
+                meal.sections.iter().map(|section| SectionRow {
+                    meal_type: format!("{:?}", meal.meal_type),
+                    name: section.name().to_string(),
+                    food_items: section
+                        .food_items
+                        .iter()
+                        .map(|food_item| FoodItemRow {
+                            name: food_item.name().to_string(),
+                            recipe_id: food_item.recipe_id().map(str::to_string),
+                            allergens: (&food_item.get_allergen_mask()).into(),
+                        })
+                        .collect(),
+                })
+            })
+            .collect();
+        Ok(Self {
+            location_id: location_id.to_string(),
+            date: menu.date().format(DATE_FORMAT).to_string(),
+            menu: serde_json::to_string(menu)?,
+            sections,
+        })
+    }
+}
+
+fn save_daily_menu(
+    tx: &rusqlite::Transaction,
+    menu: &MenuRow,
+    archived_at: &str,
+) -> Result<(), Error> {
+    // the sections, items and allergens of the old copy are removed by the cascade
+    let old_id: Option<i64> = tx
+        .query_row(
+            "SELECT id FROM daily_menus WHERE location_id = ?1 AND date = ?2",
+            params![menu.location_id, menu.date],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(old_id) = old_id {
+        tx.execute("DELETE FROM daily_menus WHERE id = ?1", params![old_id])?;
+    }
+    tx.execute(
+        "INSERT INTO daily_menus (location_id, date, archived_at, menu) VALUES (?1, ?2, ?3, ?4)",
+        params![menu.location_id, menu.date, archived_at, menu.menu],
+    )?;
+    let daily_menu_id = tx.last_insert_rowid();
+
+    let mut insert_section = tx.prepare_cached(
+        "INSERT INTO sections (daily_menu_id, meal_type, name) VALUES (?1, ?2, ?3)",
+    )?;
+    let mut insert_food_item = tx.prepare_cached(
+        "INSERT INTO food_items (section_id, name, recipe_id) VALUES (?1, ?2, ?3)",
+    )?;
+    let mut insert_allergen = tx.prepare_cached(
+        "INSERT OR IGNORE INTO food_item_allergens (food_item_id, allergen) VALUES (?1, ?2)",
+    )?;
+    for section in &menu.sections {
+        insert_section.execute(params![daily_menu_id, section.meal_type, section.name])?;
+        let section_id = tx.last_insert_rowid();
+        for food_item in &section.food_items {
+            insert_food_item.execute(params![section_id, food_item.name, food_item.recipe_id])?;
+            let food_item_id = tx.last_insert_rowid();
+            for allergen in &food_item.allergens {
+                insert_allergen.execute(params![food_item_id, allergen])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::fetch::DEFAULT_UPSTREAM;
+    use crate::parse::changes::MenuChange;
+
+    fn locations_html() -> (scraper::Html, scraper::Html) {
+        let locations =
+            fs::read_to_string("./src/parse/html_examples/locations/locations.html").unwrap();
+        let menu =
+            fs::read_to_string("./src/parse/html_examples/daily_menu/dining_hall.html").unwrap();
+        (
+            scraper::Html::parse_document(&locations),
+            scraper::Html::parse_document(&menu),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load() {
+        let (locations_html, menu_html) = locations_html();
+        let mut locations = Locations::from_html_element(
+            locations_html.root_element(),
+            &DEFAULT_UPSTREAM.parse().unwrap(),
+        )
+        .unwrap();
+        let menus = [menu_html];
+        let location = locations.iter_mut().next().unwrap();
+        location.add_meals(menus.iter()).unwrap();
+        let location_id = location.metadata().id().to_string();
+        let date = location.daily_menus().next().unwrap().date();
+
+        let archive = Archive::open_in_memory().unwrap();
+        let mut changes = ChangeSet {
+            refreshed_at: Utc::now(),
+            menus: Vec::new(),
+        };
+        archive.save_changes(&locations, &changes).await.unwrap();
+        let archived_at = || -> String {
+            let conn = archive.0.lock().unwrap();
+            conn.query_row("SELECT archived_at FROM daily_menus", [], |row| row.get(0))
+                .unwrap()
+        };
+        let first = archived_at();
+        // menus which are already archived and didn't change aren't written again
+        archive.save_changes(&locations, &changes).await.unwrap();
+        assert_eq!(archived_at(), first);
+        // changed menus replace their archived copy rather than being duplicated
+        changes
+            .menus
+            .push(MenuChange::modified(&location_id, date, Vec::new()));
+        archive.save_changes(&locations, &changes).await.unwrap();
+        assert_ne!(archived_at(), first);
+
+        let loaded = archive.daily_menus(&location_id, date, None).await.unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(
+            serde_json::to_string(&loaded[0]).unwrap(),
+            serde_json::to_string(
+                locations
+                    .iter()
+                    .next()
+                    .unwrap()
+                    .daily_menus()
+                    .next()
+                    .unwrap()
+            )
+            .unwrap()
+        );
+        let before = date.pred_opt().unwrap();
+        assert!(archive
+            .daily_menus(&location_id, before, Some(before))
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(archive
+            .daily_menus("none", date, None)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let conn = archive.0.lock().unwrap();
+        let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        let food_items = locations
+            .iter()
+            .next()
+            .unwrap()
+            .daily_menus()
+            .flat_map(DailyMenu::food_items)
+            .count();
+        assert_eq!(count("SELECT COUNT(*) FROM daily_menus"), 1);
+        assert_eq!(
+            count("SELECT COUNT(*) FROM food_items"),
+            i64::try_from(food_items).unwrap()
+        );
+        assert!(count("SELECT COUNT(*) FROM food_item_allergens") > 0);
+        assert_eq!(count("SELECT COUNT(*) FROM locations"), 14);
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::OnceLock;
+
+use serde::de::DeserializeOwned;
+
+use crate::{config::OverrideConfig, error::Error};
+
+/// Data which is kept by hand in a TOML file bundled with the server, like the hours of the
+/// locations, and which a file in the same format can add to
+pub trait Overridable: Default {
+    /// The format of the bundled file and of the files which add to it
+    type File: DeserializeOwned;
+
+    /// Adds `file` on top of what was loaded before. `name` is what to call the file in errors.
+    fn extend(&mut self, file: Self::File, name: &str) -> Result<(), Error>;
+}
+
+/// Holds the bundled data of the server, with the file set in its config on top once `init`
+/// is called
+#[derive(Debug)]
+pub struct Bundled<T> {
+    /// What the data is called in errors and logs, ex. "hours"
+    name: &'static str,
+    contents: &'static str,
+    loaded: OnceLock<T>,
+}
+
+impl<T: Overridable> Bundled<T> {
+    /// `contents` is the bundled file, ex. `include_str!("hours.toml")`
+    pub const fn new(name: &'static str, contents: &'static str) -> Self {
+        Self {
+            name,
+            contents,
+            loaded: OnceLock::new(),
+        }
+    }
+
+    /// Only the bundled data
+    pub fn bundled(&self) -> Result<T, Error> {
+        self.load_contents(None)
+    }
+
+    /// The bundled data, with the file of `config` on top of it
+    pub fn load(&self, config: &OverrideConfig) -> Result<T, Error> {
+        let Some(path) = &config.file else {
+            return self.bundled();
+        };
+        let name = path.display().to_string();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("failed to read {name}: {e}")))?;
+        self.load_contents(Some((&contents, &name)))
+    }
+
+    /// The bundled data, with `contents` on top of it as if it were read from a file
+    #[cfg(test)]
+    pub fn with_contents(&self, contents: &str) -> Result<T, Error> {
+        self.load_contents(Some((contents, "the test file")))
+    }
+
+    fn load_contents(&self, file: Option<(&str, &str)>) -> Result<T, Error> {
+        let mut value = T::default();
+        let bundled = format!("the bundled {}", self.name);
+        for (contents, name) in std::iter::once((self.contents, bundled.as_str())).chain(file) {
+            let file = toml::from_str(contents)
+                .map_err(|e| Error::Config(format!("{name} is invalid: {e}")))?;
+            value.extend(file, name)?;
+        }
+        Ok(value)
+    }
+
+    /// Sets what `get` returns. Only the first call has an effect.
+    pub fn init(&self, value: T) {
+        if self.loaded.set(value).is_err() {
+            tracing::warn!("The {} were already initialized", self.name);
+        }
+    }
+
+    /// The bundled data if `init` wasn't called
+    pub fn get(&self) -> &T {
+        self.loaded.get_or_init(|| {
+            self.bundled()
+                .unwrap_or_else(|e| panic!("the bundled {} should be valid: {e}", self.name))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Counts(BTreeMap<String, u32>);
+
+    impl Overridable for Counts {
+        type File = BTreeMap<String, u32>;
+
+        fn extend(&mut self, file: Self::File, name: &str) -> Result<(), Error> {
+            if file.values().any(|x| *x == 0) {
+                return Err(Error::Config(format!("{name} has a count of 0")));
+            }
+            self.0.extend(file);
+            Ok(())
+        }
+    }
+
+    static COUNTS: Bundled<Counts> = Bundled::new("counts", "a = 1\nb = 2");
+
+    #[test]
+    fn test_load() {
+        let dir = std::env::temp_dir().join(format!("ucsc_menu_bundled_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("counts.toml");
+        let config = OverrideConfig {
+            file: Some(path.clone()),
+        };
+        // a file which is set has to exist
+        assert!(COUNTS.load(&config).is_err());
+
+        std::fs::write(&path, "b = 3\nc = 4").unwrap();
+        let counts = COUNTS.load(&config).unwrap();
+        assert_eq!(
+            counts.0,
+            BTreeMap::from([("a".into(), 1), ("b".into(), 3), ("c".into(), 4)])
+        );
+        assert_eq!(COUNTS.load(&OverrideConfig::default()).unwrap().0.len(), 2);
+
+        std::fs::write(&path, "b = \"three\"").unwrap();
+        let error = COUNTS.load(&config).unwrap_err().to_string();
+        assert!(error.contains(&path.display().to_string()), "{error}");
+        std::fs::write(&path, "b = 0").unwrap();
+        assert!(COUNTS.load(&config).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(COUNTS.get().0.len(), 2);
+    }
+}
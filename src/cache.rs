@@ -1,5 +1,11 @@
 mod menu_cache;
 mod multithreaded_cache;
+mod refresh_report;
+mod search_index;
+mod store;
 
-pub use menu_cache::REFRESH_INTERVAL;
+pub use menu_cache::MenuCache;
 pub use multithreaded_cache::MultithreadedCache as Multithreaded;
+pub use refresh_report::RefreshReport;
+pub use search_index::{FoodSearchHit, SearchFilter};
+pub use store::AnyStore;
@@ -1,34 +1,64 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Instant,
+};
+
+use super::refresh_report::{RefreshFailure, RefreshReport};
+use super::search_index::{FoodSearchHit, SearchFilter, SearchIndex};
+use super::store::{CacheStore, GCloudMenuCache};
 use crate::{
+    config::CacheConfig,
     error::Error,
-    fetch::{date_iter, locations_page, make_client, menus_on_date},
-    parse::Locations,
+    fetch::{
+        date_iter, fetch_label_page, fetch_long_menu_page, locations_page, long_menu_url,
+        menus_on_date, Fetcher,
+    },
+    parse::{ChangeSet, Label, Locations, LongMenu, RefreshScope},
     transpose::transposed,
 };
 use chrono::{DateTime, Utc};
-use firestore::FirestoreDb;
-use futures::{stream::FuturesUnordered, StreamExt};
-use log::info;
+use futures::future::join_all;
 use tokio::io::AsyncReadExt;
-const CACHES_COLLECTION: &str = "caches";
+use tracing::{info, info_span, instrument, warn, Instrument};
+use url::Url;
 #[derive(Debug)]
 pub struct MenuCache<'a> {
     cached_at: DateTime<Utc>,
-    locations: Locations<'a>,
+    locations: Arc<Locations<'a>>, // shared with the views of open and nearby locations
+    changes: VecDeque<ChangeSet>,  // oldest first
+    report: Option<RefreshReport>,
+    index: SearchIndex, // rebuilt from the locations, so it isn't saved
 }
-#[derive(serde::Serialize, serde::Deserialize, Default)]
-struct GCloudMenuCache {
-    cached_at: DateTime<Utc>,
-    data: Vec<u8>,
+
+// about a day of refreshes at the usual interval
+const MAX_CHANGE_SETS: usize = 96;
+
+// the change sets are flattened in beside the locations so caches saved before they existed still load
+#[derive(serde::Serialize)]
+struct CacheData<'b, 'a> {
+    #[serde(flatten)]
+    locations: &'b Locations<'a>,
+    changes: &'b VecDeque<ChangeSet>,
+    report: Option<&'b RefreshReport>,
 }
 
-pub static REFRESH_INTERVAL: chrono::Duration = chrono::Duration::minutes(15);
+#[derive(serde::Deserialize)]
+struct OwnedCacheData<'a> {
+    #[serde(flatten)]
+    locations: Locations<'a>,
+    #[serde(default)]
+    changes: VecDeque<ChangeSet>,
+    #[serde(default)]
+    report: Option<RefreshReport>,
+}
 
-impl<'a> MenuCache<'a> {
+impl MenuCache<'_> {
     async fn from_async(cache: GCloudMenuCache) -> Self {
         if cache.data.is_empty() {
             return MenuCache {
                 cached_at: cache.cached_at,
-                locations: Locations::default(),
+                ..Default::default()
             };
         }
         let mut decompress =
@@ -40,146 +70,678 @@ impl<'a> MenuCache<'a> {
             .await
             .expect("should succeed");
         info!("Size of data uncompressed: {}", dst.len());
-        let locations: Locations =
+        crate::metrics::get().cache_size(cache.data.len(), dst.len());
+        let data: OwnedCacheData =
             serde_json::from_str(&dst).expect("Data parse should always be valid");
         MenuCache {
             cached_at: cache.cached_at,
-            locations,
+            index: info_span!("index").in_scope(|| SearchIndex::build(&data.locations)),
+            locations: Arc::new(data.locations),
+            changes: data.changes,
+            report: data.report,
         }
     }
 }
 
-impl<'a> Default for MenuCache<'a> {
+impl Default for MenuCache<'_> {
     fn default() -> Self {
         Self {
             cached_at: Utc::now(),
-            locations: Locations::default(),
+            locations: Arc::default(),
+            changes: VecDeque::new(),
+            report: None,
+            index: SearchIndex::default(),
         }
     }
 }
 impl<'a> MenuCache<'a> {
-    pub async fn open() -> Result<Self, Error> {
-        let cache = Self::fetch_from_db().await?;
-        Ok(cache)
+    pub async fn open(store: &impl CacheStore) -> Result<Self, Error> {
+        let cache = store.load().await?.unwrap_or_default(); // default is an empty cache
+        Ok(MenuCache::from_async(cache).await)
     }
 
-    pub async fn maybe_refresh(&mut self) -> Result<bool, Error> {
-        if self.get_time_since_refresh() > chrono::Duration::minutes(15) {
-            self.refresh().await?;
+    /// Refreshes the menus if they were cached more than `max_age` ago
+    pub async fn maybe_refresh(
+        &mut self,
+        store: &impl CacheStore,
+        fetcher: &Fetcher,
+        config: &CacheConfig,
+        max_age: chrono::Duration,
+    ) -> Result<bool, Error> {
+        if self.get_time_since_refresh() > max_age {
+            self.refresh(store, fetcher, config, &RefreshScope::default())
+                .await?;
             Ok(true)
         } else {
             Ok(false)
         }
     }
 
-    pub fn get_time_since_refresh(&self) -> chrono::Duration {
-        Utc::now().signed_duration_since(self.cached_at)
+    pub const fn cached_at(&self) -> DateTime<Utc> {
+        self.cached_at
     }
 
-    pub fn get_time_until_refresh(&self) -> chrono::Duration {
-        REFRESH_INTERVAL - self.get_time_since_refresh()
+    pub fn get_time_since_refresh(&self) -> chrono::Duration {
+        Utc::now().signed_duration_since(self.cached_at)
     }
 
-    async fn fetch_from_db() -> Result<Self, crate::error::Error> {
-        let db = FirestoreDb::new("ucsc-menu").await?;
-        let cache: GCloudMenuCache = db
-            .fluent()
-            .select()
-            .by_id_in(CACHES_COLLECTION)
-            .obj()
-            .one("menu")
-            .await?
-            .unwrap_or_default(); // default is an empty cache
-        Ok(MenuCache::from_async(cache).await)
+    pub fn get_time_until_refresh(&self, config: &CacheConfig) -> chrono::Duration {
+        config.refresh_interval() - self.get_time_since_refresh()
     }
 
     async fn to_db_representation(&self) -> GCloudMenuCache {
-        let json = serde_json::to_string(self.locations()).unwrap();
+        let json = info_span!("serialize").in_scope(|| {
+            serde_json::to_string(&CacheData {
+                locations: &self.locations,
+                changes: &self.changes,
+                report: self.report.as_ref(),
+            })
+            .unwrap()
+        });
+        let uncompressed = json.len();
         let mut compressed = Vec::with_capacity(json.len() / 4);
         let mut compress =
             async_compression::tokio::bufread::GzipEncoder::new(std::io::Cursor::new(json));
         compress
-            .read_buf(&mut compressed)
+            .read_to_end(&mut compressed)
+            .instrument(info_span!("compress", bytes = uncompressed))
             .await
             .expect("This should succeed");
+        crate::metrics::get().cache_size(compressed.len(), uncompressed);
         GCloudMenuCache {
             cached_at: self.cached_at,
             data: compressed,
         }
     }
 
-    async fn save_to_db(&self) -> Result<(), firestore::errors::FirestoreError> {
+    async fn save(&self, store: &impl CacheStore) -> Result<(), Error> {
         let cache: GCloudMenuCache = self.to_db_representation().await;
-        let db = FirestoreDb::new("ucsc-menu").await?;
-        db.fluent()
-            .update()
-            .in_col(CACHES_COLLECTION)
-            .document_id("menu")
-            .object(&cache)
-            .execute()
-            .await?;
-        Ok(())
+        store
+            .save(&cache)
+            .instrument(info_span!("store", bytes = cache.data.len()))
+            .await
     }
-    /// Returns whether or not it refreshed. Will return error if it fails
-    async fn refresh(&mut self) -> Result<(), crate::error::Error> {
-        let client = make_client();
-        let locations_page = locations_page(&client).await?;
-        let mut locations = {
+    /// Fetches the menus in `scope` and saves them. Will return error if it fails
+    #[instrument(skip_all, fields(
+        location = scope.location_id.as_deref(),
+        start = ?scope.start,
+        end = ?scope.end,
+    ))]
+    pub async fn refresh(
+        &mut self,
+        store: &impl CacheStore,
+        fetcher: &Fetcher,
+        config: &CacheConfig,
+        scope: &RefreshScope,
+    ) -> Result<(), crate::error::Error> {
+        let started = Instant::now();
+        let locations_page = locations_page(fetcher).await?;
+        let locations = info_span!("parse_locations").in_scope(|| {
             let parsed = scraper::Html::parse_document(&locations_page);
-            let locations: Locations = Locations::from_html_element(parsed.root_element())?;
-            locations
-        };
-        {
-            let start_date = chrono::Utc::now().date_naive() - chrono::Duration::days(1); // subtract one day to make sure we try to get today's menu due to timezones
-            let week_menus: FuturesUnordered<_> = date_iter(start_date, 10)
-                .map(|x| menus_on_date(&client, &locations, Some(x)))
-                .collect();
-            let week_menus: Vec<_> = week_menus.collect().await;
-            let valid_week_menus = week_menus.into_iter().filter_map(Result::ok).collect();
-
-            let valid_week_menus: Vec<_> = transposed(valid_week_menus)
-                .into_iter()
-                .map(|v| -> Vec<_> {
-                    v.into_iter()
-                        .map(|s| scraper::Html::parse_document(&s))
-                        .collect()
-                })
-                .collect();
-            let parsed_week_menus_iter = valid_week_menus.iter();
-            for (location, htmls) in locations.iter_mut().zip(parsed_week_menus_iter) {
-                location.add_meals(htmls.iter())?;
+            let mut locations: Locations =
+                Locations::from_html_element(parsed.root_element(), fetcher.base())
+                    .inspect_err(|e| crate::metrics::get().parse_failed(e))?;
+            if let Some(id) = &scope.location_id {
+                locations.retain(|x| x.id() == id);
+                // otherwise a typo would remove every menu of the location
+                if locations.iter().next().is_none() {
+                    return Err(Error::InvalidInput(format!("Unknown location {id:?}")));
+                }
+            }
+            Ok(locations)
+        })?;
+        let start_date = config.start_date(fetcher.today());
+        let dates: Vec<_> = date_iter(start_date, config.days.into())
+            .filter(|x| scope.includes_date(*x))
+            .collect();
+        if dates.is_empty() {
+            return Err(Error::InvalidInput(format!(
+                "Only the {} days from {start_date} on are fetched",
+                config.days
+            )));
+        }
+        let week_menus = join_all(
+            dates
+                .iter()
+                .map(|x| menus_on_date(fetcher, &locations, Some(*x))),
+        )
+        .await;
+        let mut failures = Vec::new();
+        let mut failed = Vec::new();
+        let mut locations: Locations<'a> = {
+            let mut locations = locations;
+            // one page per date for each location
+            let location_menus: Vec<Vec<_>> = info_span!("parse_html").in_scope(|| {
+                transposed(week_menus)
+                    .into_iter()
+                    .map(|v| {
+                        v.into_iter()
+                            .map(|page| page.map(|s| scraper::Html::parse_document(&s)))
+                            .collect()
+                    })
+                    .collect()
+            });
+            for (location, pages) in locations.iter_mut().zip(&location_menus) {
+                for (date, page) in dates.iter().zip(pages) {
+                    let error = match page {
+                        Ok(html) => info_span!(
+                            "parse_menu",
+                            location = location.metadata().id(),
+                            %date
+                        )
+                        .in_scope(|| location.add_meal(html))
+                        .err()
+                        .map(|e| {
+                            crate::metrics::get().parse_failed(&e);
+                            e.to_string()
+                        }),
+                        Err(e) => Some(e.to_string()),
+                    };
+                    if let Some(error) = error {
+                        let id = location.metadata().id();
+                        warn!("Failed to get the menu of {id} on {date}: {error}");
+                        failures.push(RefreshFailure::short_menu(id, *date, &error));
+                        failed.push((id.to_string(), *date));
+                    }
+                }
             }
-            self.locations =
-                serde_json::from_str(&serde_json::to_string(&locations).unwrap()).unwrap();
+            // the parsed html can't be held across an await, so take ownership of the menus first
+            serde_json::from_str(&serde_json::to_string(&locations).unwrap()).unwrap()
         };
+        failures.extend(add_long_menus(fetcher, &mut locations).await);
+        failures.extend(add_labels(fetcher, &mut locations, &self.locations).await);
+        // copies the locations only if a view of them outlived the last refresh
+        let cached = Arc::make_mut(&mut self.locations);
+        cached.expire_before(start_date);
+        let menus = cached.update(locations, scope, &failed);
+        self.index = info_span!("index").in_scope(|| SearchIndex::build(&self.locations));
         self.cached_at = Utc::now();
-        self.save_to_db().await?;
+        self.report = Some(RefreshReport {
+            refreshed_at: self.cached_at,
+            failures,
+        });
+        if !menus.is_empty() {
+            info!("{} menus changed", menus.len());
+        }
+        let changes = ChangeSet {
+            refreshed_at: self.cached_at,
+            menus,
+        };
+        if let Some(archive) = crate::archive::get() {
+            // the archive is only for history, so the refresh still succeeds without it
+            if let Err(e) = archive.save_changes(&self.locations, &changes).await {
+                warn!("Failed to archive menus: {e}");
+            }
+        }
+        self.changes.push_back(changes);
+        while self.changes.len() > MAX_CHANGE_SETS {
+            self.changes.pop_front();
+        }
+        self.save(store).await?;
+        crate::metrics::get().refreshed(started.elapsed());
         Ok(())
     }
 
-    pub const fn locations(&self) -> &Locations<'a> {
+    pub fn locations(&self) -> &Locations<'a> {
         &self.locations
     }
+
+    /// The locations, kept alive by the caller rather than copied out of the cache
+    pub fn shared_locations(&self) -> Arc<Locations<'a>> {
+        Arc::clone(&self.locations)
+    }
+
+    /// The food items matching `query` across every location and date, best matches first
+    pub fn search(
+        &self,
+        query: &str,
+        filter: &SearchFilter,
+        limit: usize,
+    ) -> Vec<FoodSearchHit<'a>> {
+        self.index.search(&self.locations, query, filter, limit)
+    }
+
+    /// The pages which failed in the last refresh
+    pub const fn report(&self) -> Option<&RefreshReport> {
+        self.report.as_ref()
+    }
+
+    /// The change set of the last refresh
+    pub fn latest_changes(&self) -> Option<&ChangeSet> {
+        self.changes.back()
+    }
+
+    /// The change sets of the refreshes after `since`, oldest first. `None` if the kept history
+    /// doesn't reach back to `since`, in which case everything should be fetched again.
+    pub fn changes_since(&self, since: DateTime<Utc>) -> Option<Vec<ChangeSet>> {
+        if self.changes.front()?.refreshed_at > since {
+            return None;
+        }
+        Some(
+            self.changes
+                .iter()
+                .filter(|x| x.refreshed_at > since)
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+/// Fills in the recipe ids, portions and label urls of every meal from its long menu.
+/// The long menus only add detail to the short menus, so failures are skipped and returned.
+#[instrument(skip_all)]
+async fn add_long_menus(fetcher: &Fetcher, locations: &mut Locations<'_>) -> Vec<RefreshFailure> {
+    let requests: Vec<_> = locations
+        .iter()
+        .flat_map(|location| {
+            location.daily_menus().flat_map(move |menu| {
+                menu.long_menu_meals().map(move |(meal_type, meal_name)| {
+                    (
+                        location.metadata().clone(),
+                        menu.date(),
+                        meal_type,
+                        meal_name,
+                    )
+                })
+            })
+        })
+        .collect();
+    let pages = join_all(requests.iter().map(|(location_meta, date, _, meal_name)| {
+        fetch_long_menu_page(fetcher, location_meta, *date, meal_name)
+    }))
+    .await;
+    let mut failures = Vec::new();
+    for ((location_meta, date, meal_type, meal_name), page) in requests.into_iter().zip(pages) {
+        let page = match page {
+            Ok(page) => page,
+            Err(e) => {
+                warn!(
+                    "Failed to fetch long menu for {} on {date}: {e}",
+                    location_meta.id()
+                );
+                failures.push(RefreshFailure::long_menu(
+                    location_meta.id(),
+                    date,
+                    meal_type,
+                    &e,
+                ));
+                continue;
+            }
+        };
+        let _span = info_span!("parse_long_menu", location = location_meta.id(), %date).entered();
+        let html = scraper::Html::parse_document(&page);
+        let long_menu = match LongMenu::from_html_element(html.root_element()) {
+            Ok(long_menu) => long_menu,
+            Err(e) => {
+                crate::metrics::get().parse_failed(&e);
+                warn!(
+                    "Failed to parse long menu for {} on {date}: {e}",
+                    location_meta.id()
+                );
+                failures.push(RefreshFailure::long_menu(
+                    location_meta.id(),
+                    date,
+                    meal_type,
+                    &e,
+                ));
+                continue;
+            }
+        };
+        let menu = locations
+            .iter_mut()
+            .find(|location| *location.metadata() == location_meta)
+            .and_then(|location| location.daily_menu_mut(date));
+        if let Some(menu) = menu {
+            let base = long_menu_url(&location_meta, date, meal_name);
+            menu.add_long_menu(meal_type, &long_menu, &base);
+        }
+    }
+    failures
+}
+
+/// Attaches nutrition labels to every food item with a label url. Labels are looked up by
+/// recipe id, so only the labels of recipes which were not in `previous` are fetched.
+/// Returns the labels which failed, whose items are left without one.
+#[instrument(skip_all)]
+async fn add_labels(
+    fetcher: &Fetcher,
+    locations: &mut Locations<'_>,
+    previous: &Locations<'_>,
+) -> Vec<RefreshFailure> {
+    let mut labels: HashMap<String, Label> = previous
+        .food_items()
+        .filter_map(|item| Some((item.recipe_id()?.to_string(), item.label()?.clone())))
+        .collect();
+    let missing: HashMap<String, Url> = locations
+        .food_items()
+        .filter_map(|item| Some((item.recipe_id()?, item.label_url()?)))
+        .filter(|(recipe_id, _)| !labels.contains_key(*recipe_id))
+        .filter_map(|(recipe_id, url)| Some((recipe_id.to_string(), Url::parse(url).ok()?)))
+        .collect();
+    let missing: Vec<_> = missing.into_iter().collect();
+    let pages = join_all(
+        missing
+            .iter()
+            .map(|(_, url)| fetch_label_page(fetcher, url.clone())),
+    )
+    .await;
+    let mut failures = Vec::new();
+    for ((recipe_id, _), page) in missing.into_iter().zip(pages) {
+        let label = page.and_then(|page| {
+            let _span = info_span!("parse_label", %recipe_id).entered();
+            let html = scraper::Html::parse_document(&page);
+            Ok(Label::from_html_element(html.root_element())
+                .inspect_err(|e| crate::metrics::get().parse_failed(e))?)
+        });
+        match label {
+            Ok(label) => {
+                labels.insert(recipe_id, label);
+            }
+            Err(e) => {
+                warn!("Failed to get label of recipe {recipe_id}: {e}");
+                failures.push(RefreshFailure::label(&recipe_id, &e));
+            }
+        }
+    }
+    for item in locations.food_items_mut() {
+        let label = item.recipe_id().and_then(|id| labels.get(id)).cloned();
+        if let Some(label) = label {
+            item.set_label(label);
+        }
+    }
+    failures
 }
 
 #[cfg(test)]
 mod tests {
 
-    use std::time::Instant;
+    use std::fs;
 
     use super::*;
+    use crate::cache::refresh_report::Page;
+    #[cfg(feature = "firestore")]
+    use crate::cache::store::FirestoreStore;
+    use crate::cache::store::MemoryStore;
+    use crate::fetch::{examples, examples_dir, DEFAULT_UPSTREAM};
+    use crate::parse::{changes::MenuChangeKind, DailyMenu};
 
+    #[cfg(feature = "firestore")]
     #[tokio::test]
+    #[ignore = "needs GCP credentials"]
     async fn test_open() {
-        pretty_env_logger::init();
-        let _mc = MenuCache::open().await.unwrap();
+        tracing_subscriber::fmt::init();
+        let _mc = MenuCache::open(&FirestoreStore::new("ucsc-menu").await.unwrap())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_open_empty() {
+        let mc = MenuCache::open(&MemoryStore::default()).await.unwrap();
+        assert_eq!(mc.locations().iter().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_save_and_open() {
+        let html =
+            fs::read_to_string("./src/parse/html_examples/locations/locations.html").unwrap();
+        let document = scraper::Html::parse_document(&html);
+        let mut locations = Locations::from_html_element(
+            document.root_element(),
+            &crate::fetch::DEFAULT_UPSTREAM.parse().unwrap(),
+        )
+        .unwrap();
+        let menu = scraper::Html::parse_document(
+            &fs::read_to_string("./src/parse/html_examples/daily_menu/dining_hall.html").unwrap(),
+        );
+        let menus = [menu];
+        locations
+            .iter_mut()
+            .next()
+            .unwrap()
+            .add_meals(menus.iter())
+            .unwrap();
+        let mc = MenuCache {
+            cached_at: Utc::now(),
+            locations: Arc::new(locations),
+            changes: VecDeque::from([ChangeSet {
+                refreshed_at: Utc::now(),
+                menus: Vec::new(),
+            }]),
+            report: Some(RefreshReport {
+                refreshed_at: Utc::now(),
+                failures: vec![RefreshFailure::label("061002", &"not found")],
+            }),
+            index: SearchIndex::default(),
+        };
+
+        let store = MemoryStore::default();
+        mc.save(&store).await.unwrap();
+        let opened = MenuCache::open(&store).await.unwrap();
+        assert_eq!(opened.cached_at, mc.cached_at);
+        assert_eq!(
+            serde_json::to_string(opened.locations()).unwrap(),
+            serde_json::to_string(mc.locations()).unwrap()
+        );
+        assert_eq!(opened.changes, mc.changes);
+        assert_eq!(opened.report, mc.report);
+    }
+
+    #[tokio::test]
+    async fn test_open_without_changes() {
+        // caches saved before change sets were kept only have the locations
+        let cache = MenuCache::default();
+        let json = serde_json::to_string(cache.locations()).unwrap();
+        let mut data = Vec::new();
+        async_compression::tokio::bufread::GzipEncoder::new(json.as_bytes())
+            .read_to_end(&mut data)
+            .await
+            .unwrap();
+        let store = MemoryStore::default();
+        store
+            .save(&GCloudMenuCache {
+                cached_at: cache.cached_at,
+                data,
+            })
+            .await
+            .unwrap();
+        let opened = MenuCache::open(&store).await.unwrap();
+        assert!(opened.changes.is_empty());
+        assert_eq!(opened.changes_since(cache.cached_at), None);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_changes() {
+        let store = MemoryStore::default();
+        let mut mc = MenuCache::open(&store).await.unwrap();
+        let before = Utc::now();
+        mc.refresh(
+            &store,
+            &examples(),
+            &CacheConfig::default(),
+            &RefreshScope::default(),
+        )
+        .await
+        .unwrap();
+        let first = mc.changes.back().unwrap().clone();
+        assert_eq!(first.menus.len(), 2);
+        assert!(first
+            .menus
+            .iter()
+            .all(|x| x.kind == MenuChangeKind::Added && x.location_id == "40"));
+
+        // nothing changed upstream, so nothing is replaced
+        mc.refresh(
+            &store,
+            &examples(),
+            &CacheConfig::default(),
+            &RefreshScope::default(),
+        )
+        .await
+        .unwrap();
+        let second = mc.changes.back().unwrap().clone();
+        assert!(second.menus.is_empty());
+
+        assert_eq!(mc.changes_since(before), None);
+        assert_eq!(
+            mc.changes_since(first.refreshed_at),
+            Some(vec![second.clone()])
+        );
+        assert_eq!(
+            MenuCache::open(&store)
+                .await
+                .unwrap()
+                .changes_since(second.refreshed_at),
+            Some(vec![])
+        );
     }
 
     #[tokio::test]
     async fn test_refresh() {
-        let mut mc = MenuCache::open().await.unwrap();
+        let store = MemoryStore::default();
+        let mut mc = MenuCache::open(&store).await.unwrap();
         let start = Instant::now();
-        mc.refresh().await.unwrap();
+        mc.refresh(
+            &store,
+            &examples(),
+            &CacheConfig::default(),
+            &RefreshScope::default(),
+        )
+        .await
+        .unwrap();
         println!("{:?}", start.elapsed());
+
+        let location = mc.locations().iter().next().unwrap();
+        let dates: Vec<_> = location.daily_menus().map(DailyMenu::date).collect();
+        assert_eq!(
+            dates,
+            vec![
+                chrono::NaiveDate::from_ymd_opt(2024, 4, 5).unwrap(),
+                chrono::NaiveDate::from_ymd_opt(2024, 4, 9).unwrap()
+            ]
+        );
+        // the long menu and label were merged into the breakfast on the 5th
+        let eggs = mc
+            .locations()
+            .food_items()
+            .find(|item| item.recipe_id() == Some("061002"))
+            .expect("the long menu should have been merged");
+        assert_eq!(eggs.name(), "Cage-Free Scrambled Eggs");
+        assert_eq!(eggs.label().unwrap().nutrition.calories, Some(202));
+        // and it was saved
+        let reopened = MenuCache::open(&store).await.unwrap();
+        assert_eq!(reopened.cached_at, mc.cached_at);
+        // only two of the ten days were recorded
+        let short_menus: Vec<_> = reopened
+            .report()
+            .unwrap()
+            .failures
+            .iter()
+            .filter(|x| x.page == Page::ShortMenu)
+            .collect();
+        assert_eq!(short_menus.len(), 8);
+        assert!(short_menus
+            .iter()
+            .all(|x| x.location_id.as_deref() == Some("40")));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_keeps_failed_menus() {
+        let store = MemoryStore::default();
+        let mut mc = MenuCache::open(&store).await.unwrap();
+        mc.refresh(
+            &store,
+            &examples(),
+            &CacheConfig::default(),
+            &RefreshScope::default(),
+        )
+        .await
+        .unwrap();
+
+        // the same fixtures, except the menu of the 9th now fails to parse
+        let dir = std::env::temp_dir().join(format!("ucsc_menu_failing_{}", std::process::id()));
+        copy_dir(examples_dir(), &dir);
+        let ninth = chrono::NaiveDate::from_ymd_opt(2024, 4, 9).unwrap();
+        fs::write(dir.join("40/2024-04-09/shortmenu.html"), "<html></html>").unwrap();
+        mc.refresh(
+            &store,
+            &Fetcher::replay(&dir).unwrap(),
+            &CacheConfig::default(),
+            &RefreshScope::default(),
+        )
+        .await
+        .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let location = mc.locations().iter().next().unwrap();
+        assert_eq!(location.daily_menus().count(), 2);
+        assert!(mc.latest_changes().unwrap().menus.is_empty());
+        let short_menus: Vec<_> = mc
+            .report()
+            .unwrap()
+            .failures
+            .iter()
+            .filter(|x| x.page == Page::ShortMenu)
+            .collect();
+        assert_eq!(short_menus.len(), 9);
+        assert!(short_menus.iter().any(|x| x.date == Some(ninth)));
+    }
+
+    fn copy_dir(from: &std::path::Path, to: &std::path::Path) {
+        fs::create_dir_all(to).unwrap();
+        for entry in fs::read_dir(from).unwrap() {
+            let entry = entry.unwrap();
+            if entry.file_type().unwrap().is_dir() {
+                copy_dir(&entry.path(), &to.join(entry.file_name()));
+            } else {
+                fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_from_mock_upstream() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(crate::fetch::mock_upstream::serve(
+            examples_dir().to_path_buf(),
+            listener,
+        ));
+        let base = format!("http://{addr}/").parse().unwrap();
+        let store = MemoryStore::default();
+        let mut mc = MenuCache::open(&store).await.unwrap();
+        let fetcher = Fetcher::live()
+            .with_base(base)
+            .with_today(chrono::NaiveDate::from_ymd_opt(2024, 4, 6).unwrap());
+        mc.refresh(
+            &store,
+            &fetcher,
+            &CacheConfig::default(),
+            &RefreshScope::default(),
+        )
+        .await
+        .unwrap();
+
+        // the same menus as when replaying the fixtures directly
+        let mut replayed = MenuCache::default();
+        replayed
+            .refresh(
+                &store,
+                &examples(),
+                &CacheConfig::default(),
+                &RefreshScope::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::to_string(mc.locations()).unwrap(),
+            serde_json::to_string(replayed.locations())
+                .unwrap()
+                .replace(DEFAULT_UPSTREAM, &format!("http://{addr}/"))
+        );
+        assert!(mc
+            .locations()
+            .food_items()
+            .any(|item| item.label().is_some()));
     }
 }
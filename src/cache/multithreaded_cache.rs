@@ -1,36 +1,225 @@
 use super::menu_cache::MenuCache;
+use super::store::{CacheStore, GCloudMenuCache, RefreshLease};
+use crate::config::CacheConfig;
 use crate::error::Error;
-use std::ops::Deref;
+use crate::fetch::Fetcher;
+use crate::parse::{ChangeSet, RefreshScope};
+use std::{ops::Deref, time::Duration};
 
+use chrono::NaiveDate;
 use futures_locks::RwLock;
+use tokio::sync::{broadcast, Mutex};
+
+// subscribers which fall further behind than this skip the change sets they missed
+const UPDATES_CAPACITY: usize = 16;
+
+// how often an instance waiting on another one's refresh checks if its lease was released
+const LEASE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy)]
+enum Refresh<'s> {
+    IfOlder(chrono::Duration),
+    Forced(&'s RefreshScope),
+    FromScratch,
+}
 
 #[derive(Debug)]
-pub struct MultithreadedCache<'a>(RwLock<MenuCache<'a>>);
+pub struct MultithreadedCache<'a, S> {
+    cache: RwLock<MenuCache<'a>>,
+    store: S,
+    fetcher: Fetcher,
+    config: CacheConfig,
+    updates: broadcast::Sender<ChangeSet>,
+    // held for the whole of a refresh, so that only one runs at a time in this process
+    refreshing: Mutex<()>,
+    // identifies this instance in the store's refresh lease
+    lease_holder: String,
+}
 
-impl<'a> MultithreadedCache<'a> {
-    pub async fn new() -> Result<Self, crate::error::Error> {
-        let menu = MenuCache::open().await?;
+impl<'a, S: CacheStore> MultithreadedCache<'a, S> {
+    pub async fn new(
+        store: S,
+        fetcher: Fetcher,
+        config: CacheConfig,
+    ) -> Result<Self, crate::error::Error> {
+        let menu = MenuCache::open(&store).await?;
 
-        Ok(Self(RwLock::new(menu)))
+        Ok(Self {
+            cache: RwLock::new(menu),
+            store,
+            fetcher,
+            config,
+            updates: broadcast::channel(UPDATES_CAPACITY).0,
+            refreshing: Mutex::new(()),
+            lease_holder: format!("{}-{:016x}", std::process::id(), rand::random::<u64>()),
+        })
     }
 
+    /// Refreshes the menus if they are older than the refresh interval
     pub async fn refresh(&self) -> Result<bool, Error> {
-        // spawn local thread to do the refreshing
-        let mut new_menu = MenuCache::open().await?;
-        let refreshed = new_menu.maybe_refresh().await?;
-        if refreshed {
-            let mut guard = self.0.write().await;
-            *guard = new_menu;
+        self.refresh_if_older(self.config.refresh_interval()).await
+    }
+
+    /// Refreshes the menus if they were cached more than `max_age` ago. Returns whether newer
+    /// menus were swapped in, which may have been refreshed by another instance.
+    pub async fn refresh_if_older(&self, max_age: chrono::Duration) -> Result<bool, Error> {
+        self.refresh_with(Refresh::IfOlder(max_age)).await
+    }
+
+    /// Refreshes the menus in `scope` no matter how fresh they are
+    pub async fn force_refresh(&self, scope: &RefreshScope) -> Result<bool, Error> {
+        self.refresh_with(Refresh::Forced(scope)).await
+    }
+
+    /// Discards the menus and their history, and refreshes them from scratch
+    pub async fn invalidate(&self) -> Result<bool, Error> {
+        self.refresh_with(Refresh::FromScratch).await
+    }
+
+    // Only one refresh runs at a time across this process and the other instances sharing the
+    // store. The rest wait for it, and pick up its menus instead if they only wanted fresh ones.
+    async fn refresh_with(&self, refresh: Refresh<'_>) -> Result<bool, Error> {
+        // a refresh which waited here finds the menus fresh once the one before it is done
+        let _refreshing = self.refreshing.lock().await;
+        if let Refresh::IfOlder(max_age) = refresh {
+            let menu = MenuCache::open(&self.store).await?;
+            if menu.get_time_since_refresh() <= max_age {
+                // another instance may have refreshed since this one last looked
+                return Ok(self.swap_in_if_newer(menu).await);
+            }
+        }
+        // only the small lease is polled while waiting, and the menus are opened again once it is
+        // taken, which finds them fresh if the other instance's refresh was enough
+        let lease = RefreshLease::new(&self.lease_holder, self.config.lease_ttl());
+        if !self.store.try_lease(&lease).await? {
+            tracing::info!("Another instance is refreshing the menus, waiting for it to finish");
+            while !self.store.try_lease(&lease).await? {
+                tokio::time::sleep(LEASE_POLL_INTERVAL).await;
+            }
+        }
+
+        let refreshed = tokio::select! {
+            refreshed = self.refresh_leased(refresh) => refreshed,
+            lost = self.keep_lease() => Err(lost),
+        };
+        if let Err(e) = self.store.release_lease(&self.lease_holder).await {
+            tracing::warn!("Failed to release the refresh lease, it will expire instead: {e}");
+        }
+        refreshed
+    }
+
+    /// Renews the lease while a refresh runs, so that it doesn't expire however long the refresh
+    /// takes. Only returns once the lease was taken over, which cancels the refresh.
+    async fn keep_lease(&self) -> Error {
+        let ttl = self.config.lease_ttl();
+        let interval = (ttl / 3).to_std().unwrap_or(LEASE_POLL_INTERVAL);
+        loop {
+            tokio::time::sleep(interval).await;
+            let lease = RefreshLease::new(&self.lease_holder, ttl);
+            match self.store.try_lease(&lease).await {
+                Ok(true) => {}
+                Ok(false) => return Error::LeaseLost,
+                // the save still checks the lease, so a renewal which failed is only retried
+                Err(e) => tracing::warn!("Failed to renew the refresh lease: {e}"),
+            }
+        }
+    }
+
+    async fn refresh_leased(&self, refresh: Refresh<'_>) -> Result<bool, Error> {
+        let store = &Leased {
+            store: &self.store,
+            holder: &self.lease_holder,
+        };
+        let (fetcher, config) = (&self.fetcher, &self.config);
+        let mut new_menu = match refresh {
+            Refresh::FromScratch => MenuCache::default(),
+            // opened again since another instance may have finished refreshing before the lease
+            // was taken
+            Refresh::IfOlder(_) | Refresh::Forced(_) => MenuCache::open(store).await?,
+        };
+        match refresh {
+            Refresh::IfOlder(max_age) => {
+                new_menu
+                    .maybe_refresh(store, fetcher, config, max_age)
+                    .await?;
+            }
+            Refresh::Forced(scope) => new_menu.refresh(store, fetcher, config, scope).await?,
+            Refresh::FromScratch => {
+                new_menu
+                    .refresh(store, fetcher, config, &RefreshScope::default())
+                    .await?;
+            }
+        }
+        Ok(self.swap_in_if_newer(new_menu).await)
+    }
+
+    async fn swap_in_if_newer(&self, menu: MenuCache<'a>) -> bool {
+        let mut guard = self.cache.write().await;
+        if menu.cached_at() <= guard.cached_at() {
+            return false;
         }
+        let changes = menu.latest_changes().cloned();
+        *guard = menu;
+        drop(guard);
+        if let Some(changes) = changes {
+            // sending only fails when nobody is subscribed
+            let _ = self.updates.send(changes);
+        }
+        true
+    }
+
+    /// Receives the change set of every refresh, once its data has been swapped in
+    pub fn subscribe(&self) -> broadcast::Receiver<ChangeSet> {
+        self.updates.subscribe()
+    }
+
+    /// The date the menus are fetched as of
+    pub fn today(&self) -> NaiveDate {
+        self.fetcher.today()
+    }
+
+    pub const fn fetcher(&self) -> &Fetcher {
+        &self.fetcher
+    }
 
-        Ok(refreshed)
+    pub const fn config(&self) -> &CacheConfig {
+        &self.config
     }
 
     pub async fn get<'b>(&'b self) -> impl Deref<Target = MenuCache<'a>> + 'b
     where
         'a: 'b,
     {
-        self.0.read().await
+        self.cache.read().await
+    }
+}
+
+/// The store as a refresh holding the lease of `holder` sees it, which only saves while the
+/// lease is still held
+struct Leased<'s, S> {
+    store: &'s S,
+    holder: &'s str,
+}
+
+impl<S: CacheStore> CacheStore for Leased<'_, S> {
+    async fn load(&self) -> Result<Option<GCloudMenuCache>, Error> {
+        self.store.load().await
+    }
+
+    async fn save(&self, cache: &GCloudMenuCache) -> Result<(), Error> {
+        self.store.save_leased(cache, self.holder).await
+    }
+
+    async fn save_leased(&self, cache: &GCloudMenuCache, holder: &str) -> Result<(), Error> {
+        self.store.save_leased(cache, holder).await
+    }
+
+    async fn try_lease(&self, lease: &RefreshLease) -> Result<bool, Error> {
+        self.store.try_lease(lease).await
+    }
+
+    async fn release_lease(&self, holder: &str) -> Result<(), Error> {
+        self.store.release_lease(holder).await
     }
 }
 
@@ -38,10 +227,15 @@ impl<'a> MultithreadedCache<'a> {
 mod tests {
 
     use super::*;
+    use crate::cache::store::{FileStore, MemoryStore};
+    use crate::fetch::examples;
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_refresh() {
-        let menu = MultithreadedCache::new().await.unwrap();
+        let menu =
+            MultithreadedCache::new(MemoryStore::default(), examples(), CacheConfig::default())
+                .await
+                .unwrap();
         menu.refresh().await.unwrap();
         // try having multiple threads read from menu at the same time
         // using the get() function
@@ -62,4 +256,87 @@ mod tests {
             }
         });
     }
+
+    #[tokio::test]
+    async fn test_concurrent_refreshes() {
+        let menu =
+            MultithreadedCache::new(MemoryStore::default(), examples(), CacheConfig::default())
+                .await
+                .unwrap();
+        let (a, b) = tokio::join!(menu.refresh(), menu.refresh());
+        // the second refresh waits for the first and then finds the menus fresh
+        assert!(a.unwrap() ^ b.unwrap());
+        assert_eq!(
+            MenuCache::open(&menu.store).await.unwrap().latest_changes(),
+            menu.get().await.latest_changes()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_needs_lease() {
+        let store = MemoryStore::default();
+        let leased = Leased {
+            store: &store,
+            holder: "a",
+        };
+        let mut menu = MenuCache::default();
+        let refreshed = menu
+            .refresh(
+                &leased,
+                &examples(),
+                &CacheConfig::default(),
+                &RefreshScope::default(),
+            )
+            .await;
+        // a refresh whose lease was taken over doesn't overwrite the store
+        assert!(matches!(refreshed, Err(Error::LeaseLost)));
+        assert_eq!(store.load().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_across_instances() {
+        let path =
+            std::env::temp_dir().join(format!("ucsc_menu_instances_{}.gz", std::process::id()));
+        let a = MultithreadedCache::new(FileStore::new(&path), examples(), CacheConfig::default())
+            .await
+            .unwrap();
+        let b = MultithreadedCache::new(FileStore::new(&path), examples(), CacheConfig::default())
+            .await
+            .unwrap();
+        let (refreshed_a, refreshed_b) = tokio::join!(a.refresh(), b.refresh());
+        // both end up with the menus of the one refresh which ran
+        assert!(refreshed_a.unwrap() && refreshed_b.unwrap());
+        let saved = MenuCache::open(&FileStore::new(&path)).await.unwrap();
+        assert_eq!(a.get().await.cached_at(), saved.cached_at());
+        assert_eq!(b.get().await.cached_at(), saved.cached_at());
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(path.with_extension("gz.lock"));
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let menu =
+            MultithreadedCache::new(MemoryStore::default(), examples(), CacheConfig::default())
+                .await
+                .unwrap();
+        let mut updates = menu.subscribe();
+        assert!(menu.refresh().await.unwrap());
+        let changes = updates.try_recv().unwrap();
+        assert_eq!(changes.menus.len(), 2);
+        assert_eq!(
+            menu.get().await.latest_changes().unwrap().refreshed_at,
+            changes.refreshed_at
+        );
+
+        // the menus on and after the 6th only include the 9th
+        let appeared = menu
+            .get()
+            .await
+            .locations()
+            .appeared_items(&changes, menu.today());
+        assert!(!appeared.is_empty());
+        assert!(appeared
+            .iter()
+            .all(|x| x.date == chrono::NaiveDate::from_ymd_opt(2024, 4, 9).unwrap()));
+    }
 }
@@ -0,0 +1,72 @@
+use std::fmt::Display;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use juniper::{GraphQLEnum, GraphQLObject};
+
+use crate::parse::MealType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, GraphQLEnum, serde::Serialize, serde::Deserialize)]
+pub enum Page {
+    ShortMenu,
+    LongMenu,
+    Label,
+}
+
+/// A page which failed to be fetched or parsed. Short menus which failed are kept from the
+/// previous refresh, while long menus and labels are left out since they only add detail.
+#[derive(Debug, Clone, PartialEq, Eq, GraphQLObject, serde::Serialize, serde::Deserialize)]
+pub struct RefreshFailure {
+    pub page: Page,
+    /// Not set for labels, which are shared between locations
+    pub location_id: Option<String>,
+    pub date: Option<NaiveDate>,
+    /// Only set for long menus
+    pub meal_type: Option<MealType>,
+    /// Only set for labels
+    pub recipe_id: Option<String>,
+    pub error: String,
+}
+
+impl RefreshFailure {
+    pub fn short_menu(location_id: &str, date: NaiveDate, error: &impl Display) -> Self {
+        Self {
+            page: Page::ShortMenu,
+            location_id: Some(location_id.to_string()),
+            date: Some(date),
+            meal_type: None,
+            recipe_id: None,
+            error: error.to_string(),
+        }
+    }
+
+    pub fn long_menu(
+        location_id: &str,
+        date: NaiveDate,
+        meal_type: MealType,
+        error: &impl Display,
+    ) -> Self {
+        Self {
+            page: Page::LongMenu,
+            meal_type: Some(meal_type),
+            ..Self::short_menu(location_id, date, error)
+        }
+    }
+
+    pub fn label(recipe_id: &str, error: &impl Display) -> Self {
+        Self {
+            page: Page::Label,
+            location_id: None,
+            date: None,
+            meal_type: None,
+            recipe_id: Some(recipe_id.to_string()),
+            error: error.to_string(),
+        }
+    }
+}
+
+/// The pages which failed in the last refresh
+#[derive(Debug, Clone, PartialEq, Eq, GraphQLObject, serde::Serialize, serde::Deserialize)]
+pub struct RefreshReport {
+    pub refreshed_at: DateTime<Utc>,
+    pub failures: Vec<RefreshFailure>,
+}
@@ -0,0 +1,335 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use chrono::NaiveDate;
+use juniper::GraphQLObject;
+
+use crate::parse::{AllergenFilter, DateRange, FoodItem, Locations, MealType};
+
+/// A food item found by `searchFood`, with where and when it is served
+#[derive(Debug, Clone, GraphQLObject)]
+pub struct FoodSearchHit<'a> {
+    pub location_id: String,
+    pub location_name: String,
+    pub date: NaiveDate,
+    pub meal_type: MealType,
+    /// The name of the section of the meal the item is in, ex. "Entrees"
+    pub section: String,
+    pub food_item: FoodItem<'a>,
+}
+
+/// What the hits of a search are narrowed down to, besides matching the query
+#[derive(Debug, Default)]
+pub struct SearchFilter {
+    pub date_range: Option<DateRange>,
+    pub meal_types: Option<Vec<MealType>>,
+    pub location_ids: Option<Vec<String>>,
+    pub allergens: AllergenFilter,
+}
+
+// where an item is in the locations the index was built from
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    location: usize,
+    menu: usize,
+    meal: usize,
+    section: usize,
+    item: usize,
+}
+
+/// An inverted index from the words in the names of the food items to where the items are, so
+/// that searches don't walk every menu. It points into the locations it was built from, so it
+/// has to be rebuilt whenever they change.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    positions: Vec<Position>,
+    // each word maps to the indices of the positions whose name has it, in ascending order
+    words: HashMap<String, Vec<usize>>,
+}
+
+impl SearchIndex {
+    pub fn build(locations: &Locations) -> Self {
+        let mut index = Self::default();
+        for (l, location) in locations.iter().enumerate() {
+            for (m, menu) in location.daily_menus().enumerate() {
+                for (ml, meal) in menu.all_meals().iter().enumerate() {
+                    for (s, section) in meal.sections.iter().enumerate() {
+                        for (i, item) in section.food_items.iter().enumerate() {
+                            let id = index.positions.len();
+                            index.positions.push(Position {
+                                location: l,
+                                menu: m,
+                                meal: ml,
+                                section: s,
+                                item: i,
+                            });
+                            let mut words = tokenize(item.name());
+                            words.sort_unstable();
+                            words.dedup();
+                            for word in words {
+                                index.words.entry(word).or_default().push(id);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        index
+    }
+
+    /// The items whose name has a word matching each word of `query`, best matches first.
+    /// A word matches exactly, as the start of a longer word, or with a typo or two if it is
+    /// long enough. `locations` must be the ones the index was built from.
+    pub fn search<'a>(
+        &self,
+        locations: &Locations<'a>,
+        query: &str,
+        filter: &SearchFilter,
+        limit: usize,
+    ) -> Vec<FoodSearchHit<'a>> {
+        let query = tokenize(query);
+        if query.is_empty() {
+            return Vec::new();
+        }
+        // the summed score of the items which matched every word so far
+        let mut scores: Option<HashMap<usize, u32>> = None;
+        for word in &query {
+            let query_word: Vec<char> = word.chars().collect();
+            let mut matched: HashMap<usize, u32> = HashMap::new();
+            for (indexed, ids) in &self.words {
+                let Some(score) = match_score(&query_word, indexed) else {
+                    continue;
+                };
+                for id in ids {
+                    let best = matched.entry(*id).or_default();
+                    *best = (*best).max(score);
+                }
+            }
+            scores = Some(match scores {
+                None => matched,
+                Some(scores) => scores
+                    .into_iter()
+                    .filter_map(|(id, score)| Some((id, score + matched.get(&id)?)))
+                    .collect(),
+            });
+        }
+        let mut hits: Vec<_> = scores
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(id, score)| {
+                let hit = Self::resolve(locations, self.positions[id])?;
+                filter.includes(&hit).then_some((Reverse(score), id, hit))
+            })
+            .collect();
+        // positions are in the order of the locations and dates, so ties keep that order
+        hits.sort_unstable_by_key(|(score, id, _)| (*score, *id));
+        hits.into_iter()
+            .take(limit)
+            .map(|(_, _, hit)| hit)
+            .collect()
+    }
+
+    fn resolve<'a>(locations: &Locations<'a>, position: Position) -> Option<FoodSearchHit<'a>> {
+        let location = locations.iter().nth(position.location)?;
+        let menu = location.daily_menus().nth(position.menu)?;
+        let meal = menu.all_meals().get(position.meal)?;
+        let section = meal.sections.get(position.section)?;
+        Some(FoodSearchHit {
+            location_id: location.id().to_string(),
+            location_name: location.name().to_string(),
+            date: menu.date(),
+            meal_type: meal.meal_type,
+            section: section.name.to_string(),
+            food_item: section.food_items.get(position.item)?.clone(),
+        })
+    }
+}
+
+impl SearchFilter {
+    fn includes(&self, hit: &FoodSearchHit) -> bool {
+        self.date_range
+            .as_ref()
+            .is_none_or(|x| x.includes(hit.date))
+            && self
+                .meal_types
+                .as_ref()
+                .is_none_or(|x| x.contains(&hit.meal_type))
+            && self
+                .location_ids
+                .as_ref()
+                .is_none_or(|x| x.contains(&hit.location_id))
+            && self.allergens.matches(hit.food_item.get_allergen_mask())
+    }
+}
+
+/// Splits `text` into lowercase words, without accents so that "pho" finds "Phở"
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            x.chars()
+                .flat_map(char::to_lowercase)
+                .map(unaccent)
+                .collect()
+        })
+        .collect()
+}
+
+const fn unaccent(c: char) -> char {
+    match c {
+        'à'..='å' | 'ā' | 'ă' | 'ạ' | 'ả' | 'ấ' | 'ầ' | 'ậ' => 'a',
+        'ç' | 'č' => 'c',
+        'è'..='ë' | 'ē' | 'ě' | 'ẹ' | 'ế' | 'ề' | 'ể' | 'ệ' => 'e',
+        'ì'..='ï' | 'ī' => 'i',
+        'ñ' => 'n',
+        'ò'..='ö' | 'ø' | 'ō' | 'ơ' | 'ọ' | 'ố' | 'ồ' | 'ộ' | 'ớ' | 'ờ' | 'ở' | 'ợ' => {
+            'o'
+        }
+        'ù'..='ü' | 'ū' | 'ư' | 'ụ' | 'ứ' | 'ừ' | 'ữ' => 'u',
+        'ý' | 'ÿ' => 'y',
+        _ => c,
+    }
+}
+
+// short words would match too much with a typo, ex. "egg" and "eel"
+const fn max_typos(len: usize) -> usize {
+    match len {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// How well the indexed word matches the word of the query, or `None` if it doesn't
+fn match_score(query: &[char], indexed: &str) -> Option<u32> {
+    let indexed: Vec<char> = indexed.chars().collect();
+    if query == indexed.as_slice() {
+        return Some(3);
+    }
+    if query.len() >= 2 && indexed.starts_with(query) {
+        return Some(2);
+    }
+    let typos = max_typos(query.len());
+    (typos > 0
+        && query.len().abs_diff(indexed.len()) <= typos
+        && edit_distance(query, &indexed) <= typos)
+        .then_some(1)
+}
+
+/// The Damerau-Levenshtein distance between `a` and `b`, where swapping two neighbouring
+/// letters counts as one edit
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    // rows of the distances between prefixes of `a` and `b`, two back, one back and current
+    let mut before: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        before = std::mem::replace(&mut previous, current);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::{examples, scrape};
+    use crate::parse::Allergens;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("Phở Gà, (Chicken) Noodle-Soup"),
+            ["pho", "ga", "chicken", "noodle", "soup"]
+        );
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        let distance = |a: &str, b: &str| {
+            edit_distance(
+                &a.chars().collect::<Vec<_>>(),
+                &b.chars().collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(distance("muffin", "muffin"), 0);
+        assert_eq!(distance("mufin", "muffin"), 1);
+        assert_eq!(distance("mfufin", "muffin"), 1);
+        assert_eq!(distance("burrito", "burito"), 1);
+        assert_eq!(distance("", "egg"), 3);
+        assert_eq!(distance("pizza", "pasta"), 3);
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let dates = [
+            NaiveDate::from_ymd_opt(2024, 4, 5).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 4, 9).unwrap(),
+        ];
+        let locations = scrape(&examples(), &dates, None).await.unwrap();
+        let index = SearchIndex::build(&locations);
+        let search = |query: &str, filter: &SearchFilter| {
+            index
+                .search(&locations, query, filter, 100)
+                .into_iter()
+                .map(|x| (x.date, x.food_item.name().to_string()))
+                .collect::<Vec<_>>()
+        };
+        let all = SearchFilter::default();
+        let muffins = search("muffin", &all);
+        assert_eq!(
+            muffins,
+            [
+                (dates[0], "Carrot Raisin Muffin".to_string()),
+                (dates[0], "Chocolate Muffin".to_string()),
+                (dates[1], "Apple Cinnamon Muffin".to_string()),
+                (dates[1], "Lemon Poppyseed Muffin".to_string()),
+            ]
+        );
+        // typos, prefixes and case don't matter
+        assert_eq!(search("mufin", &all), muffins);
+        assert_eq!(search("MUFF", &all), muffins);
+        // every word has to match
+        assert_eq!(
+            search("chocolate muffins", &all),
+            [(dates[0], "Chocolate Muffin".to_string())]
+        );
+        assert!(search("muffin pizza", &all).is_empty());
+        assert!(search("", &all).is_empty());
+
+        let filter = SearchFilter {
+            date_range: Some(DateRange::new(Some(dates[1]), None)),
+            ..SearchFilter::default()
+        };
+        assert_eq!(search("muffin", &filter), muffins[2..]);
+        let filter = SearchFilter {
+            meal_types: Some(vec![MealType::Dinner]),
+            ..SearchFilter::default()
+        };
+        assert!(search("muffin", &filter).is_empty());
+        let filter = SearchFilter {
+            location_ids: Some(vec!["30".to_string()]),
+            ..SearchFilter::default()
+        };
+        assert!(search("muffin", &filter).is_empty());
+        let filter = SearchFilter {
+            allergens: AllergenFilter::new(None, Some(vec![Allergens::Soy]), None),
+            ..SearchFilter::default()
+        };
+        let hits = index.search(&locations, "phở", &filter, 100);
+        assert!(hits
+            .iter()
+            .any(|x| x.food_item.name() == "Vietnamese Pho Broth"));
+        assert!(hits.iter().all(|x| x.date == dates[0]
+            && x.section == "Hot Bars"
+            && !x.food_item.allergens().contains(&Allergens::Soy)));
+        assert_eq!(index.search(&locations, "muffin", &all, 1).len(), 1);
+    }
+}
@@ -0,0 +1,151 @@
+mod file_store;
+#[cfg(feature = "firestore")]
+mod firestore_store;
+mod memory_store;
+
+use std::future::Future;
+
+use chrono::{DateTime, Utc};
+
+use crate::config::{CacheConfig, StoreKind};
+use crate::error::Error;
+
+pub use file_store::FileStore;
+#[cfg(feature = "firestore")]
+pub use firestore_store::FirestoreStore;
+pub use memory_store::MemoryStore;
+
+/// The serialized form of a `MenuCache`, the gzipped json of its locations
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
+pub struct GCloudMenuCache {
+    pub cached_at: DateTime<Utc>,
+    pub data: Vec<u8>,
+}
+
+/// Who is refreshing the menus in the store, so that instances sharing it don't all refresh at once
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RefreshLease {
+    pub holder: String,
+    /// If the holder dies mid refresh, another instance takes over the lease after this
+    pub expires_at: DateTime<Utc>,
+}
+
+impl RefreshLease {
+    pub fn new(holder: &str, ttl: chrono::Duration) -> Self {
+        Self {
+            holder: holder.to_string(),
+            expires_at: Utc::now() + ttl,
+        }
+    }
+
+    /// Whether `holder` may take the lease over from this one
+    pub fn available_to(&self, holder: &str) -> bool {
+        self.holder == holder || self.expires_at <= Utc::now()
+    }
+
+    /// Whether this is the lease of `holder`, and others can't take it over yet
+    pub fn held_by(&self, holder: &str) -> bool {
+        self.holder == holder && self.expires_at > Utc::now()
+    }
+}
+
+/// Somewhere to keep the menu cache between refreshes and restarts.
+pub trait CacheStore: Send + Sync {
+    /// Returns `None` if nothing has been saved yet
+    fn load(&self) -> impl Future<Output = Result<Option<GCloudMenuCache>, Error>> + Send;
+    fn save(&self, cache: &GCloudMenuCache) -> impl Future<Output = Result<(), Error>> + Send;
+    /// Saves `cache` only if `holder` still has the lease, failing with `Error::LeaseLost`
+    /// otherwise, so that a refresh which outlived its lease can't overwrite the menus of the
+    /// instance which took it over
+    fn save_leased(
+        &self,
+        cache: &GCloudMenuCache,
+        holder: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+    /// Takes `lease` unless another holder has one which hasn't expired. Returns whether it was
+    /// taken.
+    fn try_lease(&self, lease: &RefreshLease) -> impl Future<Output = Result<bool, Error>> + Send;
+    /// Gives up the lease of `holder`, if it still has it
+    fn release_lease(&self, holder: &str) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+/// Any of the stores, picked at startup by `cache.store`:
+/// - `firestore` (default) uses the `caches/menu` document of `cache.firestore_project`, if the
+///   `firestore` feature is on
+/// - `file` uses the file at `cache.file`
+/// - `memory` keeps nothing between restarts
+#[derive(Debug)]
+pub enum AnyStore {
+    #[cfg(feature = "firestore")]
+    Firestore(FirestoreStore),
+    File(FileStore),
+    Memory(MemoryStore),
+}
+
+impl AnyStore {
+    // only opening firestore awaits
+    #[cfg_attr(not(feature = "firestore"), allow(clippy::unused_async))]
+    pub async fn from_config(config: &CacheConfig) -> Result<Self, Error> {
+        Ok(match config.store {
+            #[cfg(feature = "firestore")]
+            StoreKind::Firestore => {
+                Self::Firestore(FirestoreStore::new(&config.firestore_project).await?)
+            }
+            #[cfg(not(feature = "firestore"))]
+            StoreKind::Firestore => {
+                return Err(Error::Config(
+                    "the firestore store needs the `firestore` feature".to_string(),
+                ))
+            }
+            StoreKind::File => Self::File(FileStore::new(&config.file)),
+            StoreKind::Memory => Self::Memory(MemoryStore::default()),
+        })
+    }
+}
+
+impl CacheStore for AnyStore {
+    async fn load(&self) -> Result<Option<GCloudMenuCache>, Error> {
+        match self {
+            #[cfg(feature = "firestore")]
+            Self::Firestore(store) => store.load().await,
+            Self::File(store) => store.load().await,
+            Self::Memory(store) => store.load().await,
+        }
+    }
+
+    async fn save(&self, cache: &GCloudMenuCache) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "firestore")]
+            Self::Firestore(store) => store.save(cache).await,
+            Self::File(store) => store.save(cache).await,
+            Self::Memory(store) => store.save(cache).await,
+        }
+    }
+
+    async fn save_leased(&self, cache: &GCloudMenuCache, holder: &str) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "firestore")]
+            Self::Firestore(store) => store.save_leased(cache, holder).await,
+            Self::File(store) => store.save_leased(cache, holder).await,
+            Self::Memory(store) => store.save_leased(cache, holder).await,
+        }
+    }
+
+    async fn try_lease(&self, lease: &RefreshLease) -> Result<bool, Error> {
+        match self {
+            #[cfg(feature = "firestore")]
+            Self::Firestore(store) => store.try_lease(lease).await,
+            Self::File(store) => store.try_lease(lease).await,
+            Self::Memory(store) => store.try_lease(lease).await,
+        }
+    }
+
+    async fn release_lease(&self, holder: &str) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "firestore")]
+            Self::Firestore(store) => store.release_lease(holder).await,
+            Self::File(store) => store.release_lease(holder).await,
+            Self::Memory(store) => store.release_lease(holder).await,
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    fs::{File, TryLockError},
+    io::{self, Write},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use chrono::DateTime;
+
+use super::{CacheStore, GCloudMenuCache, RefreshLease};
+use crate::error::Error;
+
+/// Stores the cache in a file on the local filesystem.
+/// The file holds the time it was cached at as an RFC 3339 line, followed by the gzipped data.
+///
+/// The refresh lease is an OS lock on the file with `.lock` appended to the path. The OS releases
+/// it when the holder dies, so its expiry isn't needed.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    // the lock file is held open for as long as the lease is
+    lease: Mutex<Option<(String, File)>>,
+}
+
+impl FileStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lease: Mutex::default(),
+        }
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".lock");
+        path.into()
+    }
+}
+
+impl CacheStore for FileStore {
+    async fn load(&self) -> Result<Option<GCloudMenuCache>, Error> {
+        let contents = match tokio::fs::read(&self.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a menu cache file", self.path.display()),
+            )
+        };
+        let newline = contents
+            .iter()
+            .position(|&x| x == b'\n')
+            .ok_or_else(invalid)?;
+        let cached_at = std::str::from_utf8(&contents[..newline])
+            .ok()
+            .and_then(|x| DateTime::parse_from_rfc3339(x).ok())
+            .ok_or_else(invalid)?
+            .to_utc();
+        Ok(Some(GCloudMenuCache {
+            cached_at,
+            data: contents[newline + 1..].to_vec(),
+        }))
+    }
+
+    async fn save(&self, cache: &GCloudMenuCache) -> Result<(), Error> {
+        let mut contents = format!("{}\n", cache.cached_at.to_rfc3339()).into_bytes();
+        contents.extend_from_slice(&cache.data);
+        // write then rename so that a crash mid write doesn't leave a truncated cache behind
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        tokio::fs::write(&tmp, contents).await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+        Ok(())
+    }
+
+    async fn save_leased(&self, cache: &GCloudMenuCache, holder: &str) -> Result<(), Error> {
+        // the OS lock doesn't expire, so nobody else can take the lease while it is held here
+        let held = self
+            .lease
+            .lock()
+            .expect("lock should not be poisoned")
+            .as_ref()
+            .is_some_and(|(x, _)| x == holder);
+        if !held {
+            return Err(Error::LeaseLost);
+        }
+        self.save(cache).await
+    }
+
+    async fn try_lease(&self, lease: &RefreshLease) -> Result<bool, Error> {
+        let mut held = self.lease.lock().expect("lock should not be poisoned");
+        if let Some((holder, _)) = held.as_ref() {
+            return Ok(*holder == lease.holder);
+        }
+        let mut file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.lock_path())?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(false),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        // only for whoever looks at the file, the lock is what matters
+        file.set_len(0)?;
+        file.write_all(&serde_json::to_vec(lease)?)?;
+        *held = Some((lease.holder.clone(), file));
+        drop(held);
+        Ok(true)
+    }
+
+    async fn release_lease(&self, holder: &str) -> Result<(), Error> {
+        let mut held = self.lease.lock().expect("lock should not be poisoned");
+        if held.as_ref().is_some_and(|(x, _)| x == holder) {
+            // closing the file unlocks it
+            *held = None;
+        }
+        drop(held);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("ucsc_menu_test_{}.gz", std::process::id()));
+        let store = FileStore::new(&path);
+        assert_eq!(store.load().await.unwrap(), None);
+        let cache = GCloudMenuCache {
+            cached_at: DateTime::from_timestamp(1_714_000_000, 0).unwrap(),
+            data: vec![0x1f, 0x8b, b'\n', 0, 255],
+        };
+        store.save(&cache).await.unwrap();
+        assert_eq!(store.load().await.unwrap(), Some(cache));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lease() {
+        let path =
+            std::env::temp_dir().join(format!("ucsc_menu_lease_test_{}.gz", std::process::id()));
+        // two stores on the same file stand in for two instances
+        let a = FileStore::new(&path);
+        let b = FileStore::new(&path);
+        let ttl = chrono::Duration::minutes(10);
+        assert!(a.try_lease(&RefreshLease::new("a", ttl)).await.unwrap());
+        assert!(a.try_lease(&RefreshLease::new("a", ttl)).await.unwrap());
+        assert!(!b.try_lease(&RefreshLease::new("b", ttl)).await.unwrap());
+        a.release_lease("a").await.unwrap();
+        assert!(b.try_lease(&RefreshLease::new("b", ttl)).await.unwrap());
+        b.release_lease("b").await.unwrap();
+        std::fs::remove_file(a.lock_path()).unwrap();
+    }
+}
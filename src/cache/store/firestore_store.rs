@@ -0,0 +1,178 @@
+use firestore::{
+    errors::FirestoreError, timestamp_utils::from_timestamp, FirestoreDb,
+    FirestoreWritePrecondition,
+};
+
+use chrono::{DateTime, Utc};
+
+use super::{CacheStore, GCloudMenuCache, RefreshLease};
+use crate::error::Error;
+
+const CACHES_COLLECTION: &str = "caches";
+const MENU_DOCUMENT: &str = "menu";
+const LEASE_DOCUMENT: &str = "refresh_lease";
+
+/// Stores the cache in the `caches/menu` document of a Firestore database, and the refresh lease
+/// in `caches/refresh_lease`
+#[derive(Debug)]
+pub struct FirestoreStore {
+    db: FirestoreDb,
+}
+
+impl FirestoreStore {
+    pub async fn new(project_id: &str) -> Result<Self, Error> {
+        let db = FirestoreDb::new(project_id).await?;
+        Ok(Self { db })
+    }
+}
+
+impl CacheStore for FirestoreStore {
+    async fn load(&self) -> Result<Option<GCloudMenuCache>, Error> {
+        let cache = self
+            .db
+            .fluent()
+            .select()
+            .by_id_in(CACHES_COLLECTION)
+            .obj()
+            .one(MENU_DOCUMENT)
+            .await?;
+        Ok(cache)
+    }
+
+    async fn save(&self, cache: &GCloudMenuCache) -> Result<(), Error> {
+        self.db
+            .fluent()
+            .update()
+            .in_col(CACHES_COLLECTION)
+            .document_id(MENU_DOCUMENT)
+            .object(cache)
+            .execute::<()>()
+            .await?;
+        Ok(())
+    }
+
+    async fn save_leased(&self, cache: &GCloudMenuCache, holder: &str) -> Result<(), Error> {
+        let Some((lease, update_time)) = self.read_lease().await? else {
+            return Err(Error::LeaseLost);
+        };
+        if !lease.held_by(holder) {
+            return Err(Error::LeaseLost);
+        }
+        // the lease is written back unchanged alongside the menus, and the commit fails as a whole
+        // if another instance wrote the lease since it was read
+        let mut transaction = self.db.begin_transaction().await?;
+        self.db
+            .fluent()
+            .update()
+            .in_col(CACHES_COLLECTION)
+            .document_id(MENU_DOCUMENT)
+            .object(cache)
+            .add_to_transaction(&mut transaction)?;
+        self.db
+            .fluent()
+            .update()
+            .in_col(CACHES_COLLECTION)
+            .precondition(FirestoreWritePrecondition::UpdateTime(update_time))
+            .document_id(LEASE_DOCUMENT)
+            .object(&lease)
+            .add_to_transaction(&mut transaction)?;
+        match transaction.commit().await {
+            Ok(_) => Ok(()),
+            Err(e) if lost_race(&e) => Err(Error::LeaseLost),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn try_lease(&self, lease: &RefreshLease) -> Result<bool, Error> {
+        let current = self
+            .db
+            .fluent()
+            .select()
+            .by_id_in(CACHES_COLLECTION)
+            .one(LEASE_DOCUMENT)
+            .await?;
+        // the write only goes through if nobody else wrote the lease since it was read
+        let precondition = match current {
+            None => FirestoreWritePrecondition::Exists(false),
+            Some(document) => {
+                let current: RefreshLease = FirestoreDb::deserialize_doc_to(&document)?;
+                let Some(update_time) = document.update_time else {
+                    return Ok(false);
+                };
+                if !current.available_to(&lease.holder) {
+                    return Ok(false);
+                }
+                FirestoreWritePrecondition::UpdateTime(from_timestamp(update_time)?)
+            }
+        };
+        let written = self
+            .db
+            .fluent()
+            .update()
+            .in_col(CACHES_COLLECTION)
+            .precondition(precondition)
+            .document_id(LEASE_DOCUMENT)
+            .object(lease)
+            .execute::<()>()
+            .await;
+        match written {
+            Ok(()) => Ok(true),
+            Err(e) if lost_race(&e) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn release_lease(&self, holder: &str) -> Result<(), Error> {
+        let Some((lease, update_time)) = self.read_lease().await? else {
+            return Ok(());
+        };
+        if lease.holder != holder {
+            return Ok(());
+        }
+        // if the lease expired and was taken over since it was read, the new holder keeps it
+        let deleted = self
+            .db
+            .fluent()
+            .delete()
+            .from(CACHES_COLLECTION)
+            .document_id(LEASE_DOCUMENT)
+            .precondition(FirestoreWritePrecondition::UpdateTime(update_time))
+            .execute()
+            .await;
+        match deleted {
+            Ok(()) => Ok(()),
+            Err(e) if lost_race(&e) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl FirestoreStore {
+    /// The current lease and when it was last written, for the precondition of the next write
+    async fn read_lease(&self) -> Result<Option<(RefreshLease, DateTime<Utc>)>, Error> {
+        let Some(document) = self
+            .db
+            .fluent()
+            .select()
+            .by_id_in(CACHES_COLLECTION)
+            .one(LEASE_DOCUMENT)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let lease: RefreshLease = FirestoreDb::deserialize_doc_to(&document)?;
+        let Some(update_time) = document.update_time else {
+            return Ok(None);
+        };
+        Ok(Some((lease, from_timestamp(update_time)?)))
+    }
+}
+
+/// Whether a write with a precondition failed because another instance wrote first
+fn lost_race(e: &FirestoreError) -> bool {
+    match e {
+        FirestoreError::DataConflictError(_) => true,
+        FirestoreError::DatabaseError(e) => e.public.code == "FailedPrecondition",
+        _ => false,
+    }
+}
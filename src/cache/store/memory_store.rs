@@ -0,0 +1,110 @@
+use std::sync::Mutex;
+
+use super::{CacheStore, GCloudMenuCache, RefreshLease};
+use crate::error::Error;
+
+/// Keeps the cache in memory, so it is lost on restart. Useful for tests and local runs.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    cache: Mutex<Option<GCloudMenuCache>>,
+    lease: Mutex<Option<RefreshLease>>,
+}
+
+impl CacheStore for MemoryStore {
+    async fn load(&self) -> Result<Option<GCloudMenuCache>, Error> {
+        Ok(self
+            .cache
+            .lock()
+            .expect("lock should not be poisoned")
+            .clone())
+    }
+
+    async fn save(&self, cache: &GCloudMenuCache) -> Result<(), Error> {
+        *self.cache.lock().expect("lock should not be poisoned") = Some(cache.clone());
+        Ok(())
+    }
+
+    async fn save_leased(&self, cache: &GCloudMenuCache, holder: &str) -> Result<(), Error> {
+        // the lease stays locked while saving, so it can't be taken over in between
+        let lease = self.lease.lock().expect("lock should not be poisoned");
+        if !lease.as_ref().is_some_and(|x| x.held_by(holder)) {
+            return Err(Error::LeaseLost);
+        }
+        *self.cache.lock().expect("lock should not be poisoned") = Some(cache.clone());
+        drop(lease);
+        Ok(())
+    }
+
+    async fn try_lease(&self, lease: &RefreshLease) -> Result<bool, Error> {
+        let mut current = self.lease.lock().expect("lock should not be poisoned");
+        let taken = current
+            .as_ref()
+            .is_none_or(|current| current.available_to(&lease.holder));
+        if taken {
+            *current = Some(lease.clone());
+        }
+        drop(current);
+        Ok(taken)
+    }
+
+    async fn release_lease(&self, holder: &str) -> Result<(), Error> {
+        let mut current = self.lease.lock().expect("lock should not be poisoned");
+        if current.as_ref().is_some_and(|x| x.holder == holder) {
+            *current = None;
+        }
+        drop(current);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lease() {
+        let store = MemoryStore::default();
+        let ttl = chrono::Duration::minutes(10);
+        assert!(store.try_lease(&RefreshLease::new("a", ttl)).await.unwrap());
+        assert!(!store.try_lease(&RefreshLease::new("b", ttl)).await.unwrap());
+        // the holder can renew its own lease
+        assert!(store.try_lease(&RefreshLease::new("a", ttl)).await.unwrap());
+        // only the holder can release it
+        store.release_lease("b").await.unwrap();
+        assert!(!store.try_lease(&RefreshLease::new("b", ttl)).await.unwrap());
+        store.release_lease("a").await.unwrap();
+        assert!(store.try_lease(&RefreshLease::new("b", ttl)).await.unwrap());
+
+        // an expired lease is taken over
+        let expired = RefreshLease::new("c", chrono::Duration::minutes(-1));
+        *store.lease.lock().unwrap() = Some(expired);
+        assert!(store.try_lease(&RefreshLease::new("a", ttl)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_save_leased() {
+        let store = MemoryStore::default();
+        let cache = GCloudMenuCache::default();
+        assert!(matches!(
+            store.save_leased(&cache, "a").await,
+            Err(Error::LeaseLost)
+        ));
+        let ttl = chrono::Duration::minutes(10);
+        assert!(store.try_lease(&RefreshLease::new("a", ttl)).await.unwrap());
+        store.save_leased(&cache, "a").await.unwrap();
+        assert_eq!(store.load().await.unwrap(), Some(cache.clone()));
+
+        // "a" outlived its lease and "b" took it over, so "a" can't save anymore
+        *store.lease.lock().unwrap() = Some(RefreshLease::new("a", chrono::Duration::minutes(-1)));
+        assert!(store.try_lease(&RefreshLease::new("b", ttl)).await.unwrap());
+        let newer = GCloudMenuCache {
+            cached_at: chrono::Utc::now(),
+            data: vec![1],
+        };
+        assert!(matches!(
+            store.save_leased(&newer, "a").await,
+            Err(Error::LeaseLost)
+        ));
+        assert_eq!(store.load().await.unwrap(), Some(cache));
+    }
+}
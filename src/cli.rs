@@ -0,0 +1,438 @@
+//! Subcommands which scrape and query the menus without running the server, for scripts and
+//! cron jobs. Tables are printed by default and JSON with `--json`, while logs go to stderr.
+
+use std::{fmt::Write, path::PathBuf};
+
+use chrono::NaiveDate;
+use tokio::net::TcpListener;
+
+use crate::{
+    cache::{AnyStore, MenuCache},
+    config::Config,
+    error::Error,
+    fetch::{self, date_iter, scrape, Fetcher},
+    parse::{DailyMenu, Locations, MealType},
+};
+
+pub const USAGE: &str = "\
+Usage:
+  ucsc_menu                          run the server
+  ucsc_menu scrape  [OPTIONS]        fetch the menus and list what was found at each location
+  ucsc_menu show    [OPTIONS]        print the menu of one location, --location is required
+  ucsc_menu search  QUERY [OPTIONS]  find the food items whose name contains QUERY
+  ucsc_menu export  [OPTIONS]        print every menu as JSON
+  ucsc_menu mock-upstream [DIR]      serve recorded pages in place of the dining site
+
+Options:
+  --location ID    only this location, ex. 40
+  --date DATE      only this day, ex. 2024-04-05 (show defaults to today)
+  --meal MEAL      only this meal, ex. dinner or late-night
+  --days N         how many days to fetch without --date (default cache.days)
+  --cached         read the menus from the configured cache instead of fetching them
+  --json           print JSON instead of a table
+";
+
+const MEAL_TYPES: [MealType; 7] = [
+    MealType::Breakfast,
+    MealType::Lunch,
+    MealType::Dinner,
+    MealType::LateNight,
+    MealType::BananaJoes,
+    MealType::Menu,
+    MealType::AllDay,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Scrape,
+    Show,
+    Search(String),
+    Export,
+    MockUpstream(PathBuf),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    pub location: Option<String>,
+    pub date: Option<NaiveDate>,
+    pub meal: Option<MealType>,
+    pub days: Option<u32>,
+    pub cached: bool,
+    pub json: bool,
+}
+
+impl Command {
+    /// The command in `args`, which don't include the program name. `None` runs the server.
+    pub fn from_args(
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<Option<(Self, Options)>, Error> {
+        let mut args = args.into_iter();
+        let Some(name) = args.next() else {
+            return Ok(None);
+        };
+        let mut options = Options::default();
+        let mut positional = Vec::new();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| Error::InvalidInput(format!("{arg} needs a value")))
+            };
+            match arg.as_str() {
+                "--location" => options.location = Some(value()?),
+                "--date" => options.date = Some(parse_arg("--date", &value()?)?),
+                "--meal" => options.meal = Some(parse_meal(&value()?)?),
+                "--days" => options.days = Some(parse_arg("--days", &value()?)?),
+                "--cached" => options.cached = true,
+                "--json" => options.json = true,
+                _ if arg.starts_with("--") => {
+                    return Err(Error::InvalidInput(format!("Unknown option {arg}")))
+                }
+                _ => positional.push(arg),
+            }
+        }
+        let command = match (name.as_str(), positional.as_slice()) {
+            ("scrape", []) => Self::Scrape,
+            ("show", []) if options.location.is_some() => Self::Show,
+            ("show", []) => {
+                return Err(Error::InvalidInput("show needs --location".to_string()));
+            }
+            ("search", [query]) => Self::Search(query.clone()),
+            ("export", []) => Self::Export,
+            ("mock-upstream", []) => Self::MockUpstream("fixtures".into()),
+            ("mock-upstream", [dir]) => Self::MockUpstream(dir.into()),
+            ("scrape" | "show" | "search" | "export" | "mock-upstream", _) => {
+                return Err(Error::InvalidInput(format!(
+                    "Unexpected arguments to {name}: {positional:?}"
+                )));
+            }
+            _ => return Err(Error::InvalidInput(format!("Unknown command {name:?}"))),
+        };
+        command.check(&options)?;
+        Ok(Some((command, options)))
+    }
+
+    // rejects the options which would otherwise be ignored
+    fn check(&self, options: &Options) -> Result<(), Error> {
+        let unsupported = if matches!(self, Self::MockUpstream(_)) {
+            (options != &Options::default()).then_some("mock-upstream doesn't take options")
+        } else if options.days.is_none() {
+            None
+        } else if matches!(self, Self::Show) {
+            Some("show only fetches --date, so it doesn't take --days")
+        } else if options.cached {
+            Some("--days only applies to fetched menus, not --cached ones")
+        } else if options.date.is_some() {
+            Some("--date fetches only that day, so it can't be combined with --days")
+        } else {
+            None
+        };
+        unsupported.map_or(Ok(()), |x| Err(Error::InvalidInput(x.to_string())))
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, Error>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| Error::InvalidInput(format!("{name} {value:?} is invalid: {e}")))
+}
+
+/// Matches the meal names on the menu pages, ignoring case, spaces, dashes and underscores
+fn parse_meal(name: &str) -> Result<MealType, Error> {
+    let normalize = |x: &str| {
+        x.chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_ascii_lowercase()
+    };
+    let name = normalize(name);
+    MEAL_TYPES
+        .into_iter()
+        .find(|x| x.page_name().is_some_and(|page| normalize(page) == name))
+        .or_else(|| (name == "bananajoes").then_some(MealType::BananaJoes))
+        .ok_or_else(|| Error::InvalidInput(format!("Unknown meal {name:?}")))
+}
+
+/// Runs `command`, printing its output to stdout
+pub async fn run(command: Command, options: Options, config: &Config) -> Result<(), Error> {
+    if let Command::MockUpstream(dir) = command {
+        let addr = config.addr();
+        let listener = TcpListener::bind(addr).await?;
+        tracing::info!("serving {} as the upstream on http://{addr}", dir.display());
+        return Ok(fetch::mock_upstream::serve(dir, listener).await?);
+    }
+    let fetcher = Fetcher::from_config(&config.fetch)?;
+    // the day `show` shows, today whether or not the menus come from the cache
+    let date = options.date.unwrap_or_else(|| fetcher.today());
+    let mut locations = if options.cached {
+        let store = AnyStore::from_config(&config.cache).await?;
+        let mut locations = MenuCache::open(&store).await?.locations().clone();
+        drop(store);
+        if let Some(id) = &options.location {
+            locations.retain(|x| x.id() == id);
+        }
+        locations
+    } else {
+        let dates: Vec<_> = if matches!(command, Command::Show) || options.date.is_some() {
+            vec![date]
+        } else {
+            let days = options.days.unwrap_or(config.cache.days);
+            date_iter(config.cache.start_date(fetcher.today()), days.into()).collect()
+        };
+        scrape(&fetcher, &dates, options.location.as_deref()).await?
+    };
+    if matches!(command, Command::Scrape | Command::Export) {
+        locations.retain_menus(options.date, options.meal);
+    }
+    let output = match command {
+        Command::Scrape => summary(&locations, options.json)?,
+        Command::Show => show(&locations, date, &options)?,
+        Command::Search(query) => search(&locations, &query, &options)?,
+        Command::Export => serde_json::to_string_pretty(&locations)? + "\n",
+        Command::MockUpstream(_) => unreachable!("handled above"),
+    };
+    print!("{output}");
+    Ok(())
+}
+
+/// Lines up `rows` under `header`, ex. for `scrape` and `search`
+fn table<const N: usize>(header: [&str; N], rows: &[[String; N]]) -> String {
+    let mut widths = header.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    let header = header.map(str::to_string);
+    for row in std::iter::once(&header).chain(rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        let _ = writeln!(out, "{}", line.trim_end());
+    }
+    out
+}
+
+fn summary(locations: &Locations, json: bool) -> Result<String, Error> {
+    #[derive(serde::Serialize)]
+    struct Summary<'a> {
+        id: &'a str,
+        name: &'a str,
+        dates: Vec<NaiveDate>,
+        food_items: usize,
+    }
+    let summaries: Vec<_> = locations
+        .iter()
+        .map(|location| Summary {
+            id: location.id(),
+            name: location.name(),
+            dates: location.daily_menus().map(DailyMenu::date).collect(),
+            food_items: location
+                .daily_menus()
+                .flat_map(DailyMenu::food_items)
+                .count(),
+        })
+        .collect();
+    if json {
+        return Ok(serde_json::to_string_pretty(&summaries)? + "\n");
+    }
+    let rows: Vec<_> = summaries
+        .iter()
+        .map(|x| {
+            [
+                x.id.to_string(),
+                x.name.to_string(),
+                x.dates.len().to_string(),
+                x.food_items.to_string(),
+            ]
+        })
+        .collect();
+    Ok(table(["ID", "LOCATION", "DAYS", "ITEMS"], &rows))
+}
+
+fn show(locations: &Locations, date: NaiveDate, options: &Options) -> Result<String, Error> {
+    let id = options.location.as_deref().unwrap_or_default();
+    let location = locations
+        .iter()
+        .find(|x| x.id() == id)
+        .ok_or_else(|| Error::InvalidInput(format!("Unknown location {id:?}")))?;
+    let menu = location.daily_menus().find(|x| x.date() == date);
+    let meals: Vec<_> = menu
+        .map(|menu| menu.meals(options.meal, None))
+        .unwrap_or_default();
+    if options.json {
+        return Ok(serde_json::to_string_pretty(&meals)? + "\n");
+    }
+    let Some(menu) = menu else {
+        return Ok(format!("{} has no menu on that day\n", location.name()));
+    };
+    let mut out = format!("{} on {}\n", location.name(), menu.date());
+    for meal in &meals {
+        let _ = writeln!(out, "\n{:?}", meal.meal_type);
+        for section in &meal.sections {
+            let _ = writeln!(out, "  {}", section.name);
+            for item in &section.food_items {
+                let allergens = item.get_allergen_mask().to_string();
+                if allergens.is_empty() {
+                    let _ = writeln!(out, "    {}", item.name());
+                } else {
+                    let _ = writeln!(out, "    {} ({allergens})", item.name());
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn search(locations: &Locations, query: &str, options: &Options) -> Result<String, Error> {
+    #[derive(serde::Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Hit<'a> {
+        location_id: &'a str,
+        location: &'a str,
+        date: NaiveDate,
+        meal_type: MealType,
+        section: String,
+        name: String,
+    }
+    let query = query.to_lowercase();
+    let mut hits = Vec::new();
+    for location in locations {
+        let menus = location
+            .daily_menus()
+            .filter(|x| options.date.is_none_or(|date| x.date() == date));
+        for menu in menus {
+            for meal in menu.meals(options.meal, None) {
+                for section in &meal.sections {
+                    let matching = section
+                        .food_items
+                        .iter()
+                        .filter(|item| item.name().to_lowercase().contains(&query));
+                    hits.extend(matching.map(|item| Hit {
+                        location_id: location.id(),
+                        location: location.name(),
+                        date: menu.date(),
+                        meal_type: meal.meal_type,
+                        section: section.name.to_string(),
+                        name: item.name().to_string(),
+                    }));
+                }
+            }
+        }
+    }
+    if options.json {
+        return Ok(serde_json::to_string_pretty(&hits)? + "\n");
+    }
+    let rows: Vec<_> = hits
+        .iter()
+        .map(|x| {
+            [
+                x.date.to_string(),
+                x.location.to_string(),
+                format!("{:?}", x.meal_type),
+                x.section.clone(),
+                x.name.clone(),
+            ]
+        })
+        .collect();
+    Ok(table(
+        ["DATE", "LOCATION", "MEAL", "SECTION", "ITEM"],
+        &rows,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fetch::examples, parse::Location};
+
+    fn args(args: &str) -> Result<Option<(Command, Options)>, Error> {
+        Command::from_args(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn test_from_args() {
+        assert_eq!(args("").unwrap(), None);
+        let (command, options) = args("show --location 40 --date 2024-04-05 --meal late-night")
+            .unwrap()
+            .unwrap();
+        assert_eq!(command, Command::Show);
+        assert_eq!(
+            options,
+            Options {
+                location: Some("40".to_string()),
+                date: NaiveDate::from_ymd_opt(2024, 4, 5),
+                meal: Some(MealType::LateNight),
+                ..Options::default()
+            }
+        );
+        let (command, options) = args("search muffin --json --cached").unwrap().unwrap();
+        assert_eq!(command, Command::Search("muffin".to_string()));
+        assert!(options.json && options.cached);
+
+        assert!(args("show").is_err());
+        assert!(args("search").is_err());
+        assert!(args("scrape --days").is_err());
+        assert!(args("show --location 40 --meal brunch").is_err());
+        assert!(args("serve").is_err());
+
+        // options which would be ignored
+        assert!(args("scrape --days 3").is_ok());
+        assert!(args("show --location 40 --days 3").is_err());
+        assert!(args("export --cached --days 3").is_err());
+        assert!(args("scrape --date 2024-04-05 --days 3").is_err());
+        assert!(args("mock-upstream fixtures --json").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_scrape_narrowed() {
+        let date = NaiveDate::from_ymd_opt(2024, 4, 5).unwrap();
+        let mut locations = scrape(&examples(), &[date], None).await.unwrap();
+        let items = |locations: &Locations| {
+            let summaries: serde_json::Value =
+                serde_json::from_str(&summary(locations, true).unwrap()).unwrap();
+            summaries
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|x| x["food_items"].as_u64().unwrap())
+                .sum::<u64>()
+        };
+        let all = items(&locations);
+        locations.retain_menus(Some(date), Some(MealType::Dinner));
+        assert!((1..all).contains(&items(&locations)));
+        assert!(locations
+            .iter()
+            .flat_map(Location::daily_menus)
+            .flat_map(DailyMenu::all_meals)
+            .all(|x| x.meal_type == MealType::Dinner));
+        locations.retain_menus(date.succ_opt(), None);
+        assert_eq!(items(&locations), 0);
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let date = NaiveDate::from_ymd_opt(2024, 4, 5).unwrap();
+        let locations = scrape(&examples(), &[date], None).await.unwrap();
+        let options = Options {
+            meal: Some(MealType::Breakfast),
+            ..Options::default()
+        };
+        let found = search(&locations, "EGGS", &options).unwrap();
+        assert!(found.starts_with("DATE"));
+        assert!(found.contains("Breakfast"), "{found}");
+        let options = Options {
+            json: true,
+            ..Options::default()
+        };
+        let hits: serde_json::Value =
+            serde_json::from_str(&search(&locations, "no such dish", &options).unwrap()).unwrap();
+        assert_eq!(hits, serde_json::json!([]));
+    }
+}
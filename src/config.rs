@@ -0,0 +1,596 @@
+use std::{env, path::PathBuf, str::FromStr};
+
+use chrono::NaiveDate;
+use url::Url;
+
+use crate::{error::Error, hours::HOURS, places::PLACES, scheduler::Schedule};
+
+/// Where the config is read from when `CONFIG_FILE` isn't set. It's fine for it not to exist.
+const DEFAULT_CONFIG_FILE: &str = "ucsc_menu.toml";
+
+const MIN_SECRET_LEN: usize = 16;
+
+/// The settings of the server, read from a TOML file and then overridden by environment variables.
+/// Every setting has a default, so the file only needs the ones which differ.
+#[derive(Debug, Clone, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub cache: CacheConfig,
+    pub fetch: FetchConfig,
+    pub scheduler: SchedulerConfig,
+    pub archive: ArchiveConfig,
+    /// Service hours in the format of the bundled `hours.toml`, which replace the bundled hours
+    /// of the locations in it and add to the holidays
+    pub hours: OverrideConfig,
+    /// Location kinds, colleges and coordinates in the format of the bundled `places.toml`,
+    /// which replace the bundled ones of the locations in it
+    pub places: OverrideConfig,
+    pub admin: AdminConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 3000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    Firestore,
+    File,
+    Memory,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub store: StoreKind,
+    /// The file of the `file` store
+    pub file: PathBuf,
+    /// The Google Cloud project of the `firestore` store
+    pub firestore_project: String,
+    pub refresh_interval_mins: u32,
+    /// How many days of menus are fetched and kept
+    pub days: u32,
+    /// How many days before today the fetched menus start, so that today's menu is fetched
+    /// even when the server's timezone is ahead of the dining halls'
+    pub start_offset_days: u32,
+    /// How long the refresh lease lasts before others may take it over. The instance refreshing
+    /// renews it every third of this, so it only runs out if that instance stops.
+    pub lease_ttl_secs: u32,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            store: StoreKind::Firestore,
+            file: PathBuf::from("menu_cache.gz"),
+            firestore_project: "ucsc-menu".to_string(),
+            refresh_interval_mins: 15,
+            days: 10,
+            start_offset_days: 1,
+            lease_ttl_secs: 600,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn refresh_interval(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.refresh_interval_mins.into())
+    }
+
+    pub fn lease_ttl(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.lease_ttl_secs.into())
+    }
+
+    /// The first date of the menus which are fetched when it is `today`
+    pub fn start_date(&self, today: NaiveDate) -> NaiveDate {
+        today - chrono::Duration::days(self.start_offset_days.into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FetchMode {
+    Live,
+    /// Fetches pages like `live`, and also saves them to the fixture directory
+    Record,
+    /// Serves the pages saved by `record` instead of fetching them
+    Replay,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FetchConfig {
+    pub mode: FetchMode,
+    pub fixtures: PathBuf,
+    /// The root of the dining site
+    pub upstream_url: String,
+    /// Overrides the current date, ex. to match the fixtures of a mock upstream
+    pub today: Option<NaiveDate>,
+    /// Requests per second to the dining site
+    pub rate_limit: u32,
+    /// The most a request is randomly delayed by, to spread requests out
+    pub delay_jitter_secs: u64,
+    pub retries: u32,
+    /// The wait before the first retry, which doubles after every retry
+    pub backoff_ms: u64,
+    pub timeout_secs: u64,
+    /// How many requests in a row may fail before requests are paused
+    pub breaker_threshold: u32,
+    pub breaker_cooldown_secs: u64,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            mode: FetchMode::Live,
+            fixtures: PathBuf::from("fixtures"),
+            upstream_url: crate::fetch::DEFAULT_UPSTREAM.to_string(),
+            today: None,
+            rate_limit: 20,
+            delay_jitter_secs: 2,
+            retries: 3,
+            backoff_ms: 500,
+            timeout_secs: 30,
+            breaker_threshold: 5,
+            breaker_cooldown_secs: 60,
+        }
+    }
+}
+
+/// When the menus are refreshed in the background, on top of every `cache.refresh_interval_mins`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    /// The most each scheduled refresh is randomly delayed by
+    pub jitter_secs: u64,
+    /// Extra times to refresh at, ex. `"*/5 7-9 * * *"` for every 5 minutes through breakfast
+    pub cron: Vec<String>,
+    /// The timezone of the cron expressions
+    pub timezone: String,
+    /// Scheduled refreshes are skipped if the cache was refreshed more recently than this,
+    /// ex. by another instance
+    pub min_age_secs: u64,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            jitter_secs: 30,
+            cron: Vec::new(),
+            timezone: "America/Los_Angeles".to_string(),
+            min_age_secs: 60,
+        }
+    }
+}
+
+impl SchedulerConfig {
+    pub fn min_age(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.min_age_secs.try_into().unwrap_or(i64::MAX))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveConfig {
+    /// The `SQLite` database of past menus, which is disabled if this isn't set
+    pub path: Option<PathBuf>,
+}
+
+/// Data bundled with the server which a file can add to, see `Bundled`
+#[derive(Debug, Clone, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OverrideConfig {
+    /// A file in the format of the bundled one, nothing is added if this isn't set
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines, for running locally
+    #[default]
+    Pretty,
+    /// One JSON object per line with the fields of the event and its spans, for log collectors
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// Which events are logged, in the syntax of `RUST_LOG`, ex. `info,ucsc_menu=debug`
+    pub filter: String,
+    /// Where spans are exported over OTLP/HTTP, ex. `http://localhost:4318` for a local collector.
+    /// `/v1/traces` is added to it like to `OTEL_EXPORTER_OTLP_ENDPOINT`, unless it already ends
+    /// with that. Needs the `otlp` feature, and nothing is exported if this isn't set.
+    pub otlp_endpoint: Option<String>,
+    /// The `service.name` of the exported spans
+    pub service_name: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::default(),
+            filter: "info".to_string(),
+            otlp_endpoint: None,
+            service_name: "ucsc_menu".to_string(),
+        }
+    }
+}
+
+/// The credentials of the admin API, which is disabled unless at least one of them is set
+#[derive(Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Sent as `Authorization: Bearer <token>`
+    pub token: Option<String>,
+    /// Signs requests with HMAC-SHA256 instead of sending a token
+    pub hmac_secret: Option<String>,
+    /// How old the timestamp of a signed request may be, so that it can't be replayed later.
+    /// Within it a request is turned away if its nonce was already used, but the nonces are only
+    /// remembered by the instance which received them, so behind a load balancer the same
+    /// request can still be sent once to each instance until its timestamp is too old.
+    pub max_skew_secs: u64,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            token: None,
+            hmac_secret: None,
+            max_skew_secs: 60,
+        }
+    }
+}
+
+// the secrets are left out so they don't end up in logs
+impl std::fmt::Debug for AdminConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdminConfig")
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .field(
+                "hmac_secret",
+                &self.hmac_secret.as_ref().map(|_| "<redacted>"),
+            )
+            .field("max_skew_secs", &self.max_skew_secs)
+            .finish()
+    }
+}
+
+impl Config {
+    /// Reads the file at `CONFIG_FILE`, or `ucsc_menu.toml` if it exists, then applies the
+    /// environment variable overrides and validates the result.
+    pub fn load() -> Result<Self, Error> {
+        let path = env::var("CONFIG_FILE").ok();
+        let config = match &path {
+            Some(path) => Self::from_file(path)?,
+            None if std::path::Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(DEFAULT_CONFIG_FILE)?
+            }
+            None => Self::default(),
+        };
+        let config = config.with_overrides(|name| env::var(name).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &str) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("failed to read {path}: {e}")))?;
+        toml::from_str(&contents).map_err(|e| Error::Config(format!("{path} is invalid: {e}")))
+    }
+
+    /// Overrides the settings with the environment variables which `var` finds
+    #[allow(clippy::too_many_lines)] // one override per setting
+    fn with_overrides(mut self, var: impl Fn(&str) -> Option<String>) -> Result<Self, Error> {
+        let var = &var;
+        override_with(var, "HOST", &mut self.server.host)?;
+        override_with(var, "PORT", &mut self.server.port)?;
+
+        if let Some(store) = var("CACHE_STORE") {
+            self.cache.store = match store.as_str() {
+                "firestore" => StoreKind::Firestore,
+                "file" => StoreKind::File,
+                "memory" => StoreKind::Memory,
+                _ => {
+                    return Err(Error::Config(format!(
+                        "Unknown CACHE_STORE {store:?}, expected firestore, file or memory"
+                    )))
+                }
+            };
+        }
+        override_with(var, "CACHE_FILE", &mut self.cache.file)?;
+        override_with(var, "FIRESTORE_PROJECT", &mut self.cache.firestore_project)?;
+        override_with(
+            var,
+            "REFRESH_INTERVAL_MINS",
+            &mut self.cache.refresh_interval_mins,
+        )?;
+        override_with(var, "MENU_DAYS", &mut self.cache.days)?;
+        override_with(var, "START_OFFSET_DAYS", &mut self.cache.start_offset_days)?;
+        override_with(var, "LEASE_TTL_SECS", &mut self.cache.lease_ttl_secs)?;
+
+        if let Some(mode) = var("FETCH_MODE") {
+            self.fetch.mode = match mode.as_str() {
+                "live" => FetchMode::Live,
+                "record" => FetchMode::Record,
+                "replay" => FetchMode::Replay,
+                _ => {
+                    return Err(Error::Config(format!(
+                        "Unknown FETCH_MODE {mode:?}, expected live, record or replay"
+                    )))
+                }
+            };
+        }
+        override_with(var, "FETCH_FIXTURES", &mut self.fetch.fixtures)?;
+        override_with(var, "UPSTREAM_URL", &mut self.fetch.upstream_url)?;
+        if let Some(today) = var("FETCH_TODAY") {
+            self.fetch.today = Some(parse("FETCH_TODAY", &today)?);
+        }
+        override_with(var, "RATE_LIMIT", &mut self.fetch.rate_limit)?;
+        override_with(var, "DELAY_JITTER_SECS", &mut self.fetch.delay_jitter_secs)?;
+        override_with(var, "FETCH_RETRIES", &mut self.fetch.retries)?;
+        override_with(var, "FETCH_BACKOFF_MS", &mut self.fetch.backoff_ms)?;
+        override_with(var, "FETCH_TIMEOUT_SECS", &mut self.fetch.timeout_secs)?;
+        override_with(var, "BREAKER_THRESHOLD", &mut self.fetch.breaker_threshold)?;
+        override_with(
+            var,
+            "BREAKER_COOLDOWN_SECS",
+            &mut self.fetch.breaker_cooldown_secs,
+        )?;
+
+        override_with(var, "SCHEDULE_JITTER_SECS", &mut self.scheduler.jitter_secs)?;
+        if let Some(cron) = var("REFRESH_CRON") {
+            // cron expressions have spaces in them, so they're separated by semicolons
+            self.scheduler.cron = cron
+                .split(';')
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        override_with(var, "TIMEZONE", &mut self.scheduler.timezone)?;
+        override_with(
+            var,
+            "SCHEDULE_MIN_AGE_SECS",
+            &mut self.scheduler.min_age_secs,
+        )?;
+
+        if let Some(path) = var("ARCHIVE_PATH") {
+            self.archive.path = Some(path.into());
+        }
+        if let Some(path) = var("HOURS_FILE") {
+            self.hours.file = Some(path.into());
+        }
+        if let Some(path) = var("PLACES_FILE") {
+            self.places.file = Some(path.into());
+        }
+
+        if let Some(token) = var("ADMIN_TOKEN") {
+            self.admin.token = Some(token);
+        }
+        if let Some(secret) = var("ADMIN_HMAC_SECRET") {
+            self.admin.hmac_secret = Some(secret);
+        }
+        override_with(var, "ADMIN_MAX_SKEW_SECS", &mut self.admin.max_skew_secs)?;
+
+        if let Some(format) = var("LOG_FORMAT") {
+            self.log.format = match format.as_str() {
+                "pretty" => LogFormat::Pretty,
+                "json" => LogFormat::Json,
+                _ => {
+                    return Err(Error::Config(format!(
+                        "Unknown LOG_FORMAT {format:?}, expected pretty or json"
+                    )))
+                }
+            };
+        }
+        override_with(var, "RUST_LOG", &mut self.log.filter)?;
+        // the variables of the OpenTelemetry spec, so collectors' docs apply as is
+        if let Some(endpoint) = var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+            self.log.otlp_endpoint = Some(endpoint);
+        }
+        override_with(var, "OTEL_SERVICE_NAME", &mut self.log.service_name)?;
+        Ok(self)
+    }
+
+    /// Catches settings which would only fail once the server is running
+    pub fn validate(&self) -> Result<(), Error> {
+        let positive = [
+            (
+                "cache.refresh_interval_mins",
+                u64::from(self.cache.refresh_interval_mins),
+            ),
+            ("cache.days", u64::from(self.cache.days)),
+            ("cache.lease_ttl_secs", u64::from(self.cache.lease_ttl_secs)),
+            ("fetch.rate_limit", u64::from(self.fetch.rate_limit)),
+            ("fetch.timeout_secs", self.fetch.timeout_secs),
+            (
+                "fetch.breaker_threshold",
+                u64::from(self.fetch.breaker_threshold),
+            ),
+        ];
+        for (name, value) in positive {
+            if value == 0 {
+                return Err(Error::Config(format!("{name} must be greater than 0")));
+            }
+        }
+        if self.cache.start_offset_days >= self.cache.days {
+            return Err(Error::Config(
+                "cache.start_offset_days must be less than cache.days, or today isn't fetched"
+                    .to_string(),
+            ));
+        }
+        if cfg!(not(feature = "firestore")) && self.cache.store == StoreKind::Firestore {
+            return Err(Error::Config(
+                "cache.store is firestore, but the server was built without the `firestore` feature"
+                    .to_string(),
+            ));
+        }
+        Url::parse(&self.fetch.upstream_url).map_err(|e| {
+            Error::Config(format!(
+                "fetch.upstream_url {:?} is invalid: {e}",
+                self.fetch.upstream_url
+            ))
+        })?;
+        Schedule::from_config(self)?;
+        HOURS.load(&self.hours)?;
+        PLACES.load(&self.places)?;
+        tracing_subscriber::EnvFilter::try_new(&self.log.filter)
+            .map_err(|e| Error::Config(format!("log.filter is invalid: {e}")))?;
+        // a short secret is easy to guess, and an empty one would let anyone in
+        let secrets = [
+            ("admin.token", &self.admin.token),
+            ("admin.hmac_secret", &self.admin.hmac_secret),
+        ];
+        for (name, secret) in secrets {
+            if secret.as_ref().is_some_and(|x| x.len() < MIN_SECRET_LEN) {
+                return Err(Error::Config(format!(
+                    "{name} must be at least {MIN_SECRET_LEN} characters"
+                )));
+            }
+        }
+        format!("{}:{}", self.server.host, self.server.port)
+            .parse::<std::net::SocketAddr>()
+            .map_err(|e| Error::Config(format!("server.host is invalid: {e}")))?;
+        Ok(())
+    }
+
+    /// The address the server listens on
+    pub fn addr(&self) -> std::net::SocketAddr {
+        format!("{}:{}", self.server.host, self.server.port)
+            .parse()
+            .expect("address should have been validated")
+    }
+}
+
+fn override_with<T: FromStr>(
+    var: impl Fn(&str) -> Option<String>,
+    name: &str,
+    setting: &mut T,
+) -> Result<(), Error>
+where
+    T::Err: std::fmt::Display,
+{
+    if let Some(value) = var(name) {
+        *setting = parse(name, &value)?;
+    }
+    Ok(())
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, Error>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| Error::Config(format!("{name} {value:?} is invalid: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+            port = 8080
+
+            [cache]
+            store = "file"
+            days = 14
+
+            [fetch]
+            mode = "replay"
+            today = "2024-04-06"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.server.host, "127.0.0.1");
+        assert_eq!(config.cache.store, StoreKind::File);
+        assert_eq!(config.cache.days, 14);
+        assert_eq!(config.cache.refresh_interval_mins, 15);
+        assert_eq!(config.fetch.mode, FetchMode::Replay);
+        assert_eq!(config.fetch.today, NaiveDate::from_ymd_opt(2024, 4, 6));
+        config.validate().unwrap();
+
+        assert!(toml::from_str::<Config>("[cache]\nday = 14").is_err());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let vars = HashMap::from([
+            ("PORT", "9000"),
+            ("CACHE_STORE", "memory"),
+            ("RATE_LIMIT", "5"),
+            ("ARCHIVE_PATH", "menus.db"),
+            ("PLACES_FILE", "places.toml"),
+            ("REFRESH_CRON", "*/5 7-9 * * *; */10 11-13 * * *"),
+            ("LOG_FORMAT", "json"),
+        ]);
+        let config = Config::default()
+            .with_overrides(|name| vars.get(name).map(ToString::to_string))
+            .unwrap();
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(config.cache.store, StoreKind::Memory);
+        assert_eq!(config.fetch.rate_limit, 5);
+        assert_eq!(config.archive.path, Some(PathBuf::from("menus.db")));
+        assert_eq!(config.places.file, Some(PathBuf::from("places.toml")));
+        assert_eq!(
+            config.scheduler.cron,
+            vec!["*/5 7-9 * * *", "*/10 11-13 * * *"]
+        );
+        assert_eq!(config.log.format, LogFormat::Json);
+
+        let invalid = Config::default()
+            .with_overrides(|name| (name == "PORT").then(|| "not a port".to_string()));
+        assert!(matches!(invalid, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn test_validate() {
+        Config::default().validate().unwrap();
+        let mut config = Config::default();
+        config.cache.days = 0;
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.cache.start_offset_days = 10;
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.fetch.upstream_url = "not a url".to_string();
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.scheduler.cron = vec!["* * *".to_string()];
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.scheduler.timezone = "Pacific".to_string();
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.admin.token = Some("hunter2".to_string());
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.log.filter = "ucsc_menu=loud".to_string();
+        assert!(config.validate().is_err());
+    }
+}
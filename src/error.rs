@@ -1,14 +1,35 @@
+//! The error type of the crate
+#[cfg(feature = "firestore")]
 use firestore::errors::FirestoreError;
 
 use crate::parse;
 use std::fmt::{self, Display, Formatter};
 
+/// Anything which can go wrong while fetching the menus, or while serving them
 #[derive(Debug)]
 pub enum Error {
+    /// A page didn't look the way the dining site serves it
     Parse(parse::Error),
+    /// A page couldn't be fetched
     Request(reqwest::Error),
+    /// The Firestore cache couldn't be read or written
+    #[cfg(feature = "firestore")]
     Database(FirestoreError),
+    /// The menu archive couldn't be read or written
+    #[cfg(feature = "server")]
+    Archive(rusqlite::Error),
+    /// The menus couldn't be converted to or from json
     Json(serde_json::Error),
+    /// A file, ex. a fixture, couldn't be read or written
+    Io(std::io::Error),
+    /// The server config is invalid
+    Config(String),
+    /// A request asked for something which doesn't exist or can't be done
+    InvalidInput(String),
+    /// The upstream failed too many requests in a row, so requests to it are paused
+    CircuitOpen,
+    /// Another instance took over the refresh lease, so the menus of this refresh weren't saved
+    LeaseLost,
 }
 
 impl From<parse::Error> for Error {
@@ -23,25 +44,53 @@ impl From<reqwest::Error> for Error {
     }
 }
 
+#[cfg(feature = "firestore")]
 impl From<FirestoreError> for Error {
     fn from(e: FirestoreError) -> Self {
         Self::Database(e)
     }
 }
 
+#[cfg(feature = "server")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Self::Archive(e)
+    }
+}
+
 impl From<serde_json::Error> for Error {
     fn from(e: serde_json::Error) -> Self {
         Self::Json(e)
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Self::Parse(e) => write!(f, "Parse error: {e}"),
             Self::Request(e) => write!(f, "Request error: {e}"),
+            #[cfg(feature = "firestore")]
             Self::Database(e) => write!(f, "Database error: {e}"),
+            #[cfg(feature = "server")]
+            Self::Archive(e) => write!(f, "Archive error: {e}"),
             Self::Json(e) => write!(f, "Json error: {e}"),
+            Self::Io(e) => write!(f, "Io error: {e}"),
+            Self::Config(e) => write!(f, "Config error: {e}"),
+            Self::InvalidInput(e) => write!(f, "Invalid input: {e}"),
+            Self::CircuitOpen => write!(
+                f,
+                "Upstream error: requests are paused after repeated failures"
+            ),
+            Self::LeaseLost => write!(
+                f,
+                "Lease error: another instance took over the refresh before it was saved"
+            ),
         }
     }
 }
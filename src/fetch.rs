@@ -1,22 +1,43 @@
-use std::{num::NonZeroU32, sync::OnceLock, time::Duration};
+//! Gets the pages of the dining site. [`scrape`] fetches and parses the menus in one go, while
+//! the other functions get single pages for [`crate::parse`].
 
+mod fetcher;
+#[cfg(feature = "server")]
+pub mod mock_upstream;
+mod retry;
+
+use std::{path::Path, time::Duration};
+
+use chrono::NaiveDate;
 use governor::{
     clock::{QuantaClock, QuantaInstant},
     middleware::NoOpMiddleware,
     state::InMemoryState,
 };
 use reqwest::{Client, Error as RequestError};
+use scraper::Html;
 use tracing::{instrument, Level};
+use url::Url;
 
+use crate::error::Error;
 use crate::parse::{LocationMeta, Locations};
+pub use fetcher::Fetcher;
+#[cfg(test)]
+pub use fetcher::{examples, examples_dir};
+use fetcher::{fixture_path, label_fixture_path};
+pub use retry::{BreakerState, CircuitBreaker, RetryPolicy};
+
+/// The dining site of UC Santa Cruz, which `Fetcher` uses unless it is given another host
+pub const DEFAULT_UPSTREAM: &str = "https://nutrition.sa.ucsc.edu/";
 
-pub async fn locations_page(client: &reqwest::Client) -> Result<String, RequestError> {
-    static URL: &str = "https://nutrition.sa.ucsc.edu/";
-    let response = client.get(URL).send().await?;
-    response.text().await
+/// The locations page, which is served at the root of the site
+#[instrument(skip_all)]
+pub async fn locations_page(fetcher: &Fetcher) -> Result<String, Error> {
+    let url = fetcher.base().clone();
+    fetcher.get(Path::new("locations.html"), url, None).await
 }
 
-pub fn make_client() -> reqwest::Client {
+pub(crate) fn make_client() -> reqwest::Client {
     Client::builder()
         .danger_accept_invalid_certs(true)
         .gzip(true)
@@ -24,63 +45,158 @@ pub fn make_client() -> reqwest::Client {
         .expect("client creation should succeed")
 }
 
-static RATE_LIMIT: u32 = 20;
-static DELAY_JITTER: u64 = 2;
-static RATE_LIMITER: OnceLock<
-    governor::RateLimiter<
-        governor::state::NotKeyed,
-        InMemoryState,
-        QuantaClock,
-        NoOpMiddleware<QuantaInstant>,
-    >,
-> = OnceLock::new();
-#[instrument(skip(client, location_meta, date), fields(
+type RateLimiter = governor::RateLimiter<
+    governor::state::NotKeyed,
+    InMemoryState,
+    QuantaClock,
+    NoOpMiddleware<QuantaInstant>,
+>;
+
+/// The short menu page of a location, for `date` or for today if there is none
+#[instrument(skip(fetcher, location_meta, date), fields(
     // `%` serializes the peer IP addr with `Display`
     id = %location_meta.id(),
     date = %date.ok_or_else(|| "No date provided").unwrap_or_default().format("%m/%d/%Y"),
-), level = Level::TRACE)]
+))]
 pub async fn fetch_location_page(
-    client: &reqwest::Client,
+    fetcher: &Fetcher,
     location_meta: &LocationMeta,
     date: Option<chrono::NaiveDate>,
-) -> Result<String, RequestError> {
-    let rate_limiter = RATE_LIMITER.get_or_init(|| {
-        governor::RateLimiter::direct(governor::Quota::per_second(
-            NonZeroU32::new(RATE_LIMIT).unwrap(),
-        ))
-    });
-    let retry_jitter = governor::Jitter::new(Duration::ZERO, Duration::from_secs(DELAY_JITTER));
-    rate_limiter.until_ready_with_jitter(retry_jitter).await;
-    let id = location_meta.id();
-    let cookies = format!("WebInaCartDates=;  WebInaCartMeals=; WebInaCartQtys=; WebInaCartRecipes=; WebInaCartLocation={id}");
+) -> Result<String, Error> {
     let mut url = location_meta.url().to_owned();
     if let Some(date) = date {
         url.query_pairs_mut()
             .append_pair("dtdate", date.format("%m/%d/%Y").to_string().as_str());
     }
-    // println!("Fetching location page for\t{}", location_meta.name());
-    let res = client.get(url).header("Cookie", cookies).send().await?;
+    let fixture = fixture_path(location_meta.id(), date, "shortmenu");
+    fetcher.get(&fixture, url, Some(location_meta.id())).await
+}
+
+/// The url of the long menu page for one meal. It shares the query of the short menu page.
+pub fn long_menu_url(
+    location_meta: &LocationMeta,
+    date: chrono::NaiveDate,
+    meal_name: &str,
+) -> Url {
+    let mut url = location_meta.url().to_owned();
+    if let Ok(mut segments) = url.path_segments_mut() {
+        segments.pop().push("longmenu.aspx");
+    }
+    url.query_pairs_mut()
+        .append_pair("dtdate", date.format("%m/%d/%Y").to_string().as_str())
+        .append_pair("mealName", meal_name);
+    url
+}
+
+/// The long menu page of one meal, which has the prices and the links to the nutrition labels
+#[instrument(skip(fetcher, location_meta, date), fields(
+    id = %location_meta.id(),
+    date = %date.format("%m/%d/%Y"),
+))]
+pub async fn fetch_long_menu_page(
+    fetcher: &Fetcher,
+    location_meta: &LocationMeta,
+    date: chrono::NaiveDate,
+    meal_name: &str,
+) -> Result<String, Error> {
+    let url = long_menu_url(location_meta, date, meal_name);
+    let fixture = fixture_path(
+        location_meta.id(),
+        Some(date),
+        &format!("longmenu {meal_name}"),
+    );
+    fetcher.get(&fixture, url, Some(location_meta.id())).await
+}
+
+/// The nutrition label page at `url`, which is linked from the long menu page
+#[instrument(skip_all, fields(url = %url), level = Level::DEBUG)]
+pub async fn fetch_label_page(fetcher: &Fetcher, url: Url) -> Result<String, Error> {
+    let fixture = label_fixture_path(&url);
+    fetcher.get(&fixture, url, None).await
+}
+
+async fn fetch_page(
+    client: &reqwest::Client,
+    url: Url,
+    location_id: Option<&str>,
+    timeout: Duration,
+) -> Result<String, RequestError> {
+    let mut request = client.get(url).timeout(timeout);
+    if let Some(id) = location_id {
+        let cookies = format!("WebInaCartDates=;  WebInaCartMeals=; WebInaCartQtys=; WebInaCartRecipes=; WebInaCartLocation={id}");
+        request = request.header("Cookie", cookies);
+    }
+    let res = request.send().await?.error_for_status()?;
     let start = std::time::Instant::now();
     let text = res.text().await?;
-    log::trace!("Got text of location page in \t {:?}", start.elapsed());
-    // gzip decode
-    // let html = scraper::Html::parse_document(&text);
+    tracing::trace!("Got text of page in \t {:?}", start.elapsed());
     Ok(text)
 }
 
+/// The menu page of every location on `date`, in the same order as `locations`
 pub async fn menus_on_date(
-    client: &reqwest::Client,
+    fetcher: &Fetcher,
     locations: &Locations<'_>,
     date: Option<chrono::NaiveDate>,
-) -> Result<Vec<String>, RequestError> {
-    futures::future::try_join_all(
+) -> Vec<Result<String, Error>> {
+    futures::future::join_all(
         locations
             .iter()
-            .map(|x| fetch_location_page(client, x.metadata(), date)),
+            .map(|x| fetch_location_page(fetcher, x.metadata(), date)),
     )
     .await
 }
 
+/// Fetches and parses the menus of every location, or only of `location_id`, on `dates`.
+/// Menus which fail to be fetched or parsed are logged and left out.
+pub async fn scrape(
+    fetcher: &Fetcher,
+    dates: &[NaiveDate],
+    location_id: Option<&str>,
+) -> Result<Locations<'static>, Error> {
+    let page = locations_page(fetcher).await?;
+    let mut locations = {
+        let html = Html::parse_document(&page);
+        Locations::from_html_element(html.root_element(), fetcher.base())?
+    };
+    if let Some(id) = location_id {
+        locations.retain(|x| x.id() == id);
+        if locations.iter().next().is_none() {
+            return Err(Error::InvalidInput(format!("Unknown location {id:?}")));
+        }
+    }
+    let pages = futures::future::join_all(
+        dates
+            .iter()
+            .map(|date| menus_on_date(fetcher, &locations, Some(*date))),
+    )
+    .await;
+    // the menus borrow from the parsed pages, so those have to outlive them
+    let pages: Vec<Vec<_>> = pages
+        .into_iter()
+        .map(|pages| {
+            pages
+                .into_iter()
+                .map(|page| page.map(|x| Html::parse_document(&x)))
+                .collect()
+        })
+        .collect();
+    for (date, pages) in dates.iter().zip(&pages) {
+        for (location, page) in locations.iter_mut().zip(pages) {
+            let added = match page {
+                Ok(html) => location.add_meal(html).map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = added {
+                tracing::warn!("Failed to get the menu of {} on {date}: {e}", location.id());
+            }
+        }
+    }
+    // round trip through json to own the strings borrowed from the pages
+    Ok(serde_json::from_str(&serde_json::to_string(&locations)?)?)
+}
+
+/// `count` days, starting with `start`
 pub fn date_iter(start: chrono::NaiveDate, count: i64) -> impl Iterator<Item = chrono::NaiveDate> {
     (0..count).map(move |x| start + chrono::Duration::days(x))
 }
@@ -88,30 +204,52 @@ pub fn date_iter(start: chrono::NaiveDate, count: i64) -> impl Iterator<Item = c
 #[cfg(test)]
 mod tests {
 
-    use crate::parse::Locations;
+    use crate::parse::{DailyMenu, Locations};
 
     use super::*;
 
-    use url::Url;
+    #[tokio::test]
+    async fn test_scrape() {
+        let date = NaiveDate::from_ymd_opt(2024, 4, 5).unwrap();
+        let locations = scrape(&examples(), &[date], None).await.unwrap();
+        let location = locations.iter().next().unwrap();
+        assert_eq!(location.id(), "40");
+        assert_eq!(
+            location
+                .daily_menus()
+                .map(DailyMenu::date)
+                .collect::<Vec<_>>(),
+            vec![date]
+        );
+        // the menu on a day without a fixture fails, and is left out
+        let next_day = date.succ_opt().unwrap();
+        let locations = scrape(&examples(), &[date, next_day], Some("40"))
+            .await
+            .unwrap();
+        assert_eq!(locations.food_items().count(), 71);
+        assert!(scrape(&examples(), &[date], Some("99")).await.is_err());
+    }
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    #[ignore = "needs network access"]
     async fn test_fetch_locations_page() {
         // setup_tracing();
         let start_time = std::time::Instant::now();
-        let client = make_client();
-        let page = locations_page(&client).await.unwrap();
+        let fetcher = Fetcher::live();
+        let page = locations_page(&fetcher).await.unwrap();
         println!(
             "Time taken to get locations page: {:?}",
             start_time.elapsed()
         );
         let parsed = scraper::Html::parse_document(&page);
-        let _locations: Locations = Locations::from_html_element(parsed.root_element()).unwrap();
+        let _locations: Locations =
+            Locations::from_html_element(parsed.root_element(), fetcher.base()).unwrap();
         println!("Time taken to parse locations:\t{:?}", start_time.elapsed());
     }
 
     #[tokio::test]
+    #[ignore = "needs network access"]
     async fn test_fetch_location_page() {
-        let client = make_client();
         let url: Url = "https://nutrition.sa.ucsc.edu/shortmenu.aspx?\
         sName=UC+Santa+Cruz+Dining&\
         locationNum=40&\
@@ -119,9 +257,81 @@ mod tests {
             .parse()
             .expect("url should be valid");
         let location_meta = LocationMeta::from_url(url).expect("location meta should be valid");
-        let page = fetch_location_page(&client, &location_meta, None)
+        let page = fetch_location_page(&Fetcher::live(), &location_meta, None)
             .await
             .unwrap();
         println!("{page:#?}");
     }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let fetcher = examples();
+        assert_eq!(
+            fetcher.today(),
+            chrono::NaiveDate::from_ymd_opt(2024, 4, 6).unwrap()
+        );
+        let page = locations_page(&fetcher).await.unwrap();
+        let parsed = scraper::Html::parse_document(&page);
+        let locations =
+            Locations::from_html_element(parsed.root_element(), fetcher.base()).unwrap();
+        assert_eq!(locations.iter().len(), 1);
+
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 4, 5);
+        let menus = menus_on_date(&fetcher, &locations, date).await;
+        assert!(menus[0]
+            .as_ref()
+            .unwrap()
+            .contains("Menus for Friday, April 5, 2024"));
+        // nothing was recorded for the next day
+        let menus = menus_on_date(&fetcher, &locations, date.unwrap().succ_opt()).await;
+        assert!(menus[0].is_err());
+    }
+
+    // the pages are recorded from the mock of the site
+    #[cfg(feature = "server")]
+    #[tokio::test]
+    async fn test_record() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base: Url = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        tokio::spawn(mock_upstream::serve(examples_dir().to_path_buf(), listener));
+
+        let dir = std::env::temp_dir().join(format!("ucsc_menu_record_{}", std::process::id()));
+        let recorder = Fetcher::record(&dir).unwrap().with_base(base.clone());
+        let page = locations_page(&recorder).await.unwrap();
+        let parsed = scraper::Html::parse_document(&page);
+        let locations = Locations::from_html_element(parsed.root_element(), &base).unwrap();
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 4, 5);
+        let menus = menus_on_date(&recorder, &locations, date).await;
+        let menu = menus[0].as_ref().unwrap();
+        assert!(menu.contains("Menus for Friday, April 5, 2024"));
+
+        // the pages are saved where replaying looks for them
+        assert!(dir.join(fixture_path("40", date, "shortmenu")).exists());
+        let replay = Fetcher::replay(&dir).unwrap().with_base(base);
+        assert_eq!(replay.today(), chrono::Utc::now().date_naive());
+        assert_eq!(locations_page(&replay).await.unwrap(), page);
+        let replayed = menus_on_date(&replay, &locations, date).await;
+        assert_eq!(replayed[0].as_ref().unwrap(), menu);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_long_menu_url() {
+        let url: Url = "https://nutrition.sa.ucsc.edu/shortmenu.aspx?\
+        sName=UC+Santa+Cruz+Dining&\
+        locationNum=40&\
+        locationName=College+Nine/John+R.+Lewis+Dining+Hall&naFlag=1"
+            .parse()
+            .expect("url should be valid");
+        let location_meta = LocationMeta::from_url(url).expect("location meta should be valid");
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 4, 5).unwrap();
+        let url = long_menu_url(&location_meta, date, "Late Night");
+        assert_eq!(url.path(), "/longmenu.aspx");
+        let query: Vec<_> = url.query_pairs().collect();
+        assert!(query.contains(&("locationNum".into(), "40".into())));
+        assert!(query.contains(&("dtdate".into(), "04/05/2024".into())));
+        assert!(query.contains(&("mealName".into(), "Late Night".into())));
+    }
 }
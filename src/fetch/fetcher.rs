@@ -0,0 +1,325 @@
+use std::{
+    io,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::NaiveDate;
+use reqwest::Client;
+use url::Url;
+
+use governor::Quota;
+
+use super::{
+    fetch_page, make_client, BreakerState, CircuitBreaker, RateLimiter, RetryPolicy,
+    DEFAULT_UPSTREAM,
+};
+#[cfg(feature = "server")]
+use crate::config::{FetchConfig, FetchMode};
+use crate::error::Error;
+
+const DEFAULT_RATE_LIMIT: u32 = 20; // requests per second
+const DEFAULT_JITTER: Duration = Duration::from_secs(2);
+
+// holds the date the fixtures were recorded on, which replay mode treats as today
+const RECORDED_ON_FILE: &str = "recorded_on";
+
+#[derive(Debug, Clone)]
+enum Mode {
+    Live,
+    /// Fetches pages like `Live`, and also saves them to the directory
+    Record(PathBuf),
+    /// Serves the pages saved by `Record` instead of fetching them
+    Replay(PathBuf),
+}
+
+/// Gets pages from the dining site, or from fixtures saved to a directory.
+/// Fixtures are saved under the location id and date of the page, ex. `40/2024-04-05/shortmenu.html`.
+#[derive(Debug, Clone)]
+pub struct Fetcher {
+    client: Client,
+    base: Url,
+    mode: Mode,
+    today: Option<NaiveDate>,
+    // the limiter and breaker are shared between clones, since they all request the same site
+    limiter: Arc<RateLimiter>,
+    jitter: Duration,
+    retry: RetryPolicy,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl Default for Fetcher {
+    fn default() -> Self {
+        Self::live()
+    }
+}
+
+impl Fetcher {
+    /// Fetches the pages from the dining site
+    pub fn live() -> Self {
+        Self::with_mode(Mode::Live)
+    }
+
+    fn with_mode(mode: Mode) -> Self {
+        Self {
+            client: make_client(),
+            base: default_base(),
+            mode,
+            today: None,
+            limiter: Arc::new(RateLimiter::direct(Quota::per_second(
+                NonZeroU32::new(DEFAULT_RATE_LIMIT).expect("default rate limit is positive"),
+            ))),
+            jitter: DEFAULT_JITTER,
+            retry: RetryPolicy::default(),
+            breaker: Arc::default(),
+        }
+    }
+
+    /// Fetches the pages from the dining site, and saves them to `dir` to be replayed later
+    pub fn record(dir: impl Into<PathBuf>) -> Result<Self, Error> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let today = chrono::Utc::now().date_naive();
+        std::fs::write(dir.join(RECORDED_ON_FILE), today.to_string())?;
+        Ok(Self::with_mode(Mode::Record(dir)))
+    }
+
+    /// Replays the fixtures in `dir` as if it were still the day they were recorded on
+    pub fn replay(dir: impl Into<PathBuf>) -> Result<Self, Error> {
+        let dir = dir.into();
+        let today =
+            match std::fs::read_to_string(dir.join(RECORDED_ON_FILE)) {
+                Ok(date) => Some(date.trim().parse().map_err(|e| {
+                    Error::Config(format!("{RECORDED_ON_FILE} is not a date: {e}"))
+                })?),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+        Ok(Self {
+            today,
+            ..Self::with_mode(Mode::Replay(dir))
+        })
+    }
+
+    #[cfg(feature = "server")]
+    pub(crate) fn from_config(config: &FetchConfig) -> Result<Self, Error> {
+        let fetcher = match config.mode {
+            FetchMode::Live => Self::live(),
+            FetchMode::Record => Self::record(&config.fixtures)?,
+            FetchMode::Replay => Self::replay(&config.fixtures)?,
+        };
+        let base = Url::parse(&config.upstream_url)
+            .map_err(|e| Error::Config(format!("upstream url is invalid: {e}")))?;
+        let mut fetcher = fetcher
+            .with_base(base)
+            .with_rate_limit(
+                config.rate_limit,
+                Duration::from_secs(config.delay_jitter_secs),
+            )?
+            .with_retry_policy(RetryPolicy {
+                max_retries: config.retries,
+                initial_backoff: Duration::from_millis(config.backoff_ms),
+                timeout: Duration::from_secs(config.timeout_secs),
+                ..RetryPolicy::default()
+            })
+            .with_circuit_breaker(CircuitBreaker::new(
+                config.breaker_threshold,
+                Duration::from_secs(config.breaker_cooldown_secs),
+            ));
+        if let Some(today) = config.today {
+            fetcher = fetcher.with_today(today);
+        }
+        Ok(fetcher)
+    }
+
+    /// Limits requests to `per_second`, each randomly delayed by up to `jitter`
+    pub fn with_rate_limit(mut self, per_second: u32, jitter: Duration) -> Result<Self, Error> {
+        let per_second = NonZeroU32::new(per_second)
+            .ok_or_else(|| Error::Config("the rate limit must be greater than 0".to_string()))?;
+        self.limiter = Arc::new(RateLimiter::direct(Quota::per_second(per_second)));
+        self.jitter = jitter;
+        Ok(self)
+    }
+
+    /// Retries the pages which fail with `retry` instead of the default policy
+    pub const fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Pauses requests with `breaker` instead of the default one
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Arc::new(breaker);
+        self
+    }
+
+    /// Whether requests to the site are currently paused after it failed repeatedly
+    pub fn breaker_state(&self) -> BreakerState {
+        self.breaker.state()
+    }
+
+    /// Fetches the menus as if it were `today`
+    pub const fn with_today(mut self, today: NaiveDate) -> Self {
+        self.today = Some(today);
+        self
+    }
+
+    /// Fetches from another site running the same menu software, ex. a local mock of it
+    pub fn with_base(mut self, base: Url) -> Self {
+        self.base = base;
+        self
+    }
+
+    /// The root of the site, where the locations page is served
+    pub const fn base(&self) -> &Url {
+        &self.base
+    }
+
+    /// The current date, or the date the fixtures were recorded on when replaying
+    pub fn today(&self) -> NaiveDate {
+        self.today
+            .unwrap_or_else(|| chrono::Utc::now().date_naive())
+    }
+
+    /// `fixture` is the path of the page relative to the fixture directory
+    pub(super) async fn get(
+        &self,
+        fixture: &Path,
+        url: Url,
+        location_id: Option<&str>,
+    ) -> Result<String, Error> {
+        let fetch = || {
+            self.retry.run(&self.breaker, |timeout| {
+                let url = url.clone();
+                async move {
+                    let jitter = governor::Jitter::new(Duration::ZERO, self.jitter);
+                    self.limiter.until_ready_with_jitter(jitter).await;
+                    fetch_page(&self.client, url, location_id, timeout).await
+                }
+            })
+        };
+        #[cfg(feature = "server")]
+        let started = std::time::Instant::now();
+        let page = match &self.mode {
+            Mode::Live => fetch().await,
+            Mode::Record(dir) => {
+                let page = fetch().await?;
+                save_fixture(&dir.join(fixture), &page).await?;
+                Ok(page)
+            }
+            Mode::Replay(dir) => {
+                let path = dir.join(fixture);
+                tokio::fs::read_to_string(&path).await.map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!("failed to replay {}: {e}", path.display()),
+                    )
+                    .into()
+                })
+            }
+        };
+        #[cfg(feature = "server")]
+        if let Some(id) = location_id {
+            crate::metrics::get().fetched(id, started.elapsed(), page.is_ok());
+        }
+        #[cfg(feature = "server")]
+        crate::metrics::get().breaker(self.breaker_state());
+        page
+    }
+}
+
+fn default_base() -> Url {
+    Url::parse(DEFAULT_UPSTREAM).expect("default upstream should be valid")
+}
+
+async fn save_fixture(path: &Path, page: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, page).await
+}
+
+/// Where the page is saved in the fixture directory. `date` is `None` for the current menu.
+pub(super) fn fixture_path(location_id: &str, date: Option<NaiveDate>, page: &str) -> PathBuf {
+    let date = date.map_or_else(|| "current".to_string(), |date| date.to_string());
+    [location_id, &date, &format!("{}.html", sanitize(page))]
+        .iter()
+        .collect()
+}
+
+pub(super) fn label_fixture_path(url: &Url) -> PathBuf {
+    // labels are the same at every location and on every day, so they are saved by recipe
+    let recipe = url
+        .query_pairs()
+        .find(|(key, _)| key == "RecNumAndPort")
+        .map_or_else(|| url.as_str().to_string(), |(_, value)| value.into_owned());
+    ["labels", &format!("{}.html", sanitize(&recipe))]
+        .iter()
+        .collect()
+}
+
+/// Keeps only the characters which are safe in a file name on every platform
+pub(super) fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// A replaying fetcher over the pages in `html_examples`, which are all for location 40
+#[cfg(test)]
+pub fn examples() -> Fetcher {
+    Fetcher::replay(examples_dir()).unwrap()
+}
+
+/// A fixture directory with the pages in `html_examples`, under `target` so that it is written
+/// over by each test run instead of piling up
+#[cfg(test)]
+pub fn examples_dir() -> &'static Path {
+    use std::sync::OnceLock;
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+    DIR.get_or_init(|| {
+        let examples = Path::new("./src/parse/html_examples");
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/ucsc_menu_fixtures");
+        let copy = |from: &str, to: PathBuf| {
+            let to = dir.join(to);
+            std::fs::create_dir_all(to.parent().unwrap()).unwrap();
+            std::fs::copy(examples.join(from), to).unwrap();
+        };
+        // the locations page with only the location which has example menus
+        let location = std::fs::read_to_string(examples.join("locations/location.html")).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("locations.html"),
+            format!(r#"<div id="locationchoices"><ul>{location}</ul></div>"#),
+        )
+        .unwrap();
+        let date = |day| NaiveDate::from_ymd_opt(2024, 4, day);
+        copy(
+            "daily_menu/dining_hall.html",
+            fixture_path("40", date(5), "shortmenu"),
+        );
+        copy(
+            "daily_menu/meals_on_date.html",
+            fixture_path("40", date(9), "shortmenu"),
+        );
+        // the long menu example is from another day, but the parser doesn't look at the date
+        copy(
+            "nutrition/items.html",
+            fixture_path("40", date(5), "longmenu Breakfast"),
+        );
+        // the only label example is for pancakes, which aren't on the short menu of the 5th,
+        // so it stands in for the label of the scrambled eggs
+        copy(
+            "nutrition/item.html",
+            label_fixture_path(
+                &"https://nutrition.sa.ucsc.edu/label.aspx?RecNumAndPort=061002*3"
+                    .parse()
+                    .unwrap(),
+            ),
+        );
+        std::fs::write(dir.join(RECORDED_ON_FILE), "2024-04-06").unwrap();
+        dir
+    })
+}
@@ -0,0 +1,140 @@
+//! A stand-in for the dining site which serves pages from a fixture directory, laid out the way
+//! `Fetcher::record` saves them. Point `UPSTREAM_URL` at it for integration tests and staging.
+
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use chrono::NaiveDate;
+use tokio::net::TcpListener;
+use url::Url;
+
+use super::fetcher::{fixture_path, label_fixture_path};
+use super::DEFAULT_UPSTREAM;
+
+/// Serves the fixtures in `dir` at the paths of the dining site
+pub fn router(dir: PathBuf) -> Router {
+    Router::new()
+        .route("/", get(page))
+        .route("/location.aspx", get(page))
+        .route("/shortmenu.aspx", get(page))
+        .route("/longmenu.aspx", get(page))
+        .route("/label.aspx", get(page))
+        .with_state(Arc::new(dir))
+}
+
+/// Serves the fixtures in `dir` on `listener` until the server fails
+pub async fn serve(dir: PathBuf, listener: TcpListener) -> std::io::Result<()> {
+    axum::serve(listener, router(dir)).await
+}
+
+async fn page(State(dir): State<Arc<PathBuf>>, uri: Uri, headers: HeaderMap) -> Response {
+    let query: HashMap<String, String> =
+        url::form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes())
+            .into_owned()
+            .collect();
+    // like the real site, the cookie picks the location over the query
+    let location_id = cookie(&headers, "WebInaCartLocation")
+        .filter(|id| !id.is_empty())
+        .or_else(|| query.get("locationNum").cloned());
+    let date = match query.get("dtdate") {
+        Some(date) => match NaiveDate::parse_from_str(date, "%m/%d/%Y") {
+            Ok(date) => Some(date),
+            Err(_) => return (StatusCode::BAD_REQUEST, "invalid dtdate").into_response(),
+        },
+        None => None,
+    };
+    let fixture = match (uri.path(), location_id) {
+        ("/" | "/location.aspx", _) => PathBuf::from("locations.html"),
+        ("/shortmenu.aspx", Some(id)) => fixture_path(&id, date, "shortmenu"),
+        ("/longmenu.aspx", Some(id)) => {
+            let meal_name = query.get("mealName").map_or("", String::as_str);
+            fixture_path(&id, date, &format!("longmenu {meal_name}"))
+        }
+        ("/label.aspx", _) => {
+            let url = Url::parse(DEFAULT_UPSTREAM)
+                .and_then(|base| base.join(&uri.to_string()))
+                .expect("request uri should be a valid path");
+            label_fixture_path(&url)
+        }
+        _ => return (StatusCode::BAD_REQUEST, "no location was given").into_response(),
+    };
+    tokio::fs::read_to_string(dir.join(&fixture))
+        .await
+        .map_or_else(
+            |_| {
+                let message = format!("no fixture at {}", fixture.display());
+                (StatusCode::NOT_FOUND, message).into_response()
+            },
+            |page| ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], page).into_response(),
+        )
+}
+
+fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::{examples_dir, fetch_location_page, locations_page, make_client, Fetcher};
+    use crate::parse::Locations;
+
+    async fn spawn() -> Fetcher {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(examples_dir().to_path_buf(), listener));
+        Fetcher::live().with_base(format!("http://{addr}/").parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_serves_fixtures() {
+        let fetcher = spawn().await;
+        let page = locations_page(&fetcher).await.unwrap();
+        let parsed = scraper::Html::parse_document(&page);
+        let locations =
+            Locations::from_html_element(parsed.root_element(), fetcher.base()).unwrap();
+        let location = locations.iter().next().unwrap().metadata();
+        // the locations point back at the mock
+        assert_eq!(location.url().host_str(), Some("127.0.0.1"));
+
+        let date = NaiveDate::from_ymd_opt(2024, 4, 9);
+        let page = fetch_location_page(&fetcher, location, date).await.unwrap();
+        assert!(page.contains("Menus for Tuesday, April 9, 2024"));
+    }
+
+    #[tokio::test]
+    async fn test_honours_cookie() {
+        let fetcher = spawn().await;
+        let url = fetcher
+            .base()
+            .join("shortmenu.aspx?locationNum=50&dtdate=04%2F05%2F2024")
+            .unwrap();
+        let client = make_client();
+        // there are no menus for location 50
+        let response = client.get(url.clone()).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 404);
+        // but the cookie picks location 40 over the query
+        let response = client
+            .get(url)
+            .header("Cookie", "WebInaCartDates=; WebInaCartLocation=40")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let page = response.text().await.unwrap();
+        assert!(page.contains("Menus for Friday, April 5, 2024"));
+    }
+}
@@ -0,0 +1,341 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use reqwest::Error as RequestError;
+
+use crate::error::Error;
+
+/// How a page is retried when the dining site fails to serve it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, so a page is requested at most `max_retries + 1` times
+    pub max_retries: u32,
+    /// The wait before the first retry, which doubles after every retry
+    pub initial_backoff: Duration,
+    /// The longest wait between two retries, however many retries there have been
+    pub max_backoff: Duration,
+    /// How long a single attempt may take, including reading the page
+    pub timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn backoff(&self, retry: u32) -> Duration {
+        // saturate instead of overflowing for large retry counts
+        let factor = 1u32.checked_shl(retry).unwrap_or(u32::MAX);
+        let backoff = self.initial_backoff.saturating_mul(factor);
+        if backoff.as_nanos() > self.max_backoff.as_nanos() {
+            self.max_backoff
+        } else {
+            backoff
+        }
+    }
+
+    /// Requests a page with `attempt` until it succeeds, fails in a way which retrying won't
+    /// fix, or runs out of retries. Gives up early while `breaker` is open.
+    pub async fn run<F, Fut>(
+        &self,
+        breaker: &CircuitBreaker,
+        mut attempt: F,
+    ) -> Result<String, Error>
+    where
+        F: FnMut(Duration) -> Fut,
+        Fut: Future<Output = Result<String, RequestError>>,
+    {
+        breaker.try_acquire()?;
+        let mut retry = 0;
+        loop {
+            match attempt(self.timeout).await {
+                Ok(page) => {
+                    breaker.record_success();
+                    return Ok(page);
+                }
+                Err(e) if is_transient(&e) && retry < self.max_retries => {
+                    let backoff = self.backoff(retry);
+                    tracing::debug!("Retrying in {backoff:?} after: {e}");
+                    tokio::time::sleep(backoff).await;
+                    retry += 1;
+                }
+                Err(e) => {
+                    if is_transient(&e) {
+                        breaker.record_failure();
+                    } else {
+                        // the site answered, it just didn't have the page
+                        breaker.record_success();
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+}
+
+/// Timeouts, dropped connections and server errors may go away on their own,
+/// while other client errors such as a missing page won't
+fn is_transient(e: &RequestError) -> bool {
+    if e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() {
+        return true;
+    }
+    e.status()
+        .is_some_and(|status| status.is_server_error() || status.as_u16() == 429)
+}
+
+/// Whether a `CircuitBreaker` lets requests through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Requests go through as usual
+    Closed {
+        /// The failed requests since the last request which succeeded
+        consecutive_failures: u32,
+    },
+    /// Requests fail immediately until the cooldown is over
+    Open {
+        /// When the cooldown is over
+        until: Instant,
+    },
+    /// A single request is let through to check if the site has recovered
+    HalfOpen {
+        /// When the cooldown was over
+        since: Instant,
+    },
+}
+
+impl Display for BreakerState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Closed {
+                consecutive_failures,
+            } => write!(f, "closed ({consecutive_failures} consecutive failures)"),
+            Self::Open { until } => write!(
+                f,
+                "open (retrying in {}s)",
+                until.saturating_duration_since(Instant::now()).as_secs()
+            ),
+            Self::HalfOpen { .. } => write!(f, "half open (checking if the upstream recovered)"),
+        }
+    }
+}
+
+/// Stops requesting pages from the dining site after it fails `threshold` requests in a row,
+/// so that it isn't hammered while it is down. Requests are let through again after `cooldown`.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(5, Duration::from_mins(1))
+    }
+}
+
+impl CircuitBreaker {
+    /// Opens after `threshold` failed requests in a row, for `cooldown`
+    pub const fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::new(BreakerState::Closed {
+                consecutive_failures: 0,
+            }),
+        }
+    }
+
+    /// Whether requests are currently let through
+    pub fn state(&self) -> BreakerState {
+        *self.state.lock().expect("lock should not be poisoned")
+    }
+
+    fn try_acquire(&self) -> Result<(), Error> {
+        let mut state = self.state.lock().expect("lock should not be poisoned");
+        let now = Instant::now();
+        let acquired = match *state {
+            BreakerState::Closed { .. } => Ok(()),
+            BreakerState::Open { until } if now < until => Err(Error::CircuitOpen),
+            // the probe is given a cooldown to finish before another one is let through
+            BreakerState::HalfOpen { since } if now < since + self.cooldown => {
+                Err(Error::CircuitOpen)
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => {
+                *state = BreakerState::HalfOpen { since: now };
+                Ok(())
+            }
+        };
+        drop(state);
+        acquired
+    }
+
+    fn record_success(&self) {
+        *self.state.lock().expect("lock should not be poisoned") = BreakerState::Closed {
+            consecutive_failures: 0,
+        };
+    }
+
+    fn record_failure(&self) {
+        let mut state = self.state.lock().expect("lock should not be poisoned");
+        let consecutive_failures = match *state {
+            BreakerState::Closed {
+                consecutive_failures,
+            } => consecutive_failures + 1,
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => self.threshold,
+        };
+        *state = if consecutive_failures >= self.threshold {
+            tracing::warn!(
+                "The upstream failed {consecutive_failures} requests in a row, pausing requests for {:?}",
+                self.cooldown
+            );
+            BreakerState::Open {
+                until: Instant::now() + self.cooldown,
+            }
+        } else {
+            BreakerState::Closed {
+                consecutive_failures,
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    use axum::{http::StatusCode, routing::get, Router};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::fetch::make_client;
+
+    const fn policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    // serves `status` for the first `failures` requests, and a page after that
+    async fn flaky(failures: u32, status: StatusCode) -> (String, Arc<AtomicU32>) {
+        let requests = Arc::new(AtomicU32::new(0));
+        let counter = requests.clone();
+        let app = Router::new().route(
+            "/",
+            get(move || async move {
+                if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                    Err(status)
+                } else {
+                    Ok("page")
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, requests)
+    }
+
+    async fn get_page(
+        policy: &RetryPolicy,
+        breaker: &CircuitBreaker,
+        url: &str,
+    ) -> Result<String, Error> {
+        let client = make_client();
+        policy
+            .run(breaker, |timeout| {
+                let request = client.get(url).timeout(timeout).send();
+                async move { request.await?.error_for_status()?.text().await }
+            })
+            .await
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = policy(10);
+        assert_eq!(policy.backoff(0), Duration::from_millis(1));
+        assert_eq!(policy.backoff(2), Duration::from_millis(4));
+        assert_eq!(policy.backoff(3), Duration::from_millis(4));
+        assert_eq!(policy.backoff(40), Duration::from_millis(4));
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors() {
+        let (url, requests) = flaky(2, StatusCode::SERVICE_UNAVAILABLE).await;
+        let breaker = CircuitBreaker::default();
+        let page = get_page(&policy(3), &breaker, &url).await.unwrap();
+        assert_eq!(page, "page");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            breaker.state(),
+            BreakerState::Closed {
+                consecutive_failures: 0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_client_errors() {
+        let (url, requests) = flaky(1, StatusCode::NOT_FOUND).await;
+        let breaker = CircuitBreaker::default();
+        assert!(get_page(&policy(3), &breaker, &url).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_breaker_opens() {
+        let (url, requests) = flaky(u32::MAX, StatusCode::INTERNAL_SERVER_ERROR).await;
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(50));
+        for _ in 0..2 {
+            assert!(matches!(
+                get_page(&policy(1), &breaker, &url).await,
+                Err(Error::Request(_))
+            ));
+        }
+        assert!(matches!(breaker.state(), BreakerState::Open { .. }));
+        // the upstream isn't requested while the breaker is open
+        assert!(matches!(
+            get_page(&policy(1), &breaker, &url).await,
+            Err(Error::CircuitOpen)
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        // after the cooldown a failed probe opens it again
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(get_page(&policy(0), &breaker, &url).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 5);
+        assert!(matches!(breaker.state(), BreakerState::Open { .. }));
+    }
+
+    #[test]
+    fn test_half_open_lets_one_probe_through() {
+        let breaker = CircuitBreaker::new(1, Duration::from_mins(1));
+        breaker.record_failure();
+        assert!(breaker.try_acquire().is_err());
+        *breaker.state.lock().unwrap() = BreakerState::Open {
+            until: Instant::now(),
+        };
+        assert!(breaker.try_acquire().is_ok());
+        assert!(matches!(breaker.state(), BreakerState::HalfOpen { .. }));
+        assert!(breaker.try_acquire().is_err());
+        breaker.record_success();
+        assert!(breaker.try_acquire().is_ok());
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    sync::Arc,
+};
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use juniper::GraphQLObject;
+
+use crate::{
+    bundled::{Bundled, Overridable},
+    error::Error,
+    location_view::LocationView,
+    parse::{Locations, Meal, MealType, ServicePeriod},
+};
+
+/// The hours used by `Location.hours`, `Location.isOpen` and `Query.openNow`, with `hours.file`
+/// on top of the bundled `hours.toml`. See the comments in it for the format.
+pub static HOURS: Bundled<Hours> = Bundled::new("hours", include_str!("hours.toml"));
+
+/// When each location serves each of its meals, in the timezone of the dining halls
+#[derive(Debug, Clone)]
+pub struct Hours {
+    timezone: Tz,
+    holidays: BTreeMap<NaiveDate, String>,
+    locations: HashMap<String, LocationHours>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HoursFile {
+    timezone: Option<String>,
+    holidays: BTreeMap<NaiveDate, String>,
+    locations: HashMap<String, LocationHours>,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LocationHours {
+    /// The hours on days without an override
+    daily: Vec<Service>,
+    /// Replaces `daily` on these days of the week, ex. `sat`
+    weekdays: HashMap<Weekday, Vec<Service>>,
+    /// Replaces the hours on these dates, even holidays. An empty list closes the location.
+    dates: BTreeMap<NaiveDate, Vec<Service>>,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Service {
+    meal: MealType,
+    open: NaiveTime,
+    /// At or before `open` if the meal is served past midnight
+    close: NaiveTime,
+}
+
+/// A location which is serving a meal, and what is on the menu for it
+#[derive(Debug, Clone, GraphQLObject)]
+pub struct OpenLocation<'a> {
+    pub location: LocationView<'a, 'a>,
+    pub service: ServicePeriod,
+    /// Null if the menu of the day hasn't been fetched or doesn't have the meal
+    pub meal: Option<Meal<'a>>,
+}
+
+impl Default for Hours {
+    fn default() -> Self {
+        Self {
+            timezone: Tz::UTC,
+            holidays: BTreeMap::new(),
+            locations: HashMap::new(),
+        }
+    }
+}
+
+impl Overridable for Hours {
+    type File = HoursFile;
+
+    fn extend(&mut self, file: HoursFile, name: &str) -> Result<(), Error> {
+        if let Some(timezone) = file.timezone {
+            self.timezone = Tz::from_str(&timezone).map_err(|e| {
+                Error::Config(format!(
+                    "the timezone {timezone:?} of {name} is invalid: {e}"
+                ))
+            })?;
+        }
+        self.holidays.extend(file.holidays);
+        // a location's hours are replaced as a whole, so the overrides don't mix with the
+        // bundled weekdays and dates
+        self.locations.extend(file.locations);
+        Ok(())
+    }
+}
+
+impl Hours {
+    /// The last holiday, if every holiday is before `at`. The holidays are dated, so the ones
+    /// of each new year have to be added.
+    pub fn holidays_ended(&self, at: DateTime<Utc>) -> Option<NaiveDate> {
+        let (last, _) = self.holidays.last_key_value()?;
+        (*last < self.date_at(at)).then_some(*last)
+    }
+
+    /// The local date at `at`
+    pub fn date_at(&self, at: DateTime<Utc>) -> NaiveDate {
+        at.with_timezone(&self.timezone).date_naive()
+    }
+
+    /// The meals the location serves on `date`, in the order they are listed. Empty if the
+    /// location is closed or its hours aren't known.
+    pub fn on(&self, location_id: &str, date: NaiveDate) -> Vec<ServicePeriod> {
+        let Some(location) = self.locations.get(location_id) else {
+            return Vec::new();
+        };
+        let services = match location.dates.get(&date) {
+            Some(services) => services.as_slice(),
+            None if self.holidays.contains_key(&date) => &[],
+            None => location
+                .weekdays
+                .get(&date.weekday())
+                .unwrap_or(&location.daily),
+        };
+        services
+            .iter()
+            .filter_map(|service| self.period(date, *service))
+            .collect()
+    }
+
+    /// The meal being served at `at`, if any
+    pub fn serving_at(&self, location_id: &str, at: DateTime<Utc>) -> Option<ServicePeriod> {
+        let date = self.date_at(at);
+        // yesterday's late night may still be going
+        let yesterday = date.pred_opt().map(|x| self.on(location_id, x));
+        yesterday
+            .into_iter()
+            .flatten()
+            .chain(self.on(location_id, date))
+            .find(|period| period.opens_at <= at && at < period.closes_at)
+    }
+
+    fn period(&self, date: NaiveDate, service: Service) -> Option<ServicePeriod> {
+        let close_date = if service.close <= service.open {
+            date + Duration::days(1)
+        } else {
+            date
+        };
+        // times skipped by daylight saving time don't exist, so the meal is left out
+        let local = |date: NaiveDate, time| {
+            date.and_time(time)
+                .and_local_timezone(self.timezone)
+                .earliest()
+                .map(|x| x.fixed_offset())
+        };
+        Some(ServicePeriod {
+            meal_type: service.meal,
+            opens_at: local(date, service.open)?,
+            closes_at: local(close_date, service.close)?,
+        })
+    }
+}
+
+impl<'a> OpenLocation<'a> {
+    /// The locations serving a meal at `at`, with their menus for it. The views share
+    /// `locations` rather than copying each location.
+    pub fn at(hours: &Hours, locations: &Arc<Locations<'a>>, at: DateTime<Utc>) -> Vec<Self> {
+        locations
+            .iter()
+            .enumerate()
+            .filter_map(|(index, location)| {
+                let service = hours.serving_at(location.id(), at)?;
+                let date = service.opens_at.date_naive();
+                let meal = location
+                    .daily_menus()
+                    .find(|menu| menu.date() == date)
+                    .and_then(|menu| {
+                        menu.all_meals()
+                            .iter()
+                            .find(|meal| meal.meal_type == service.meal_type)
+                    })
+                    .cloned();
+                Some(OpenLocation {
+                    location: LocationView::shared(Arc::clone(locations), index),
+                    service,
+                    meal,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::{examples, scrape};
+
+    fn at(hours: &Hours, date: NaiveDate, time: &str) -> DateTime<Utc> {
+        date.and_time(time.parse().unwrap())
+            .and_local_timezone(hours.timezone)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_bundled() {
+        let hours = HOURS.bundled().unwrap();
+        assert_eq!(hours.timezone, chrono_tz::America::Los_Angeles);
+        // a friday
+        let date = NaiveDate::from_ymd_opt(2024, 4, 5).unwrap();
+        let periods = hours.on("40", date);
+        assert_eq!(
+            periods.iter().map(|x| x.meal_type).collect::<Vec<_>>(),
+            [
+                MealType::Breakfast,
+                MealType::Lunch,
+                MealType::Dinner,
+                MealType::LateNight
+            ]
+        );
+        assert_eq!(
+            periods[0].opens_at.to_rfc3339(),
+            "2024-04-05T07:00:00-07:00"
+        );
+        assert!(hours.on("unknown", date).is_empty());
+        // weekends close the cafes
+        assert!(hours.on("23", date + Duration::days(1)).is_empty());
+        // holidays close everything without a date override
+        let thanksgiving = NaiveDate::from_ymd_opt(2024, 11, 28).unwrap();
+        assert!(hours.on("05", thanksgiving).is_empty());
+        assert_eq!(hours.on("40", thanksgiving).len(), 1);
+
+        let last = NaiveDate::from_ymd_opt(2025, 7, 4).unwrap();
+        assert_eq!(hours.holidays_ended(at(&hours, last, "12:00")), None);
+        assert_eq!(
+            hours.holidays_ended(at(&hours, last + Duration::days(1), "00:00")),
+            Some(last)
+        );
+    }
+
+    #[test]
+    fn test_serving_at() {
+        let hours = HOURS.bundled().unwrap();
+        // a thursday
+        let date = NaiveDate::from_ymd_opt(2024, 4, 4).unwrap();
+        let serving = |time| {
+            hours
+                .serving_at("20", at(&hours, date, time))
+                .map(|x| x.meal_type)
+        };
+        assert_eq!(serving("06:59"), None);
+        assert_eq!(serving("07:00"), Some(MealType::Breakfast));
+        assert_eq!(serving("11:00"), None);
+        assert_eq!(serving("12:00"), Some(MealType::Lunch));
+        assert_eq!(serving("23:30"), Some(MealType::BananaJoes));
+        // past midnight is still thursday's late night
+        let friday = date + Duration::days(1);
+        let service = hours.serving_at("20", at(&hours, friday, "00:30")).unwrap();
+        assert_eq!(service.meal_type, MealType::BananaJoes);
+        assert_eq!(service.opens_at.date_naive(), date);
+        assert_eq!(hours.serving_at("20", at(&hours, friday, "01:00")), None);
+    }
+
+    #[test]
+    fn test_override() {
+        let hours = HOURS
+            .with_contents(
+                r#"
+                [holidays]
+                2024-04-05 = "Spring Closure"
+
+                [locations.40]
+                daily = [{ meal = "AllDay", open = "09:00", close = "21:00" }]
+                dates.2024-04-05 = [{ meal = "AllDay", open = "10:00", close = "14:00" }]
+                "#,
+            )
+            .unwrap();
+        let date = NaiveDate::from_ymd_opt(2024, 4, 5).unwrap();
+        assert!(hours.on("05", date).is_empty());
+        let periods = hours.on("40", date);
+        assert_eq!(periods.len(), 1);
+        assert_eq!(periods[0].meal_type, MealType::AllDay);
+        assert_eq!(
+            periods[0].opens_at.to_rfc3339(),
+            "2024-04-05T10:00:00-07:00"
+        );
+        // the whole location is replaced, including its weekend hours
+        assert_eq!(hours.on("40", date + Duration::days(1)).len(), 1);
+        // the other locations keep their bundled hours
+        assert_eq!(hours.on("05", date - Duration::days(1)).len(), 4);
+
+        assert!(HOURS.with_contents("[locations.40]\nnightly = []").is_err());
+        assert!(HOURS.with_contents("timezone = \"Mars/Olympus\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_open_at() {
+        let hours = HOURS.bundled().unwrap();
+        let date = NaiveDate::from_ymd_opt(2024, 4, 5).unwrap();
+        let locations = Arc::new(scrape(&examples(), &[date], None).await.unwrap());
+
+        let open = OpenLocation::at(&hours, &locations, at(&hours, date, "18:00"));
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].location.id(), "40");
+        // the view points into the locations instead of copying the location
+        assert_eq!(Arc::strong_count(&locations), 2);
+        assert_eq!(open[0].service.meal_type, MealType::Dinner);
+        let meal = open[0].meal.as_ref().unwrap();
+        assert_eq!(meal.meal_type, MealType::Dinner);
+        assert!(!meal.sections.is_empty());
+
+        assert!(OpenLocation::at(&hours, &locations, at(&hours, date, "04:00")).is_empty());
+        // the hours say it's open, but the menu of the day wasn't fetched
+        let open = OpenLocation::at(
+            &hours,
+            &locations,
+            at(&hours, date - Duration::days(1), "08:00"),
+        );
+        assert_eq!(open.len(), 1);
+        assert!(open[0].meal.is_none());
+    }
+}
@@ -0,0 +1,88 @@
+//! Fetches and parses the menus of the UC Santa Cruz dining halls and markets.
+//!
+//! The menus come from the `FoodPro` site of UC Santa Cruz Dining,
+//! <https://nutrition.sa.ucsc.edu/>.
+//!
+//! - [`fetch`] gets the pages, with rate limiting, retries and a circuit breaker, and can record
+//!   them to a directory and replay them later, ex. for tests
+//! - [`parse`] turns the pages into the menu model: [`parse::Locations`] has a
+//!   [`parse::Location`] for each dining hall, which has a [`parse::DailyMenu`] for each day, made
+//!   up of meals, sections and [`parse::FoodItem`]s
+//!
+//! ```no_run
+//! use chrono::NaiveDate;
+//! use ucsc_menu::fetch::{self, Fetcher};
+//!
+//! # async fn run() -> Result<(), ucsc_menu::Error> {
+//! let fetcher = Fetcher::live();
+//! let date = NaiveDate::from_ymd_opt(2024, 4, 5).unwrap();
+//! let locations = fetch::scrape(&fetcher, &[date], Some("40")).await?;
+//! for item in locations.food_items() {
+//!     println!("{} ({})", item.name(), item.get_allergen_mask());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Features
+//!
+//! - `graphql` implements the juniper GraphQL traits for the menu model
+//! - `firestore` lets the server keep its cache in Firestore
+//! - `server` is everything else the `ucsc_menu` binary needs, and turns on `graphql`
+//! - `otlp` lets the server export traces to an OpenTelemetry collector
+//!
+//! The default features build the binary. Depend on this crate with `default-features = false`
+//! to only fetch and parse menus.
+
+#![deny(unused_crate_dependencies)]
+#![warn(missing_docs)]
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+// the API is documented by example rather than by listing every error and panic
+#![allow(
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::must_use_candidate,
+    clippy::return_self_not_must_use
+)]
+
+pub mod error;
+pub mod fetch;
+pub mod parse;
+
+#[cfg(feature = "server")]
+mod admin;
+#[cfg(feature = "server")]
+mod archive;
+#[cfg(feature = "server")]
+mod bundled;
+#[cfg(feature = "server")]
+mod cache;
+#[cfg(feature = "server")]
+mod cli;
+#[cfg(feature = "server")]
+mod config;
+#[cfg(feature = "server")]
+mod hours;
+#[cfg(feature = "server")]
+mod location_view;
+#[cfg(feature = "server")]
+mod metrics;
+#[cfg(feature = "server")]
+mod places;
+#[cfg(feature = "server")]
+mod scheduler;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "server")]
+mod telemetry;
+#[cfg(feature = "server")]
+mod transpose;
+
+pub use error::Error;
+
+#[cfg(test)]
+use tokio_scoped as _; // only the cache tests use it
+
+// the allocator of the binary, see main.rs
+#[cfg(all(target_env = "musl", target_pointer_width = "64"))]
+use mimalloc as _;
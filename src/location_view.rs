@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use juniper::graphql_object;
+
+use crate::{
+    hours::HOURS,
+    parse::{
+        DailyMenuCow, DateRange, DietaryProfile, Location, LocationCow, Locations, Place,
+        ServicePeriod,
+    },
+    places::PLACES,
+};
+
+/// The `Locations` of the menu model, with each location resolved as a `LocationView`
+#[derive(Debug, Clone)]
+pub struct LocationsView<'a>(pub Locations<'a>);
+
+#[graphql_object(name = "Locations")]
+impl<'a> LocationsView<'a> {
+    /// With `dietaryProfile`, the menus of the locations only have the food items fitting it,
+    /// and the sections and meals left empty are removed. Menus looked up in the archive by
+    /// `Location.menus` aren't filtered unless the profile is passed to it too.
+    #[allow(clippy::needless_pass_by_value)] // ignored because graphql doesn't support pass by reference
+    pub fn locations(
+        &self,
+        ids: Option<Vec<String>>,
+        dietary_profile: Option<DietaryProfile>,
+    ) -> Vec<LocationView<'_, 'a>> {
+        self.0
+            .locations(ids, dietary_profile)
+            .into_iter()
+            .map(|location| LocationView(Source::Cow(location)))
+            .collect()
+    }
+}
+
+/// A location of the menu model, along with what the server knows about it beyond the dining
+/// site. It replaces `Location` in the schema of the server.
+#[derive(Debug, Clone)]
+pub struct LocationView<'r, 'a>(Source<'r, 'a>);
+
+#[derive(Debug, Clone)]
+enum Source<'r, 'a> {
+    Cow(LocationCow<'r, 'a>),
+    /// The location at the index of the cached locations, which outlive the cache's lock this way
+    Shared(Arc<Locations<'a>>, usize),
+}
+
+impl<'a> LocationView<'_, 'a> {
+    /// The location at `index` of `locations`, without copying it
+    pub fn shared(locations: Arc<Locations<'a>>, index: usize) -> Self {
+        assert!(index < locations.iter().len(), "no location at {index}");
+        Self(Source::Shared(locations, index))
+    }
+
+    fn location(&self) -> &Location<'a> {
+        match &self.0 {
+            Source::Cow(location) => location,
+            Source::Shared(locations, index) => &locations.iter().as_slice()[*index],
+        }
+    }
+}
+
+impl<'a> From<Location<'a>> for LocationView<'_, 'a> {
+    fn from(location: Location<'a>) -> Self {
+        Self(Source::Cow(location.into()))
+    }
+}
+
+#[graphql_object(name = "Location")]
+impl<'a> LocationView<'_, 'a> {
+    /// The `locationNum` of the location on the dining site, ex. "40"
+    pub fn id(&self) -> &str {
+        self.location().id()
+    }
+    /// The name of the location on the dining site, ex. "College Nine/John R Lewis Dining Hall"
+    pub fn name(&self) -> &str {
+        self.location().name()
+    }
+    /// Dates older than the cached menus are looked up in the archive, if the range has a start.
+    /// The sections and meals without any food items fitting `dietaryProfile` are left out.
+    pub async fn menus(
+        &self,
+        date_range: Option<DateRange>,
+        dietary_profile: Option<DietaryProfile>,
+    ) -> Vec<DailyMenuCow<'_, 'a>> {
+        self.location().menus(date_range, dietary_profile).await
+    }
+    /// What kind of location it is and where, or null if it isn't in the curated places
+    pub fn place(&self) -> Option<Place> {
+        PLACES.get().get(self.location().id()).cloned()
+    }
+    /// The meals served on `date`, today if omitted. Empty if the location is closed or its
+    /// hours aren't known.
+    pub fn hours(&self, date: Option<NaiveDate>) -> Vec<ServicePeriod> {
+        let hours = HOURS.get();
+        hours.on(
+            self.location().id(),
+            date.unwrap_or_else(|| hours.date_at(Utc::now())),
+        )
+    }
+    /// Whether a meal is being served at `at`, now if omitted
+    pub fn is_open(&self, at: Option<DateTime<Utc>>) -> bool {
+        HOURS
+            .get()
+            .serving_at(self.location().id(), at.unwrap_or_else(Utc::now))
+            .is_some()
+    }
+}
@@ -0,0 +1,328 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex, time::Duration};
+
+use chrono::{DateTime, Utc};
+
+use crate::{fetch::BreakerState, parse};
+
+static METRICS: Metrics = Metrics::new();
+
+// upper bounds of the histogram buckets, in seconds
+const REFRESH_BUCKETS: &[f64] = &[1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+const FETCH_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+const GRAPHQL_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+/// The metrics of the process, which `/metrics` serves
+pub fn get() -> &'static Metrics {
+    &METRICS
+}
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    buckets: &'static [f64],
+    // not cumulative, the counts are summed up when rendering
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    const fn new(buckets: &'static [f64]) -> Self {
+        Self {
+            buckets,
+            counts: Vec::new(),
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: Duration) {
+        let secs = value.as_secs_f64();
+        self.counts.resize(self.buckets.len(), 0);
+        if let Some(i) = self.buckets.iter().position(|x| secs <= *x) {
+            self.counts[i] += 1;
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (i, le) in self.buckets.iter().enumerate() {
+            cumulative += self.counts.get(i).copied().unwrap_or_default();
+            let _ = writeln!(out, "{name}_bucket{{{labels}le=\"{le}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{labels}le=\"+Inf\"}} {}", self.count);
+        let labels = match labels.trim_end_matches(',') {
+            "" => String::new(),
+            labels => format!("{{{labels}}}"),
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{labels} {}", self.count);
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    refresh_duration: Histogram,
+    last_success: Option<DateTime<Utc>>,
+    fetch_latency: BTreeMap<String, Histogram>,
+    fetch_errors: BTreeMap<String, u64>,
+    parse_errors: BTreeMap<&'static str, u64>,
+    cache_bytes_compressed: Option<usize>,
+    cache_bytes_uncompressed: Option<usize>,
+    graphql_latency: BTreeMap<&'static str, Histogram>,
+    breaker: BreakerState,
+}
+
+/// Counters of the refreshes, the requests to the dining site and the GraphQL requests,
+/// rendered in the Prometheus text format
+#[derive(Debug)]
+pub struct Metrics(Mutex<Inner>);
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Self(Mutex::new(Inner {
+            refresh_duration: Histogram::new(REFRESH_BUCKETS),
+            last_success: None,
+            fetch_latency: BTreeMap::new(),
+            fetch_errors: BTreeMap::new(),
+            parse_errors: BTreeMap::new(),
+            cache_bytes_compressed: None,
+            cache_bytes_uncompressed: None,
+            graphql_latency: BTreeMap::new(),
+            breaker: BreakerState::Closed {
+                consecutive_failures: 0,
+            },
+        }))
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut Inner) -> T) -> T {
+        f(&mut self.0.lock().expect("lock should not be poisoned"))
+    }
+
+    /// A refresh which succeeded, and how long it took
+    pub fn refreshed(&self, took: Duration) {
+        self.with(|x| {
+            x.refresh_duration.observe(took);
+            x.last_success = Some(Utc::now());
+        });
+    }
+
+    /// A page of `location_id` which was requested, with retries, and whether it was served
+    pub fn fetched(&self, location_id: &str, took: Duration, succeeded: bool) {
+        self.with(|x| {
+            x.fetch_latency
+                .entry(location_id.to_string())
+                .or_insert_with(|| Histogram::new(FETCH_BUCKETS))
+                .observe(took);
+            if !succeeded {
+                *x.fetch_errors.entry(location_id.to_string()).or_default() += 1;
+            }
+        });
+    }
+
+    /// The state of the upstream circuit breaker after a request
+    pub fn breaker(&self, state: BreakerState) {
+        self.with(|x| x.breaker = state);
+    }
+
+    pub fn parse_failed(&self, e: &parse::Error) {
+        self.with(|x| *x.parse_errors.entry(e.kind()).or_default() += 1);
+    }
+
+    /// The size of the cache as it was last loaded or saved
+    pub fn cache_size(&self, compressed: usize, uncompressed: usize) {
+        self.with(|x| {
+            x.cache_bytes_compressed = Some(compressed);
+            x.cache_bytes_uncompressed = Some(uncompressed);
+        });
+    }
+
+    pub fn graphql_request(&self, took: Duration, succeeded: bool) {
+        let outcome = if succeeded { "ok" } else { "error" };
+        self.with(|x| {
+            x.graphql_latency
+                .entry(outcome)
+                .or_insert_with(|| Histogram::new(GRAPHQL_BUCKETS))
+                .observe(took);
+        });
+    }
+
+    pub fn render(&self) -> String {
+        let x = self.with(|x| Inner {
+            refresh_duration: x.refresh_duration.clone(),
+            fetch_latency: x.fetch_latency.clone(),
+            fetch_errors: x.fetch_errors.clone(),
+            parse_errors: x.parse_errors.clone(),
+            graphql_latency: x.graphql_latency.clone(),
+            ..*x
+        });
+        let mut out = String::new();
+        let header = |out: &mut String, name: &str, kind: &str, help: &str| {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+        };
+
+        let name = "ucsc_menu_refresh_duration_seconds";
+        header(
+            &mut out,
+            name,
+            "histogram",
+            "How long successful refreshes took.",
+        );
+        x.refresh_duration.render(&mut out, name, "");
+
+        let name = "ucsc_menu_last_successful_refresh_timestamp_seconds";
+        header(
+            &mut out,
+            name,
+            "gauge",
+            "When the last successful refresh finished, as a unix timestamp.",
+        );
+        if let Some(at) = x.last_success {
+            let _ = writeln!(out, "{name} {}", at.timestamp());
+        }
+
+        let name = "ucsc_menu_fetch_duration_seconds";
+        header(
+            &mut out,
+            name,
+            "histogram",
+            "How long pages of each location took to fetch, including retries.",
+        );
+        for (id, histogram) in &x.fetch_latency {
+            histogram.render(&mut out, name, &format!("location=\"{}\",", escape(id)));
+        }
+
+        let name = "ucsc_menu_fetch_errors_total";
+        header(
+            &mut out,
+            name,
+            "counter",
+            "Pages of each location which failed to fetch.",
+        );
+        for (id, count) in &x.fetch_errors {
+            let _ = writeln!(out, "{name}{{location=\"{}\"}} {count}", escape(id));
+        }
+
+        let name = "ucsc_menu_parse_errors_total";
+        header(
+            &mut out,
+            name,
+            "counter",
+            "Pages which failed to parse, by error.",
+        );
+        for (kind, count) in &x.parse_errors {
+            let _ = writeln!(out, "{name}{{kind=\"{kind}\"}} {count}");
+        }
+
+        let sizes = [
+            ("compressed", x.cache_bytes_compressed),
+            ("uncompressed", x.cache_bytes_uncompressed),
+        ];
+        let name = "ucsc_menu_cache_size_bytes";
+        header(
+            &mut out,
+            name,
+            "gauge",
+            "The size of the cache as it was last loaded or saved.",
+        );
+        for (encoding, size) in sizes {
+            if let Some(size) = size {
+                let _ = writeln!(out, "{name}{{encoding=\"{encoding}\"}} {size}");
+            }
+        }
+
+        let name = "ucsc_menu_graphql_request_duration_seconds";
+        header(
+            &mut out,
+            name,
+            "histogram",
+            "How long GraphQL requests took, by whether they had errors.",
+        );
+        for (outcome, histogram) in &x.graphql_latency {
+            histogram.render(&mut out, name, &format!("outcome=\"{outcome}\","));
+        }
+
+        let name = "ucsc_menu_upstream_circuit_breaker_state";
+        header(
+            &mut out,
+            name,
+            "gauge",
+            "Whether requests to the dining site are paused, 1 for the current state.",
+        );
+        render_breaker(&mut out, name, x.breaker);
+        out
+    }
+}
+
+// one series per state, set to 1 for the current one
+fn render_breaker(out: &mut String, name: &str, breaker: BreakerState) {
+    let current = match breaker {
+        BreakerState::Closed { .. } => "closed",
+        BreakerState::Open { .. } => "open",
+        BreakerState::HalfOpen { .. } => "half_open",
+    };
+    for state in ["closed", "open", "half_open"] {
+        let _ = writeln!(
+            out,
+            "{name}{{state=\"{state}\"}} {}",
+            u8::from(state == current)
+        );
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.refreshed(Duration::from_secs(3));
+        metrics.fetched("40", Duration::from_millis(200), true);
+        metrics.fetched("40", Duration::from_mins(1), false);
+        metrics.parse_failed(&parse::Error::html_parse_error("no menu"));
+        metrics.cache_size(100, 800);
+        metrics.graphql_request(Duration::from_millis(20), true);
+        metrics.breaker(BreakerState::Open {
+            until: std::time::Instant::now(),
+        });
+        let rendered = metrics.render();
+        for line in [
+            "# TYPE ucsc_menu_refresh_duration_seconds histogram",
+            r#"ucsc_menu_refresh_duration_seconds_bucket{le="2.5"} 0"#,
+            r#"ucsc_menu_refresh_duration_seconds_bucket{le="5"} 1"#,
+            "ucsc_menu_refresh_duration_seconds_count 1",
+            // the slow fetch is only in the +Inf bucket
+            r#"ucsc_menu_fetch_duration_seconds_bucket{location="40",le="0.25"} 1"#,
+            r#"ucsc_menu_fetch_duration_seconds_bucket{location="40",le="30"} 1"#,
+            r#"ucsc_menu_fetch_duration_seconds_bucket{location="40",le="+Inf"} 2"#,
+            r#"ucsc_menu_fetch_duration_seconds_sum{location="40"} 60.2"#,
+            r#"ucsc_menu_fetch_errors_total{location="40"} 1"#,
+            r#"ucsc_menu_parse_errors_total{kind="HtmlParse"} 1"#,
+            r#"ucsc_menu_cache_size_bytes{encoding="uncompressed"} 800"#,
+            r#"ucsc_menu_graphql_request_duration_seconds_count{outcome="ok"} 1"#,
+            r#"ucsc_menu_upstream_circuit_breaker_state{state="closed"} 0"#,
+            r#"ucsc_menu_upstream_circuit_breaker_state{state="open"} 1"#,
+        ] {
+            assert!(
+                rendered.lines().any(|x| x == line),
+                "missing {line:?} in\n{rendered}"
+            );
+        }
+        assert!(rendered.contains("ucsc_menu_last_successful_refresh_timestamp_seconds "));
+    }
+}
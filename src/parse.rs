@@ -1,5 +1,14 @@
+//! The menu model, parsed from the pages of the dining site.
+//!
+//! Each page has a type with a `from_html_element` constructor: [`Locations`] for the locations page, [`DailyMenu`] for a
+//! short menu, [`LongMenu`] for a long menu and [`Label`] for a nutrition label.
+
+/// What changed between two refreshes of the menus
+pub mod changes;
 mod error;
 mod menu_page;
+mod object_cow;
+pub use changes::{ChangeSet, ItemAppearance};
 pub use error::Error;
 mod location_page;
 mod remove_excess_whitespace;
@@ -7,5 +16,15 @@ mod static_selector;
 mod text_from_selection;
 
 pub use location_page::LocationMeta;
-pub use location_page::Locations;
-pub use remove_excess_whitespace::remove_excess_whitespace;
+pub use location_page::{
+    Coordinates, DateRange, Location, LocationKind, Locations, Place, RefreshScope, ServicePeriod,
+};
+pub use menu_page::{
+    AllergenFilter, AllergenFlags, Allergens, DailyMenu, DietaryPreset, DietaryProfile,
+    DietaryRule, FoodItem, Ingredient, Label, LongMenu, Meal, MealType, Nutrient, NutritionFacts,
+    Section,
+};
+pub use object_cow::{DailyMenuCow, LocationCow, ObjectCow};
+pub(crate) use remove_excess_whitespace::remove_excess_whitespace;
+// the pages are parsed with scraper, so its types are part of the API
+pub use scraper::{ElementRef, Html};
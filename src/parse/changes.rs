@@ -0,0 +1,317 @@
+use chrono::{DateTime, NaiveDate, Utc};
+#[cfg(feature = "graphql")]
+use juniper::{GraphQLEnum, GraphQLObject};
+
+use super::menu_page::{Allergens, DailyMenu, FoodItem, MealType};
+
+/// Everything that changed in one refresh of the menus
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLObject))]
+pub struct ChangeSet {
+    /// When the refresh finished
+    pub refreshed_at: DateTime<Utc>,
+    /// The menus which were added, removed or modified
+    pub menus: Vec<MenuChange>,
+}
+
+impl ChangeSet {
+    /// Only the changes to the menus of the given locations
+    pub fn for_locations(&self, location_ids: &[String]) -> Self {
+        Self {
+            refreshed_at: self.refreshed_at,
+            menus: self
+                .menus
+                .iter()
+                .filter(|x| location_ids.contains(&x.location_id))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// How a menu changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLEnum))]
+pub enum MenuChangeKind {
+    /// The menu wasn't posted before
+    Added,
+    /// The menu was taken down
+    Removed,
+    /// Some items of the menu changed
+    Modified,
+}
+
+/// The menu of one location on one day which changed
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLObject))]
+pub struct MenuChange {
+    /// The `locationNum` of the location
+    pub location_id: String,
+    /// The day of the menu
+    pub date: NaiveDate,
+    /// How the menu changed
+    pub kind: MenuChangeKind,
+    /// The items of a modified menu which changed. Empty for added and removed menus, and for
+    /// menus where only details like portions or labels changed.
+    pub items: Vec<ItemChange>,
+}
+
+impl MenuChange {
+    /// A menu which wasn't posted before
+    pub fn added(location_id: &str, date: NaiveDate) -> Self {
+        Self::new(location_id, date, MenuChangeKind::Added, Vec::new())
+    }
+
+    /// A menu which was taken down
+    pub fn removed(location_id: &str, date: NaiveDate) -> Self {
+        Self::new(location_id, date, MenuChangeKind::Removed, Vec::new())
+    }
+
+    /// A menu whose `items` changed
+    pub fn modified(location_id: &str, date: NaiveDate, items: Vec<ItemChange>) -> Self {
+        Self::new(location_id, date, MenuChangeKind::Modified, items)
+    }
+
+    fn new(
+        location_id: &str,
+        date: NaiveDate,
+        kind: MenuChangeKind,
+        items: Vec<ItemChange>,
+    ) -> Self {
+        Self {
+            location_id: location_id.to_string(),
+            date,
+            kind,
+            items,
+        }
+    }
+}
+
+/// How an item of a menu changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLEnum))]
+pub enum ItemChangeKind {
+    /// The item is new to the meal
+    Added,
+    /// The item isn't served at the meal anymore
+    Removed,
+    /// The name of the item changed
+    Renamed,
+    /// The allergens of the item changed
+    AllergensChanged,
+}
+
+/// An item of a modified menu which changed
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLObject))]
+pub struct ItemChange {
+    /// How the item changed
+    pub kind: ItemChangeKind,
+    /// The meal the item is served at
+    pub meal_type: MealType,
+    /// The name of the item, after the change
+    pub name: String,
+    /// The recipe of the item, if the site links to its nutrition label
+    pub recipe_id: Option<String>,
+    /// The allergens of the item, after the change
+    pub allergens: Vec<Allergens>,
+    /// Only set for renamed items
+    pub previous_name: Option<String>,
+    /// Only set for items whose allergens changed
+    pub previous_allergens: Option<Vec<Allergens>>,
+}
+
+impl ItemChange {
+    fn new(kind: ItemChangeKind, meal_type: MealType, item: &FoodItem) -> Self {
+        Self {
+            kind,
+            meal_type,
+            name: item.name().to_string(),
+            recipe_id: item.recipe_id().map(str::to_string),
+            allergens: item.get_allergen_mask().into(),
+            previous_name: None,
+            previous_allergens: None,
+        }
+    }
+}
+
+/// A food item which showed up on a menu in a refresh
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "graphql", derive(GraphQLObject))]
+pub struct ItemAppearance<'a> {
+    /// The `locationNum` of the location whose menu the item is on
+    pub location_id: String,
+    /// The day of the menu
+    pub date: NaiveDate,
+    /// The meal the item is served at
+    pub meal_type: MealType,
+    /// The item itself
+    pub food_item: FoodItem<'a>,
+}
+
+impl ItemAppearance<'_> {
+    /// Whether the name of the item contains `name_contains`, ignoring case, and the item has
+    /// all of `allergens`
+    pub fn matches(&self, name_contains: Option<&str>, allergens: &[Allergens]) -> bool {
+        let name_matches = name_contains.is_none_or(|pat| {
+            self.food_item
+                .name()
+                .to_lowercase()
+                .contains(&pat.to_lowercase())
+        });
+        let item_allergens = self.food_item.allergens();
+        name_matches && allergens.iter().all(|x| item_allergens.contains(x))
+    }
+}
+
+/// The changes to the items of a menu between two copies of it. Items are matched within the same
+/// meal, by recipe id when both copies have one and by name otherwise.
+pub fn diff_menus(old: &DailyMenu, new: &DailyMenu) -> Vec<ItemChange> {
+    let mut meal_types: Vec<MealType> = Vec::new();
+    for meal in old.all_meals().iter().chain(new.all_meals()) {
+        if !meal_types.contains(&meal.meal_type) {
+            meal_types.push(meal.meal_type);
+        }
+    }
+
+    let mut changes = Vec::new();
+    for meal_type in meal_types {
+        let mut old_items = items_of_meal(old, meal_type);
+        for new_item in items_of_meal(new, meal_type) {
+            let Some(i) = old_items.iter().position(|x| same_item(x, new_item)) else {
+                changes.push(ItemChange::new(ItemChangeKind::Added, meal_type, new_item));
+                continue;
+            };
+            let old_item = old_items.remove(i);
+            if old_item.name() != new_item.name() {
+                changes.push(ItemChange {
+                    previous_name: Some(old_item.name().to_string()),
+                    ..ItemChange::new(ItemChangeKind::Renamed, meal_type, new_item)
+                });
+            }
+            if old_item.get_allergen_mask() != new_item.get_allergen_mask() {
+                changes.push(ItemChange {
+                    previous_allergens: Some(old_item.get_allergen_mask().into()),
+                    ..ItemChange::new(ItemChangeKind::AllergensChanged, meal_type, new_item)
+                });
+            }
+        }
+        changes.extend(
+            old_items
+                .into_iter()
+                .map(|old_item| ItemChange::new(ItemChangeKind::Removed, meal_type, old_item)),
+        );
+    }
+    changes
+}
+
+fn items_of_meal<'b, 'a>(menu: &'b DailyMenu<'a>, meal_type: MealType) -> Vec<&'b FoodItem<'a>> {
+    menu.all_meals()
+        .iter()
+        .filter(|meal| meal.meal_type == meal_type)
+        .flat_map(|meal| meal.sections.iter())
+        .flat_map(|section| section.food_items.iter())
+        .collect()
+}
+
+// unlike `FoodItem`'s `PartialEq`, this ignores allergens so that changes to them can be reported
+fn same_item(a: &FoodItem, b: &FoodItem) -> bool {
+    match (a.recipe_id(), b.recipe_id()) {
+        (Some(a), Some(b)) => a == b,
+        _ => a.name() == b.name(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::fs;
+
+    fn menu() -> DailyMenu<'static> {
+        let html = scraper::Html::parse_document(
+            &fs::read_to_string("./src/parse/html_examples/daily_menu/dining_hall.html").unwrap(),
+        );
+        let menu = DailyMenu::from_html_element(html.root_element()).unwrap();
+        serde_json::from_str(&serde_json::to_string(&menu).unwrap()).unwrap()
+    }
+
+    // edits the items of the first section of the first meal as json
+    fn edit(menu: &DailyMenu, f: impl FnOnce(&mut Vec<Value>)) -> DailyMenu<'static> {
+        let mut json = serde_json::to_value(menu).unwrap();
+        let items = json["meals"][0]["sections"][0]["food_items"]
+            .as_array_mut()
+            .unwrap();
+        f(items);
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_no_changes() {
+        let menu = menu();
+        assert!(diff_menus(&menu, &menu.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_added_and_removed() {
+        let old = menu();
+        let first = old.all_meals()[0].sections[0].food_items[0]
+            .name()
+            .to_string();
+        let new = edit(&old, |items| {
+            items.remove(0);
+            items.push(serde_json::json!({"name": "Toast", "allergen_info": 0}));
+        });
+        let changes = diff_menus(&old, &new);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].kind, ItemChangeKind::Added);
+        assert_eq!(changes[0].name, "Toast");
+        assert_eq!(changes[1].kind, ItemChangeKind::Removed);
+        assert_eq!(changes[1].name, first);
+        assert_eq!(changes[1].meal_type, old.all_meals()[0].meal_type);
+    }
+
+    #[test]
+    fn test_renamed_and_allergens_changed() {
+        let old = edit(&menu(), |items| {
+            items[0]["recipe_id"] = "061002".into();
+        });
+        let new = edit(&old, |items| {
+            items[0]["name"] = "Scrambled Eggs".into();
+            items[1]["allergen_info"] = 0.into();
+        });
+        let changes = diff_menus(&old, &new);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].kind, ItemChangeKind::Renamed);
+        assert_eq!(changes[0].name, "Scrambled Eggs");
+        assert_eq!(changes[0].recipe_id.as_deref(), Some("061002"));
+        assert_eq!(
+            changes[0].previous_name.as_deref(),
+            Some(old.all_meals()[0].sections[0].food_items[0].name())
+        );
+        assert_eq!(changes[1].kind, ItemChangeKind::AllergensChanged);
+        assert!(changes[1].allergens.is_empty());
+        assert!(!changes[1].previous_allergens.as_ref().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_appearance_matches() {
+        let food_item = menu().all_meals()[0].sections[0].food_items[0].clone();
+        let allergens = food_item.allergens();
+        let appearance = ItemAppearance {
+            location_id: "40".to_string(),
+            date: NaiveDate::from_ymd_opt(2024, 4, 5).unwrap(),
+            meal_type: MealType::Breakfast,
+            food_item,
+        };
+        let name = appearance.food_item.name().to_uppercase();
+        assert!(appearance.matches(None, &[]));
+        assert!(appearance.matches(Some(&name), &allergens));
+        assert!(!appearance.matches(Some("not on the menu"), &[]));
+        let missing = [Allergens::Egg, Allergens::Vegan, Allergens::Pork]
+            .into_iter()
+            .find(|x| !allergens.contains(x))
+            .unwrap();
+        assert!(!appearance.matches(None, &[missing]));
+    }
+}
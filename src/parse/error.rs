@@ -2,12 +2,18 @@ use std::fmt::Display;
 
 use rusty_money::MoneyError;
 
+/// Why a page couldn't be parsed
 #[derive(Debug)]
 pub enum Error {
+    /// An element is missing from the page, or has an unexpected attribute
     HtmlParse(String),
+    /// The text of an element isn't what it should be, ex. a date or a number
     TextNodeParse(String),
+    /// A price isn't in dollars and cents
     PriceParse(String),
+    /// A page couldn't be fetched
     Http(String),
+    /// A bug in this crate rather than a problem with the page
     Internal(String),
 }
 
@@ -24,22 +30,38 @@ impl From<reqwest::Error> for Error {
 }
 
 impl Error {
+    /// An `HtmlParse` error
     pub fn html_parse_error(msg: &str) -> Self {
         Self::HtmlParse(msg.to_string())
     }
+    /// A `TextNodeParse` error
     pub fn text_node_parse_error(msg: &str) -> Self {
         Self::TextNodeParse(msg.to_string())
     }
+    /// A `PriceParse` error
     pub fn price_parse_error(msg: &str) -> Self {
         Self::PriceParse(msg.to_string())
     }
 
+    /// An `Http` error
     pub fn http_error(msg: &str) -> Self {
         Self::Http(msg.to_string())
     }
 }
 
 impl Error {
+    /// The name of the variant, ex. to count errors by
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::HtmlParse(_) => "HtmlParse",
+            Self::TextNodeParse(_) => "TextNodeParse",
+            Self::PriceParse(_) => "PriceParse",
+            Self::Http(_) => "Http",
+            Self::Internal(_) => "Internal",
+        }
+    }
+
+    /// An `Internal` error
     pub fn internal_error(msg: &str) -> Self {
         Self::Internal(msg.to_string())
     }
@@ -1,6 +1,10 @@
 mod location_data;
 mod location_meta;
 mod locations;
+mod place;
+mod service_period;
 
 pub use location_meta::LocationMeta;
-pub use locations::Locations;
+pub use locations::{DateRange, Location, Locations, RefreshScope};
+pub use place::{Coordinates, LocationKind, Place};
+pub use service_period::ServicePeriod;
@@ -1,61 +1,116 @@
+use chrono::NaiveDate;
 use scraper::Html;
 
+use super::RefreshScope;
+use crate::parse::changes::{diff_menus, MenuChange};
+
 use crate::parse::error::Result;
 use crate::parse::menu_page::DailyMenu;
-use crate::parse::Error;
-
-pub const NUM_MEALS: usize = 10;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone)]
 pub struct LocationData<'a> {
-    menus: [Option<DailyMenu<'a>>; NUM_MEALS], // keep track of up to 10 days of meals
+    // sorted by date, holds however many days the cache is configured to keep
+    #[serde(deserialize_with = "deserialize_menus")]
+    menus: Vec<DailyMenu<'a>>,
+}
+
+// caches saved when the menus were a fixed size array have nulls for the empty slots
+fn deserialize_menus<'de, 'a, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<DailyMenu<'a>>, D::Error> {
+    let menus: Vec<Option<DailyMenu<'a>>> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(menus.into_iter().flatten().collect())
 }
 
-const ARRAY_REPEAT_VALUE: std::option::Option<DailyMenu<'static>> = None;
 impl<'a> LocationData<'a> {
     pub const fn new() -> Self {
-        Self {
-            menus: [ARRAY_REPEAT_VALUE; NUM_MEALS],
-        }
+        Self { menus: Vec::new() }
     }
-    #[cfg(test)]
-    pub fn is_empty(&self) -> bool {
-        self.menus.iter().all(std::option::Option::is_none)
+    pub const fn is_empty(&self) -> bool {
+        self.menus.is_empty()
     }
 
+    #[cfg(test)]
     pub fn clear(&mut self) {
-        self.menus.iter_mut().for_each(|x| *x = None);
+        self.menus.clear();
     }
 
-    #[cfg(test)]
     pub fn menus_mut(&mut self) -> impl Iterator<Item = &mut DailyMenu<'a>> {
-        self.menus.iter_mut().filter_map(|x| x.as_mut())
+        self.menus.iter_mut()
     }
 
     pub fn menus(&self) -> impl Iterator<Item = &DailyMenu<'a>> {
-        self.menus.iter().filter_map(|x| x.as_ref())
+        self.menus.iter()
     }
 
-    #[cfg(unused)]
-    pub fn remove_meals_before(&mut self, date: chrono::NaiveDate) {
-        for meal in &mut self.menus {
-            if let Some(m) = meal {
-                if m.date() < date {
-                    *meal = None;
-                }
+    /// Replaces the menus which differ from the ones in `new`, returning what changed.
+    /// Only the menus on dates in `scope` are replaced or removed, the rest are kept.
+    /// Menus on the `failed` dates couldn't be fetched, so they are kept too.
+    pub fn update(
+        &mut self,
+        new: Self,
+        scope: &RefreshScope,
+        location_id: &str,
+        failed: &[NaiveDate],
+    ) -> Vec<MenuChange> {
+        let mut old = std::mem::take(&mut self.menus);
+        let mut menus = Vec::with_capacity(new.menus.len());
+        let mut changes = Vec::new();
+        for menu in new.menus {
+            let date = menu.date();
+            let Some(i) = old.iter().position(|x| x.date() == date) else {
+                changes.push(MenuChange::added(location_id, date));
+                menus.push(menu);
+                continue;
+            };
+            let old_menu = old.remove(i);
+            if old_menu.same_contents(&menu) {
+                menus.push(old_menu);
+            } else {
+                changes.push(MenuChange::modified(
+                    location_id,
+                    date,
+                    diff_menus(&old_menu, &menu),
+                ));
+                menus.push(menu);
             }
         }
+        for menu in old {
+            if !scope.includes_date(menu.date()) || failed.contains(&menu.date()) {
+                menus.push(menu);
+            } else {
+                changes.push(MenuChange::removed(location_id, menu.date()));
+            }
+        }
+        menus.sort();
+        self.menus = menus;
+        changes
     }
 
-    pub fn add_meal(&mut self, html: &'a Html) -> Result<()> {
-        let menu = DailyMenu::from_html_element(html.root_element())?;
+    /// Removes the menus on dates in `scope` and reports them, for when the location itself is gone
+    pub fn remove_in(&mut self, scope: &RefreshScope, location_id: &str) -> Vec<MenuChange> {
+        let (removed, kept) = std::mem::take(&mut self.menus)
+            .into_iter()
+            .partition(|x| scope.includes_date(x.date()));
+        self.menus = kept;
+        removed
+            .iter()
+            .map(|x| MenuChange::removed(location_id, x.date()))
+            .collect()
+    }
 
-        self.menus
-            .iter_mut()
-            .find(|x| x.is_none())
-            .ok_or_else(|| Error::internal_error("No empty slots for meal"))?
-            .replace(menu);
+    /// Drops the menus before `date` without reporting them
+    pub fn expire_before(&mut self, date: NaiveDate) {
+        self.menus.retain(|x| x.date() >= date);
+    }
+
+    pub fn retain_menus(&mut self, f: impl FnMut(&mut DailyMenu<'a>) -> bool) {
+        self.menus.retain_mut(f);
+    }
 
+    pub fn add_meal(&mut self, html: &'a Html) -> Result<()> {
+        let menu = DailyMenu::from_html_element(html.root_element())?;
+        self.menus.push(menu);
         self.menus.sort();
 
         Ok(())
@@ -87,4 +142,56 @@ mod tests {
         location_data.clear();
         assert!(location_data.is_empty());
     }
+
+    #[test]
+    fn test_update() {
+        use crate::parse::changes::MenuChangeKind;
+        let read = |name: &str| {
+            Html::parse_document(
+                &fs::read_to_string(format!("src/parse/html_examples/daily_menu/{name}.html"))
+                    .unwrap(),
+            )
+        };
+        let (fifth, ninth) = (read("dining_hall"), read("meals_on_date"));
+        let date = |day| NaiveDate::from_ymd_opt(2024, 4, day).unwrap();
+        let mut old = LocationData::new();
+        old.add_meal(&fifth).unwrap();
+        let mut new = LocationData::new();
+        new.add_meal(&fifth).unwrap();
+        new.add_meal(&ninth).unwrap();
+
+        let everything = RefreshScope::default();
+        let changes = old.update(new.clone(), &everything, "40", &[]);
+        assert_eq!(changes, vec![MenuChange::added("40", date(9))]);
+        assert_eq!(old, new);
+
+        // the menu of the 5th went missing while it was still in the window
+        let mut only_ninth = LocationData::new();
+        only_ninth.add_meal(&ninth).unwrap();
+        let changes = old
+            .clone()
+            .update(only_ninth.clone(), &everything, "40", &[]);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].kind, MenuChangeKind::Removed);
+        assert_eq!(changes[0].date, date(5));
+        // unless it only went missing because it failed to be fetched
+        let mut kept = old.clone();
+        assert!(kept
+            .update(only_ninth.clone(), &everything, "40", &[date(5)])
+            .is_empty());
+        assert_eq!(kept, old);
+        // or because it is outside of the refreshed dates
+        let mut kept = old.clone();
+        let from_ninth = RefreshScope {
+            start: Some(date(9)),
+            ..RefreshScope::default()
+        };
+        assert!(kept
+            .update(only_ninth.clone(), &from_ninth, "40", &[])
+            .is_empty());
+        assert_eq!(kept, old);
+        // and once the window has moved past it, it expired instead
+        old.expire_before(date(6));
+        assert!(old.update(only_ninth, &everything, "40", &[]).is_empty());
+    }
 }
@@ -1,6 +1,7 @@
 use crate::parse::Error;
 use crate::static_selector;
 use url::Url;
+/// The id, name and short menu url of a location, from its link on the locations page
 #[derive(Debug, Clone, PartialEq)]
 pub struct LocationMeta {
     name: String,
@@ -25,10 +26,12 @@ impl<'de> serde::Deserialize<'de> for LocationMeta {
 impl Eq for LocationMeta {}
 
 impl LocationMeta {
+    /// The short menu page of the location
     pub const fn url(&self) -> &Url {
         &self.url
     }
 
+    /// Reads the id and name from the query of a short menu url
     pub fn from_url(url: Url) -> Result<Self, Error> {
         let mut query_pairs = url.query_pairs();
 
@@ -55,16 +58,18 @@ impl LocationMeta {
         Ok(Self { name, id, url })
     }
 
-    pub(super) fn from_html_element(element: scraper::ElementRef) -> Result<Self, Error> {
+    /// `base` is the url of the locations page, which the location links are relative to
+    pub(super) fn from_html_element(
+        element: scraper::ElementRef,
+        base: &Url,
+    ) -> Result<Self, Error> {
         static_selector!(LOCATION_SELECTOR <- ".locations > a");
         let Some(location_element) = element.select(&LOCATION_SELECTOR).next() else {
             return Err(Error::html_parse_error("location name node not found"));
         };
 
-        // TODO: make static
-        let url = Url::parse("https://nutrition.sa.ucsc.edu").expect("base url should be valid!");
         let Ok(url) =
-            url.join(location_element.attr("href").ok_or_else(|| {
+            base.join(location_element.attr("href").ok_or_else(|| {
                 Error::html_parse_error("location <a> does not have a href attr")
             })?)
         else {
@@ -74,10 +79,12 @@ impl LocationMeta {
         Self::from_url(url)
     }
 
+    /// The `locationNum` of the location, ex. "40"
     pub fn id(&self) -> &str {
         &self.id
     }
 
+    /// The `locationName` of the location
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -86,13 +93,15 @@ impl LocationMeta {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fetch::DEFAULT_UPSTREAM;
     use std::fs;
 
     #[test]
     fn test_from_html_element() {
         let html = fs::read_to_string("./src/parse/html_examples/locations/location.html").unwrap();
         let document = scraper::Html::parse_document(&html);
-        let location = LocationMeta::from_html_element(document.root_element())
+        let base = Url::parse(DEFAULT_UPSTREAM).unwrap();
+        let location = LocationMeta::from_html_element(document.root_element(), &base)
             .expect("The example html should be valid");
         assert_eq!(location.name, "College Nine/John R. Lewis Dining Hall");
         assert_eq!(location.id, "40");
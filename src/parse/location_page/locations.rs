@@ -1,61 +1,124 @@
 use std::slice::{Iter, IterMut};
 
 use chrono::NaiveDate;
+#[cfg(feature = "graphql")]
 use juniper::{graphql_object, GraphQLInputObject};
 use scraper::Html;
+use url::Url;
 
-use crate::parse::menu_page::DailyMenu;
+#[cfg(feature = "server")]
+use crate::archive::Archive;
+use crate::parse::changes::{
+    ChangeSet, ItemAppearance, ItemChangeKind, MenuChange, MenuChangeKind,
+};
+use crate::parse::menu_page::{DailyMenu, DietaryProfile, FoodItem, MealType};
+use crate::parse::{DailyMenuCow, LocationCow};
 use crate::{parse::Error, static_selector};
 
 use super::location_meta::LocationMeta;
 
 use super::location_data::LocationData;
 
+/// A dining hall, market or cafe, and its menus by date
 #[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone)]
 pub struct Location<'a>(LocationData<'a>, LocationMeta);
 
-#[derive(GraphQLInputObject, Debug)]
+/// The dates from `start` to `end`, both included. Either end is open if omitted.
+#[derive(Debug)]
+#[cfg_attr(feature = "graphql", derive(GraphQLInputObject))]
 pub struct DateRange {
     start: Option<NaiveDate>,
     end: Option<NaiveDate>,
 }
 
-#[graphql_object]
+impl DateRange {
+    /// The dates from `start` to `end`, both included
+    pub const fn new(start: Option<NaiveDate>, end: Option<NaiveDate>) -> Self {
+        Self { start, end }
+    }
+
+    /// Whether `date` is in the range
+    pub fn includes(&self, date: NaiveDate) -> bool {
+        self.start.is_none_or(|start| date >= start) && self.end.is_none_or(|end| date <= end)
+    }
+}
+
+/// The locations and dates which a refresh covers, so that the menus outside of it are left
+/// alone. Everything is covered by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLInputObject))]
+// the same keys as the GraphQL input, ex. `{"locationId": "40"}`
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct RefreshScope {
+    /// Every location if omitted
+    pub location_id: Option<String>,
+    /// The first date refreshed, the start of the cached window if omitted
+    pub start: Option<NaiveDate>,
+    /// The last date refreshed, the end of the cached window if omitted
+    pub end: Option<NaiveDate>,
+}
+
+impl RefreshScope {
+    /// Whether the location with the id `id` is refreshed
+    pub fn includes_location(&self, id: &str) -> bool {
+        self.location_id.as_deref().is_none_or(|x| x == id)
+    }
+
+    /// Whether the menus on `date` are refreshed
+    pub fn includes_date(&self, date: NaiveDate) -> bool {
+        self.start.is_none_or(|start| date >= start) && self.end.is_none_or(|end| date <= end)
+    }
+}
+
+#[cfg_attr(feature = "graphql", graphql_object)]
 impl<'a> Location<'a> {
+    /// The `locationNum` of the location on the dining site, ex. "40"
     pub fn id(&self) -> &str {
         self.1.id()
     }
+    /// The name of the location on the dining site, ex. "College Nine/John R Lewis Dining Hall"
     pub fn name(&self) -> &str {
         self.1.name()
     }
-    #[allow(clippy::needless_pass_by_value)] // ignored because graphql doesn't support pass by reference
-    pub fn menus(&self, date_range: Option<DateRange>) -> Vec<&DailyMenu<'a>> {
-        if let Some(DateRange { start, end }) = date_range {
-            self.0
-                .menus()
-                .filter(|x| {
-                    let mut incl = true;
-                    incl &= start.map_or(true, |start_date| x.date() >= start_date);
-                    incl &= end.map_or(true, |end_date| x.date() <= end_date);
-                    incl
-                })
-                .collect()
-        } else {
-            self.0.menus().collect()
+    /// Dates older than the cached menus are looked up in the archive, if the range has a start.
+    /// The sections and meals without any food items fitting `dietaryProfile` are left out.
+    #[allow(clippy::needless_pass_by_value)]
+    // ignored because graphql doesn't support pass by reference
+    // the archive is only read with the `server` feature
+    #[cfg_attr(not(feature = "server"), allow(clippy::unused_async))]
+    pub async fn menus(
+        &self,
+        date_range: Option<DateRange>,
+        dietary_profile: Option<DietaryProfile>,
+    ) -> Vec<DailyMenuCow<'_, 'a>> {
+        #[cfg(feature = "server")]
+        let mut menus = self
+            .menus_with_archive(date_range.as_ref(), crate::archive::get())
+            .await;
+        #[cfg(not(feature = "server"))]
+        let mut menus = self.menus_in(date_range.as_ref());
+        // only the menus which are filtered are copied
+        if let Some(profile) = &dietary_profile {
+            for menu in &mut menus {
+                menu.to_mut().retain_diet(profile);
+            }
         }
+        menus
     }
 }
 
 impl<'a> Location<'a> {
-    pub fn new(location_meta: LocationMeta) -> Self {
+    /// A location without any menus yet
+    pub const fn new(location_meta: LocationMeta) -> Self {
         Self(LocationData::new(), location_meta)
     }
 
+    /// Replaces the menus with the ones in `htmls`
+    #[cfg(test)]
     pub fn add_meals<'b: 'a>(
         &mut self,
         htmls: impl Iterator<Item = &'b Html>,
     ) -> Result<(), Error> {
-        // TODO: instead of immediately clearing, diff the similar meals first
         self.clear();
         for html in htmls {
             self.0.add_meal(html)?;
@@ -63,42 +126,154 @@ impl<'a> Location<'a> {
         Ok(())
     }
 
+    /// Adds the menu of a single day, keeping the menus already added
+    pub fn add_meal<'b: 'a>(&mut self, html: &'b Html) -> Result<(), Error> {
+        self.0.add_meal(html)
+    }
+
+    fn menus_in(&self, date_range: Option<&DateRange>) -> Vec<DailyMenuCow<'_, 'a>> {
+        self.0
+            .menus()
+            .filter(|x| date_range.is_none_or(|range| range.includes(x.date())))
+            .map(DailyMenuCow::from)
+            .collect()
+    }
+
+    #[cfg(feature = "server")]
+    async fn menus_with_archive(
+        &self,
+        date_range: Option<&DateRange>,
+        archive: Option<&Archive>,
+    ) -> Vec<DailyMenuCow<'_, 'a>> {
+        let mut menus = self.menus_in(date_range);
+        // without a start the whole history would be returned, so the archive is skipped
+        let (Some(archive), Some(start)) = (archive, date_range.and_then(|x| x.start)) else {
+            return menus;
+        };
+        // the cached menus are the most up to date ones, so only the dates before them are looked
+        // up in the archive
+        let mut end = date_range.and_then(|x| x.end);
+        if let Some(first_cached) = self.0.menus().map(DailyMenu::date).min() {
+            if start >= first_cached {
+                return menus;
+            }
+            let before = first_cached.pred_opt().expect("start is before it");
+            end = Some(end.map_or(before, |end| end.min(before)));
+        }
+        if end.is_some_and(|end| end < start) {
+            return menus;
+        }
+        match archive.daily_menus(self.1.id(), start, end).await {
+            Ok(archived) => {
+                menus.extend(archived.into_iter().map(DailyMenuCow::from));
+                menus.sort_by_key(|x| x.date());
+            }
+            Err(e) => tracing::warn!("Failed to read archived menus of {}: {e}", self.1.id()),
+        }
+        menus
+    }
+
+    /// A copy with only the food items which fit `profile`, without the sections and meals left
+    /// empty
+    pub fn with_diet(&self, profile: &DietaryProfile) -> Self {
+        let mut location = self.clone();
+        for menu in location.0.menus_mut() {
+            menu.retain_diet(profile);
+        }
+        location
+    }
+
+    /// Where the menus of the location are on the dining site
     pub const fn metadata(&self) -> &LocationMeta {
         &self.1
     }
+
+    /// The menus which were added, by date
+    pub fn daily_menus(&self) -> impl Iterator<Item = &DailyMenu<'a>> {
+        self.0.menus()
+    }
+
+    /// The menu on `date`, if it was added
+    pub fn daily_menu_mut(&mut self, date: NaiveDate) -> Option<&mut DailyMenu<'a>> {
+        self.0.menus_mut().find(|menu| menu.date() == date)
+    }
+    /// Whether any menus were added
     #[cfg(test)]
-    pub fn hydrated(&self) -> bool {
+    pub const fn hydrated(&self) -> bool {
         !self.0.is_empty()
     }
 
+    /// Removes every menu
+    #[cfg(test)]
     pub fn clear(&mut self) {
         self.0.clear();
     }
 }
+
+/// Every location on the locations page. Parse the page with `from_html_element`, then add the
+/// menus of each location with `Location::add_meal`.
 #[derive(Debug, serde::Serialize, serde::Deserialize, Default, PartialEq, Eq, Clone)]
 pub struct Locations<'a> {
     locations: Vec<Location<'a>>,
 }
 
-#[graphql_object]
+#[cfg_attr(feature = "graphql", graphql_object)]
 impl<'a> Locations<'a> {
+    /// With `dietaryProfile`, the menus of the locations only have the food items fitting it,
+    /// and the sections and meals left empty are removed. Menus looked up in the archive by
+    /// `Location.menus` aren't filtered unless the profile is passed to it too.
     #[allow(clippy::needless_pass_by_value)] // ignored because graphql doesn't support pass by reference
-    pub fn locations(&self, ids: Option<Vec<String>>) -> Vec<&Location<'a>> {
-        ids.map_or_else(
-            || self.locations.iter().collect(),
-            |ids| {
-                let ids: Vec<&str> = ids.iter().map(std::string::String::as_str).collect();
-                self.locations
-                    .iter()
-                    .filter(|location| ids.contains(&location.1.id()))
-                    .collect()
-            },
-        )
+    pub fn locations(
+        &self,
+        ids: Option<Vec<String>>,
+        dietary_profile: Option<DietaryProfile>,
+    ) -> Vec<LocationCow<'_, 'a>> {
+        self.locations
+            .iter()
+            .filter(|location| {
+                ids.as_ref()
+                    .is_none_or(|ids| ids.iter().any(|id| id == location.1.id()))
+            })
+            // only the filtered locations are copied
+            .map(|location| {
+                dietary_profile.as_ref().map_or_else(
+                    || location.into(),
+                    |profile| location.with_diet(profile).into(),
+                )
+            })
+            .collect()
+    }
+}
+
+impl<'a, 'b> IntoIterator for &'b Locations<'a> {
+    type Item = &'b Location<'a>;
+    type IntoIter = Iter<'b, Location<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, 'b> IntoIterator for &'b mut Locations<'a> {
+    type Item = &'b mut Location<'a>;
+    type IntoIter = IterMut<'b, Location<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<'a> FromIterator<Location<'a>> for Locations<'a> {
+    fn from_iter<T: IntoIterator<Item = Location<'a>>>(iter: T) -> Self {
+        Self {
+            locations: iter.into_iter().collect(),
+        }
     }
 }
 
 impl<'a> Locations<'a> {
-    pub fn from_html_element(element: scraper::ElementRef) -> Result<Self, Error> {
+    /// `base` is the url the page was fetched from
+    pub fn from_html_element(element: scraper::ElementRef, base: &Url) -> Result<Self, Error> {
         static_selector!(LOCATION_CHOICES_SELECTOR <- "div#locationchoices");
         static_selector!(LOCATION_SELECTOR <- "li.locations");
 
@@ -111,63 +286,233 @@ impl<'a> Locations<'a> {
         let location_matches = choices.select(&LOCATION_SELECTOR);
         let mut locations = Vec::with_capacity(location_matches.size_hint().0);
         for location in location_matches {
-            let location_meta = LocationMeta::from_html_element(location)?;
+            let location_meta = LocationMeta::from_html_element(location, base)?;
             locations.push(Location::new(location_meta));
         }
 
         Ok(Self { locations })
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<Location<'a>> {
+    /// The locations, in the order of the locations page
+    pub fn iter_mut(&mut self) -> IterMut<'_, Location<'a>> {
         self.locations.iter_mut()
     }
 
-    pub fn iter(&self) -> Iter<Location<'a>> {
+    /// The locations, in the order of the locations page
+    pub fn iter(&self) -> Iter<'_, Location<'a>> {
         self.locations.iter()
     }
-    // might eventually be used for diffing
-    #[cfg(unused)]
-    pub fn add_meals<'b: 'a>(
-        &mut self,
-        htmls: impl Iterator<Item = &'b Html>,
-        location_meta: &LocationMeta,
-    ) -> Result<(), Error> {
-        let location = self
-            .locations
+
+    /// The food items on every menu of every location
+    pub fn food_items(&self) -> impl Iterator<Item = &FoodItem<'a>> {
+        self.locations
+            .iter()
+            .flat_map(|location| location.0.menus())
+            .flat_map(DailyMenu::food_items)
+    }
+
+    /// The food items on every menu of every location
+    pub fn food_items_mut(&mut self) -> impl Iterator<Item = &mut FoodItem<'a>> {
+        self.locations
             .iter_mut()
-            .find(|x| x.1 == *location_meta)
-            .ok_or_else(|| {
-                Error::Internal(format!(
-                    "Location with id {} is either already hydrated or does not exist. Clear all locations and try again.",
-                    location_meta.id()
-                ))
-            })?;
+            .flat_map(|location| location.0.menus_mut())
+            .flat_map(DailyMenu::food_items_mut)
+    }
 
-        location.add_meals(htmls)?;
+    /// Only keeps the locations which `f` returns true for
+    pub fn retain(&mut self, f: impl FnMut(&Location<'a>) -> bool) {
+        self.locations.retain(f);
+    }
 
-        Ok(())
+    /// Drops the menus before `date` without reporting them as removed, since they have only
+    /// fallen out of the window of menus which are fetched
+    pub fn expire_before(&mut self, date: NaiveDate) {
+        for location in &mut self.locations {
+            location.0.expire_before(date);
+        }
+    }
+
+    /// Only keeps the menus on `date` and the meals of `meal_type`, dropping the menus left
+    /// without meals when filtering by meal
+    pub fn retain_menus(&mut self, date: Option<NaiveDate>, meal_type: Option<MealType>) {
+        for location in &mut self.locations {
+            location.0.retain_menus(|menu| {
+                if date.is_some_and(|date| menu.date() != date) {
+                    return false;
+                }
+                meal_type.is_none_or(|meal_type| {
+                    menu.retain_meal(meal_type);
+                    !menu.all_meals().is_empty()
+                })
+            });
+        }
+    }
+
+    /// Merges in freshly fetched locations, replacing only the menus which changed.
+    /// Only the menus in `scope` are replaced, and `new` should only have the locations in it.
+    /// The menus of the `failed` locations and dates are kept as they were.
+    pub fn update(
+        &mut self,
+        new: Self,
+        scope: &RefreshScope,
+        failed: &[(String, NaiveDate)],
+    ) -> Vec<MenuChange> {
+        let mut old = std::mem::take(&mut self.locations);
+        let old_order: Vec<String> = old.iter().map(|x| x.1.id().to_string()).collect();
+        let mut changes = Vec::new();
+        for Location(new_data, meta) in new.locations {
+            let mut data = old
+                .iter()
+                .position(|x| x.1.id() == meta.id())
+                .map_or_else(LocationData::new, |i| old.remove(i).0);
+            let failed: Vec<NaiveDate> = failed
+                .iter()
+                .filter(|(id, _)| id == meta.id())
+                .map(|(_, date)| *date)
+                .collect();
+            changes.extend(data.update(new_data, scope, meta.id(), &failed));
+            self.locations.push(Location(data, meta));
+        }
+        for mut location in old {
+            if scope.includes_location(location.1.id()) {
+                // the location is gone from the site
+                changes.extend(location.0.remove_in(scope, location.1.id()));
+                if location.0.is_empty() {
+                    continue;
+                }
+            }
+            self.locations.push(location);
+        }
+        if scope.location_id.is_some() {
+            // a refresh of one location leaves the others where they were
+            self.locations.sort_by_key(|x| {
+                old_order
+                    .iter()
+                    .position(|id| id == x.1.id())
+                    .unwrap_or(usize::MAX)
+            });
+        }
+        changes
+    }
+
+    /// The items which showed up on the menus from `from` on in the refresh of `changes`, which
+    /// should be the latest refresh. That is every item of an added menu, along with the added
+    /// and renamed items of a modified one.
+    pub fn appeared_items(&self, changes: &ChangeSet, from: NaiveDate) -> Vec<ItemAppearance<'a>> {
+        let mut appeared = Vec::new();
+        for change in changes.menus.iter().filter(|x| x.date >= from) {
+            let Some(menu) = self
+                .locations
+                .iter()
+                .find(|x| x.1.id() == change.location_id)
+                .and_then(|x| x.daily_menus().find(|menu| menu.date() == change.date))
+            else {
+                continue;
+            };
+            for meal in menu.all_meals() {
+                let items = meal.sections.iter().flat_map(|x| x.food_items.iter());
+                for food_item in items {
+                    let is_new = match change.kind {
+                        MenuChangeKind::Added => true,
+                        MenuChangeKind::Removed => false,
+                        MenuChangeKind::Modified => change.items.iter().any(|x| {
+                            matches!(x.kind, ItemChangeKind::Added | ItemChangeKind::Renamed)
+                                && x.meal_type == meal.meal_type
+                                && x.name == food_item.name()
+                        }),
+                    };
+                    if is_new {
+                        appeared.push(ItemAppearance {
+                            location_id: change.location_id.clone(),
+                            date: change.date,
+                            meal_type: meal.meal_type,
+                            food_item: food_item.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        appeared
     }
 }
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "graphql")]
     use juniper::{EmptyMutation, EmptySubscription, RootNode, Variables};
-    use url::Url;
 
     use super::*;
-    use std::{collections::HashMap, fs};
+    use crate::fetch::DEFAULT_UPSTREAM;
+    use std::fs;
+
+    fn upstream() -> Url {
+        Url::parse(DEFAULT_UPSTREAM).unwrap()
+    }
 
     #[test]
     fn test_from_html_element() {
         let html =
             fs::read_to_string("./src/parse/html_examples/locations/locations.html").unwrap();
         let document = scraper::Html::parse_document(&html);
-        let locations = Locations::from_html_element(document.root_element())
+        let locations = Locations::from_html_element(document.root_element(), &upstream())
             .expect("The example html should be valid");
         assert_eq!(locations.locations.len(), 14);
         println!("{:#?}", locations.locations);
     }
 
+    #[test]
+    fn test_locations_borrowed() {
+        let html =
+            fs::read_to_string("./src/parse/html_examples/locations/locations.html").unwrap();
+        let document = scraper::Html::parse_document(&html);
+        let locations = Locations::from_html_element(document.root_element(), &upstream())
+            .expect("The example html should be valid");
+        let first = &locations.locations[0];
+
+        let all = locations.locations(None, None);
+        assert_eq!(all.len(), 14);
+        assert!(std::ptr::eq(std::ptr::from_ref::<Location>(&all[0]), first));
+        let filtered = locations.locations(
+            Some(vec![first.id().to_string()]),
+            Some(DietaryProfile::default()),
+        );
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(*filtered[0], *first);
+        assert!(!std::ptr::eq(
+            std::ptr::from_ref::<Location>(&filtered[0]),
+            first
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_menus_borrowed() {
+        let html = Html::parse_document(
+            &fs::read_to_string("src/parse/html_examples/daily_menu/dining_hall.html").unwrap(),
+        );
+        let url: Url =
+            "https://nutrition.sa.ucsc.edu/shortmenu.aspx?locationNum=40&locationName=Nine"
+                .parse()
+                .unwrap();
+        let mut location = Location::new(LocationMeta::from_url(url).unwrap());
+        let v = [html];
+        location.add_meals(v.iter()).unwrap();
+        let cached = location.daily_menus().next().unwrap();
+
+        let menus = location.menus(None, None).await;
+        assert_eq!(menus.len(), 1);
+        assert!(std::ptr::eq(
+            std::ptr::from_ref::<DailyMenu>(&menus[0]),
+            cached
+        ));
+        let filtered = location.menus(None, Some(DietaryProfile::default())).await;
+        assert_eq!(*filtered[0], *cached);
+        assert!(!std::ptr::eq(
+            std::ptr::from_ref::<DailyMenu>(&filtered[0]),
+            cached
+        ));
+    }
+
+    #[cfg(feature = "graphql")]
     #[tokio::test]
     async fn test_location_schema() {
         let html = Html::parse_document(
@@ -209,19 +554,82 @@ mod tests {
                 }
             }
         "#;
-        let binding: Variables = HashMap::default();
+        let binding = Variables::default();
         let res = juniper::execute(query, None, &root, &binding, &())
             .await
             .unwrap();
         println!("{}", serde_json::to_string_pretty(&res).unwrap());
     }
 
+    #[cfg(feature = "server")]
+    #[tokio::test]
+    async fn test_menus_from_archive() {
+        let html = Html::parse_document(
+            &fs::read_to_string("src/parse/html_examples/daily_menu/dining_hall.html").unwrap(),
+        );
+        let url: Url = "https://nutrition.sa.ucsc.edu/shortmenu.aspx?\
+        sName=UC+Santa+Cruz+Dining&\
+        locationNum=40&\
+        locationName=College+Nine/John+R.+Lewis+Dining+Hall&naFlag=1"
+            .parse()
+            .expect("url should be valid");
+        let mut location = Location::new(LocationMeta::from_url(url).unwrap());
+        let v = [html];
+        location.add_meals(v.iter()).unwrap();
+        let date = location.daily_menus().next().unwrap().date();
+        let archive = Archive::open_in_memory().unwrap();
+        let locations = Locations {
+            locations: vec![location.clone()],
+        };
+        let changes = ChangeSet {
+            refreshed_at: chrono::Utc::now(),
+            menus: Vec::new(),
+        };
+        archive.save_changes(&locations, &changes).await.unwrap();
+
+        // the date is cached, so it isn't looked up in the archive as well
+        let range = DateRange {
+            start: Some(date),
+            end: None,
+        };
+        let menus = location
+            .menus_with_archive(Some(&range), Some(&archive))
+            .await;
+        assert_eq!(menus.len(), 1);
+
+        // the menu has since fallen out of the cache
+        location.clear();
+        let range = DateRange {
+            start: Some(date),
+            end: Some(date),
+        };
+        assert!(location
+            .menus_with_archive(Some(&range), None)
+            .await
+            .is_empty());
+        let menus = location
+            .menus_with_archive(Some(&range), Some(&archive))
+            .await;
+        assert_eq!(menus.len(), 1);
+        assert_eq!(menus[0].date(), date);
+        // the archive is only used when the range has a start
+        let range = DateRange {
+            start: None,
+            end: Some(date),
+        };
+        assert!(location
+            .menus_with_archive(Some(&range), Some(&archive))
+            .await
+            .is_empty());
+    }
+
+    #[cfg(feature = "graphql")]
     #[tokio::test]
     async fn test_locations_schema() {
         let html =
             fs::read_to_string("./src/parse/html_examples/locations/locations.html").unwrap();
         let document = scraper::Html::parse_document(&html);
-        let locations = Locations::from_html_element(document.root_element())
+        let locations = Locations::from_html_element(document.root_element(), &upstream())
             .expect("The example html should be valid");
         assert_eq!(locations.locations.len(), 14);
         let root = RootNode::new(
@@ -238,7 +646,7 @@ mod tests {
                 }
             }
         ";
-        let binding: Variables = HashMap::default();
+        let binding = Variables::default();
         let res = juniper::execute(query, None, &root, &binding, &())
             .await
             .unwrap();
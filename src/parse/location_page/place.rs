@@ -0,0 +1,92 @@
+#[cfg(feature = "graphql")]
+use juniper::{GraphQLEnum, GraphQLObject};
+
+/// What a location is and where, which `FoodPro` doesn't say beyond its name
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLObject))]
+#[serde(deny_unknown_fields)]
+pub struct Place {
+    /// What the location is
+    pub kind: LocationKind,
+    /// The colleges whose students the location mainly serves, ex. "Cowell" and "Stevenson".
+    /// Empty for the locations which serve the whole campus.
+    #[serde(default)]
+    pub colleges: Vec<String>,
+    /// Where the location is, ex. for directions
+    pub coordinates: Coordinates,
+    /// The street address of the building the location is in
+    pub address: String,
+}
+
+/// What a location is, which decides what it serves and when
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLEnum))]
+pub enum LocationKind {
+    /// An all you can eat dining hall, shared by two colleges
+    DiningHall,
+    /// A market selling groceries and snacks
+    Market,
+    /// A cafe or coffee bar
+    Cafe,
+    /// A food truck, which may move around
+    FoodTruck,
+}
+
+/// A point on the map, in degrees
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLObject))]
+#[serde(deny_unknown_fields)]
+pub struct Coordinates {
+    /// From -90 to 90, north is positive
+    pub latitude: f64,
+    /// From -180 to 180, east is positive
+    pub longitude: f64,
+}
+
+impl Coordinates {
+    const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+
+    /// The point at `latitude` and `longitude`, which aren't checked to be in range
+    pub const fn new(latitude: f64, longitude: f64) -> Self {
+        Self {
+            latitude,
+            longitude,
+        }
+    }
+
+    /// Whether the latitude and longitude are in range
+    pub fn is_valid(self) -> bool {
+        (-90.0..=90.0).contains(&self.latitude) && (-180.0..=180.0).contains(&self.longitude)
+    }
+
+    /// The great circle distance to `other`, with the haversine formula
+    pub fn distance_meters(self, other: Self) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (other.longitude - self.longitude).to_radians();
+        let a = (lat1.cos() * lat2.cos())
+            .mul_add((d_lon / 2.0).sin().powi(2), (d_lat / 2.0).sin().powi(2));
+        2.0 * Self::EARTH_RADIUS_METERS * a.sqrt().asin()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance() {
+        let nine_lewis = Coordinates::new(37.000_47, -122.058_27);
+        let porter_kresge = Coordinates::new(36.994_33, -122.065_64);
+        let distance = nine_lewis.distance_meters(porter_kresge);
+        assert!((900.0..1000.0).contains(&distance), "{distance}");
+        assert!(nine_lewis.distance_meters(nine_lewis).abs() < f64::EPSILON);
+        assert!(
+            (nine_lewis.distance_meters(porter_kresge) - porter_kresge.distance_meters(nine_lewis))
+                .abs()
+                < 1e-6
+        );
+        assert!(!Coordinates::new(91.0, 0.0).is_valid());
+        assert!(!Coordinates::new(0.0, -181.0).is_valid());
+    }
+}
@@ -0,0 +1,17 @@
+use chrono::{DateTime, FixedOffset};
+#[cfg(feature = "graphql")]
+use juniper::GraphQLObject;
+
+use crate::parse::MealType;
+
+/// When a meal is served on a day, going by the usual hours of the location
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "graphql", derive(GraphQLObject))]
+pub struct ServicePeriod {
+    /// The meal which is served
+    pub meal_type: MealType,
+    /// In the timezone of the location
+    pub opens_at: DateTime<FixedOffset>,
+    /// The next day if the meal is served past midnight
+    pub closes_at: DateTime<FixedOffset>,
+}
@@ -1,6 +1,19 @@
 mod allergens;
 mod daily_menu;
+mod dietary_profile;
 mod food_item;
+mod ingredients;
+mod label;
+mod long_menu;
 mod meal;
 mod money;
+mod nutrition;
+pub use allergens::{AllergenFilter, AllergenFlags, Allergens};
 pub use daily_menu::DailyMenu;
+pub use dietary_profile::{DietaryPreset, DietaryProfile, DietaryRule};
+pub use food_item::FoodItem;
+pub use ingredients::Ingredient;
+pub use label::Label;
+pub use long_menu::LongMenu;
+pub use meal::{Meal, Section, Type as MealType};
+pub use nutrition::{Nutrient, NutritionFacts};
@@ -1,7 +1,8 @@
-use std::fmt::Display;
+use std::{collections::BTreeSet, fmt::Display};
 
 use crate::parse::Error;
 use bitflags::bitflags;
+#[cfg(feature = "graphql")]
 use juniper::GraphQLEnum;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
@@ -100,43 +101,152 @@ impl From<&AllergenInfo> for Vec<&'static str> {
     }
 }
 
+/// The allergens listed in the "ALLERGENS:" line of a nutrition label, ex. "Milk, Egg, Wheat".
+/// Unlike `AllergenInfo` this is not limited to the allergens with a legend icon.
+#[derive(Debug, PartialEq, Eq, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct AllergenStatement(BTreeSet<String>);
+
+impl AllergenStatement {
+    pub fn from_text(text: &str) -> Self {
+        Self(
+            text.split(',')
+                .map(|allergen| allergen.trim().trim_end_matches('.').trim_end())
+                .filter(|allergen| !allergen.is_empty())
+                .map(title_case)
+                .collect(),
+        )
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+
+    pub fn contains(&self, allergen: &str) -> bool {
+        self.0
+            .iter()
+            .any(|x| x.eq_ignore_ascii_case(allergen.trim()))
+    }
+}
+
+// labels are inconsistent about capitalization, ex. "Tree nuts" and "Tree Nuts"
+fn title_case(s: &str) -> String {
+    s.split_whitespace()
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map_or_else(String::new, |first| {
+                first
+                    .to_uppercase()
+                    .chain(chars.flat_map(char::to_lowercase))
+                    .collect()
+            })
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 bitflags! {
+    /// The allergens and diets of a food item as a bit set, one flag per `Allergens` variant
     #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
     pub struct AllergenFlags: u16 {
+        /// [`Allergens::Egg`]
         const Egg = 1;
+        /// [`Allergens::Fish`]
         const Fish = 1 << 1;
+        /// [`Allergens::GlutenFriendly`]
         const GlutenFriendly = 1 << 2;
+        /// [`Allergens::Milk`]
         const Milk = 1 << 3;
+        /// [`Allergens::Peanut`]
         const Peanut = 1 << 4;
+        /// [`Allergens::Soy`]
         const Soy = 1 << 5;
+        /// [`Allergens::TreeNut`]
         const TreeNut = 1 << 6;
+        /// [`Allergens::Alcohol`]
         const Alcohol = 1 << 7;
+        /// [`Allergens::Vegan`]
         const Vegan =  1 << 8;
+        /// [`Allergens::Vegetarian`]
         const Vegetarian = 1 << 9;
+        /// [`Allergens::Pork`]
         const Pork = 1 << 10;
+        /// [`Allergens::Beef`]
         const Beef = 1 << 11;
+        /// [`Allergens::Halal`]
         const Halal = 1 << 12;
+        /// [`Allergens::Shellfish`]
         const Shellfish = 1 << 13;
+        /// [`Allergens::Sesame`]
         const Sesame = 1 << 14;
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, GraphQLEnum)]
+/// Which allergens a food item must or must not have, as taken by `Section.foodItems`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct AllergenFilter {
+    /// The item must have every one of these
+    pub contains_all: Option<AllergenFlags>,
+    /// The item must have none of these
+    pub excludes_all: Option<AllergenFlags>,
+    /// The item must have at least one of these
+    pub contains_any: Option<AllergenFlags>,
+}
+
+impl AllergenFilter {
+    /// A filter with the given conditions, which are all skipped if omitted
+    pub fn new(
+        contains_all: Option<Vec<Allergens>>,
+        excludes_all: Option<Vec<Allergens>>,
+        contains_any: Option<Vec<Allergens>>,
+    ) -> Self {
+        Self {
+            contains_all: contains_all.map(Into::into),
+            excludes_all: excludes_all.map(Into::into),
+            contains_any: contains_any.map(Into::into),
+        }
+    }
+
+    /// Whether an item with the allergens in `mask` passes every condition
+    pub fn matches(&self, mask: AllergenFlags) -> bool {
+        self.contains_all.is_none_or(|x| mask.contains(x))
+            && self.contains_any.is_none_or(|x| mask.intersects(x))
+            && self.excludes_all.is_none_or(|x| !mask.intersects(x))
+    }
+}
+
+/// The allergen and diet icons which the dining site shows next to a food item
+#[derive(Debug, PartialEq, Eq, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLEnum))]
 pub enum Allergens {
+    /// Contains egg
     Egg,
+    /// Contains fish
     Fish,
+    /// Made without gluten, though it may share a kitchen with it
     GlutenFriendly,
+    /// Contains milk
     Milk,
+    /// Contains peanuts
     Peanut,
+    /// Contains soy
     Soy,
+    /// Contains tree nuts
     TreeNut,
+    /// Contains alcohol
     Alcohol,
+    /// Has no animal products
     Vegan,
+    /// Has no meat or fish
     Vegetarian,
+    /// Contains pork
     Pork,
+    /// Contains beef
     Beef,
+    /// Prepared according to halal
     Halal,
+    /// Contains shellfish
     Shellfish,
+    /// Contains sesame
     Sesame,
 }
 
@@ -242,7 +352,6 @@ impl Display for AllergenFlags {
 }
 
 #[cfg(test)]
-
 mod tests {
 
     use crate::static_selector;
@@ -351,4 +460,18 @@ mod tests {
         let deserialized: AllergenInfo = serde_json::from_str(&serialized).unwrap();
         assert_eq!(allergen_info, deserialized);
     }
+
+    #[test]
+    fn test_allergen_statement_from_text() {
+        let statement =
+            AllergenStatement::from_text("Milk,\n Egg, Wheat, soy, Tree nuts, Gluten, Milk.");
+        assert_eq!(
+            statement.iter().collect::<Vec<_>>(),
+            vec!["Egg", "Gluten", "Milk", "Soy", "Tree Nuts", "Wheat"]
+        );
+        assert!(statement.contains("wheat"));
+        assert!(statement.contains("Tree Nuts"));
+        assert!(!statement.contains("Sesame"));
+        assert!(AllergenStatement::from_text(" ").iter().next().is_none());
+    }
 }
@@ -1,11 +1,18 @@
 use chrono::NaiveDate;
 
+#[cfg(feature = "graphql")]
 use juniper::graphql_object;
 
+use url::Url;
+
+use super::dietary_profile::DietaryProfile;
+use super::food_item::FoodItem;
+use super::long_menu::LongMenu;
 use super::meal::{Meal, Type};
 use crate::parse::Error;
 use crate::static_selector;
 
+/// The meals of a location on one day, from its short menu page (`shortmenu.aspx`)
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct DailyMenu<'a> {
     // graphql representation: yyyy-MM-dd
@@ -13,23 +20,33 @@ pub struct DailyMenu<'a> {
     meals: Vec<Meal<'a>>,
 }
 
-#[graphql_object]
+#[cfg_attr(feature = "graphql", graphql_object)]
 impl<'a> DailyMenu<'a> {
+    /// The day the meals are served on
     pub const fn date(&self) -> NaiveDate {
         self.date
     }
 
-    pub fn meals(&self, meal_type: Option<Type>) -> Vec<Meal<'a>> {
-        meal_type.map_or_else(
-            || self.meals.clone(),
-            |meal_type| {
-                self.meals
-                    .iter()
-                    .filter(|meal| meal.meal_type == meal_type)
-                    .cloned()
-                    .collect::<Vec<_>>()
-            },
-        )
+    /// The meals without any food items fitting `dietaryProfile` are left out
+    #[allow(clippy::needless_pass_by_value)] // ignored because graphql doesn't support pass by reference
+    pub fn meals(
+        &self,
+        meal_type: Option<Type>,
+        dietary_profile: Option<DietaryProfile>,
+    ) -> Vec<Meal<'a>> {
+        let mut meals: Vec<_> = self
+            .meals
+            .iter()
+            .filter(|meal| meal_type.is_none_or(|x| meal.meal_type == x))
+            .cloned()
+            .collect();
+        if let Some(profile) = &dietary_profile {
+            meals.retain_mut(|meal| {
+                meal.retain_diet(profile);
+                !meal.sections.is_empty()
+            });
+        }
+        meals
     }
 }
 
@@ -54,6 +71,7 @@ impl Ord for DailyMenu<'_> {
 }
 
 impl<'a> DailyMenu<'a> {
+    /// Parses the short menu page of a location
     pub fn from_html_element(element: scraper::ElementRef<'a>) -> Result<Self, Error> {
         static_selector!(DATE_SELECTOR <- "input[name=strCurSearchDays]");
         static_selector!(MEAL_SELECTOR <- r##"table[bordercolor="#CCC"] table[bordercolor="#FFFF00"]"##);
@@ -73,10 +91,65 @@ impl<'a> DailyMenu<'a> {
 
         Ok(Self { date, meals })
     }
+
+    /// Unlike `==`, which only compares the dates, compares everything on the menus
+    pub fn same_contents(&self, other: &Self) -> bool {
+        self.date == other.date
+            && serde_json::to_value(self).ok() == serde_json::to_value(other).ok()
+    }
+
+    /// Removes the food items which don't fit `profile`, and the sections and meals left empty
+    pub fn retain_diet(&mut self, profile: &DietaryProfile) {
+        self.meals.retain_mut(|meal| {
+            meal.retain_diet(profile);
+            !meal.sections.is_empty()
+        });
+    }
+
+    /// Removes the meals other than `meal_type`
+    pub fn retain_meal(&mut self, meal_type: Type) {
+        self.meals.retain(|meal| meal.meal_type == meal_type);
+    }
+
+    /// Every meal, unlike `meals` which filters them
+    pub fn all_meals(&self) -> &[Meal<'a>] {
+        &self.meals
+    }
+
+    /// The meals which have a long menu, along with their `mealName` for fetching it
+    pub fn long_menu_meals(&self) -> impl Iterator<Item = (Type, &'static str)> + '_ {
+        self.meals
+            .iter()
+            .filter_map(|meal| Some((meal.meal_type, meal.meal_type.page_name()?)))
+    }
+
+    /// `base` is the url of the long menu page
+    pub fn add_long_menu(&mut self, meal_type: Type, long_menu: &LongMenu, base: &Url) {
+        for meal in self.meals.iter_mut().filter(|x| x.meal_type == meal_type) {
+            long_menu.merge_into(meal, base);
+        }
+    }
+
+    /// The food items of every meal
+    pub fn food_items(&self) -> impl Iterator<Item = &FoodItem<'a>> {
+        self.meals
+            .iter()
+            .flat_map(|meal| meal.sections.iter())
+            .flat_map(|section| section.food_items.iter())
+    }
+
+    /// The food items of every meal
+    pub fn food_items_mut(&mut self) -> impl Iterator<Item = &mut FoodItem<'a>> {
+        self.meals
+            .iter_mut()
+            .flat_map(|meal| meal.sections.iter_mut())
+            .flat_map(|section| section.food_items.iter_mut())
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "graphql")]
     use juniper::{EmptyMutation, EmptySubscription, RootNode};
 
     use super::*;
@@ -95,6 +168,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "graphql")]
     #[tokio::test]
     async fn test_schema() {
         let html =
@@ -0,0 +1,215 @@
+#[cfg(feature = "graphql")]
+use juniper::{GraphQLEnum, GraphQLInputObject};
+
+use super::allergens::{AllergenFilter, AllergenFlags, Allergens};
+
+/// A diet which the food items are filtered by. The items have to meet every preset and every
+/// rule, ex. `{ presets: [VEGETARIAN, NUT_FREE] }` is the vegetarian items without nuts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "graphql", derive(GraphQLInputObject))]
+pub struct DietaryProfile {
+    /// Common diets, so that they don't have to be spelled out as rules
+    pub presets: Option<Vec<DietaryPreset>>,
+    /// Custom rules for what the presets don't cover
+    pub rules: Option<Vec<DietaryRule>>,
+}
+
+/// The common diets, going by the allergen icons on the menus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "graphql", derive(GraphQLEnum))]
+pub enum DietaryPreset {
+    /// Vegan
+    Vegan,
+    /// Vegan or vegetarian
+    Vegetarian,
+    /// Halal, vegan or vegetarian, without pork or alcohol
+    Halal,
+    /// Vegan, vegetarian, fish or shellfish, without pork or beef
+    Pescatarian,
+    /// Without peanuts or tree nuts
+    NutFree,
+    /// Without milk
+    DairyFree,
+    /// Gluten friendly
+    GlutenFriendly,
+    /// Without pork
+    NoPork,
+    /// Without beef
+    NoBeef,
+}
+
+/// The same filters as `Section.foodItems` takes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "graphql", derive(GraphQLInputObject))]
+pub struct DietaryRule {
+    /// The item must have every one of these
+    pub contains_all_allergens: Option<Vec<Allergens>>,
+    /// The item must have none of these
+    pub excludes_all_allergens: Option<Vec<Allergens>>,
+    /// The item must have at least one of these
+    pub contains_any_allergens: Option<Vec<Allergens>>,
+}
+
+impl DietaryProfile {
+    /// A profile of only `presets`, without custom rules
+    pub const fn new(presets: Vec<DietaryPreset>) -> Self {
+        Self {
+            presets: Some(presets),
+            rules: None,
+        }
+    }
+
+    /// Whether a food item with the allergens in `mask` fits the diet
+    pub fn allows(&self, mask: AllergenFlags) -> bool {
+        self.presets
+            .iter()
+            .flatten()
+            .all(|preset| preset.filter().matches(mask))
+            && self
+                .rules
+                .iter()
+                .flatten()
+                .all(|rule| rule.filter().matches(mask))
+    }
+}
+
+impl DietaryPreset {
+    /// The allergens an item must or must not have to fit the diet
+    pub const fn filter(self) -> AllergenFilter {
+        let (contains_all, excludes_all, contains_any) = match self {
+            Self::Vegan => (Some(AllergenFlags::Vegan), None, None),
+            Self::Vegetarian => (
+                None,
+                None,
+                Some(AllergenFlags::Vegan.union(AllergenFlags::Vegetarian)),
+            ),
+            Self::Halal => (
+                None,
+                Some(AllergenFlags::Pork.union(AllergenFlags::Alcohol)),
+                Some(
+                    AllergenFlags::Halal
+                        .union(AllergenFlags::Vegan)
+                        .union(AllergenFlags::Vegetarian),
+                ),
+            ),
+            Self::Pescatarian => (
+                None,
+                Some(AllergenFlags::Pork.union(AllergenFlags::Beef)),
+                Some(
+                    AllergenFlags::Vegan
+                        .union(AllergenFlags::Vegetarian)
+                        .union(AllergenFlags::Fish)
+                        .union(AllergenFlags::Shellfish),
+                ),
+            ),
+            Self::NutFree => (
+                None,
+                Some(AllergenFlags::Peanut.union(AllergenFlags::TreeNut)),
+                None,
+            ),
+            Self::DairyFree => (None, Some(AllergenFlags::Milk), None),
+            Self::GlutenFriendly => (Some(AllergenFlags::GlutenFriendly), None, None),
+            Self::NoPork => (None, Some(AllergenFlags::Pork), None),
+            Self::NoBeef => (None, Some(AllergenFlags::Beef), None),
+        };
+        AllergenFilter {
+            contains_all,
+            excludes_all,
+            contains_any,
+        }
+    }
+}
+
+impl DietaryRule {
+    fn filter(&self) -> AllergenFilter {
+        let flags = |allergens: &Option<Vec<Allergens>>| {
+            allergens
+                .as_ref()
+                .map(|x| x.iter().copied().map(AllergenFlags::from).collect())
+        };
+        AllergenFilter {
+            contains_all: flags(&self.contains_all_allergens),
+            excludes_all: flags(&self.excludes_all_allergens),
+            contains_any: flags(&self.contains_any_allergens),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        let cheese_pizza = AllergenFlags::Egg
+            | AllergenFlags::Milk
+            | AllergenFlags::Soy
+            | AllergenFlags::Vegetarian
+            | AllergenFlags::Sesame;
+        let pepperoni_pizza = AllergenFlags::Egg
+            | AllergenFlags::Milk
+            | AllergenFlags::Soy
+            | AllergenFlags::Pork
+            | AllergenFlags::Beef
+            | AllergenFlags::Sesame;
+        let tofu = AllergenFlags::GlutenFriendly | AllergenFlags::Soy | AllergenFlags::Vegan;
+        let chicken = AllergenFlags::GlutenFriendly | AllergenFlags::Halal;
+        let allowed = |presets: Vec<DietaryPreset>| {
+            let profile = DietaryProfile::new(presets);
+            [cheese_pizza, pepperoni_pizza, tofu, chicken]
+                .into_iter()
+                .map(|x| profile.allows(x))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            allowed(vec![DietaryPreset::Vegan]),
+            [false, false, true, false]
+        );
+        assert_eq!(
+            allowed(vec![DietaryPreset::Vegetarian]),
+            [true, false, true, false]
+        );
+        assert_eq!(
+            allowed(vec![DietaryPreset::Halal]),
+            [true, false, true, true]
+        );
+        assert_eq!(
+            allowed(vec![DietaryPreset::Pescatarian]),
+            [true, false, true, false]
+        );
+        assert_eq!(
+            allowed(vec![DietaryPreset::DairyFree, DietaryPreset::NoPork]),
+            [false, false, true, true]
+        );
+        assert_eq!(
+            allowed(vec![
+                DietaryPreset::GlutenFriendly,
+                DietaryPreset::Vegetarian
+            ]),
+            [false, false, true, false]
+        );
+        assert_eq!(allowed(vec![]), [true; 4]);
+    }
+
+    #[test]
+    fn test_rules() {
+        let profile = DietaryProfile {
+            presets: Some(vec![DietaryPreset::NoBeef]),
+            rules: Some(vec![
+                DietaryRule {
+                    excludes_all_allergens: Some(vec![Allergens::Soy, Allergens::Sesame]),
+                    ..DietaryRule::default()
+                },
+                DietaryRule {
+                    contains_any_allergens: Some(vec![Allergens::Halal, Allergens::Vegan]),
+                    ..DietaryRule::default()
+                },
+            ]),
+        };
+        assert!(profile.allows(AllergenFlags::GlutenFriendly | AllergenFlags::Halal));
+        assert!(profile.allows(AllergenFlags::Vegan));
+        assert!(!profile.allows(AllergenFlags::Vegan | AllergenFlags::Soy));
+        assert!(!profile.allows(AllergenFlags::Halal | AllergenFlags::Beef));
+        assert!(!profile.allows(AllergenFlags::Vegetarian));
+    }
+}
@@ -1,30 +1,56 @@
 use std::borrow::Cow;
 
 use super::allergens::{AllergenFlags, AllergenInfo, Allergens};
+use super::ingredients::Ingredient;
+use super::label::Label;
 use super::money::Usd;
+use super::nutrition::NutritionFacts;
 use crate::parse::text_from_selection::{get_inner_text, text_from_selection};
 use crate::parse::{remove_excess_whitespace, Error};
 use crate::static_selector;
+#[cfg(feature = "graphql")]
 use juniper::graphql_object;
+use url::Url;
 
+/// An item on a menu. The portion, recipe number and label are only known once the long menu
+/// and nutrition label pages are added.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct FoodItem<'a> {
     name: Cow<'a, str>,
     allergen_info: AllergenInfo,
-    #[serde(skip_serializing, skip_deserializing)]
-    price: Option<Usd<'a>>, // in cents
+    // only the markets and cafes have prices, ex. "$5.00"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    price: Option<Usd<'a>>,
+    // FoodPro's recipe number, ex. 217008. Only the long menu lists it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    recipe_id: Option<Cow<'a, str>>,
+    // serving size from the long menu, ex. "2 ea"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    portion: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label_url: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<Label>,
 }
 
 impl PartialEq for FoodItem<'_> {
     fn eq(&self, other: &Self) -> bool {
-        // we ignore meal_type, category and price intentionally in checking equality
-        self.name == other.name && self.allergen_info == other.allergen_info
+        // recipe ids are stable even when the dining hall edits the name of the item. An item
+        // with one is never equal to an item without, or equality wouldn't be transitive; the
+        // change diffing matches those up by name itself.
+        match (&self.recipe_id, &other.recipe_id) {
+            (Some(id), Some(other_id)) => id == other_id,
+            // we ignore meal_type, category and price intentionally in checking equality
+            (None, None) => self.name == other.name && self.allergen_info == other.allergen_info,
+            _ => false,
+        }
     }
 }
 
 impl Eq for FoodItem<'_> {}
 
 impl<'a> FoodItem<'a> {
+    /// Parses a row of the short menu page
     pub fn from_html_element(element: scraper::ElementRef<'a>) -> Result<Self, Error> {
         // example html tr element at ./html_examples/food_item.html
 
@@ -57,12 +83,113 @@ impl<'a> FoodItem<'a> {
             name,
             allergen_info,
             price,
+            recipe_id: None,
+            portion: None,
+            label_url: None,
+            label: None,
         })
     }
 
+    /// Parses a row of the long menu page, which also has the portion, recipe number and label url
+    pub fn from_long_menu_html_element(element: scraper::ElementRef<'a>) -> Result<Self, Error> {
+        // example html tr element at ../html_examples/nutrition/item_row.html
+        static_selector!(NAME_SELECTOR <- ".longmenucoldispname > a");
+        let name_element = element
+            .select(&NAME_SELECTOR)
+            .next()
+            .ok_or_else(|| Error::html_parse_error("Every foodItem element should have a name."))?;
+        let name = remove_excess_whitespace(get_inner_text(name_element, "name")?.trim());
+        // relative link to the nutrition label, ex. "label.aspx?...&RecNumAndPort=217008*2"
+        let label_url = name_element.attr("href").map(Cow::Borrowed);
+        static_selector!(ALLERGEN_INFO_SELECTOR <- "td > img");
+        let allergen_info =
+            AllergenInfo::from_html_elements(element.select(&ALLERGEN_INFO_SELECTOR))?;
+
+        // the checkbox value looks like "217008*2*01", ie. recipe number * portion * ?
+        static_selector!(RECIPE_SELECTOR <- ".longmenucoldispname > input[name=recipe]");
+        let recipe_id = element
+            .select(&RECIPE_SELECTOR)
+            .next()
+            .and_then(|x| x.attr("value"))
+            .and_then(|x| x.split('*').next())
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .ok_or_else(|| Error::html_parse_error("Long menu item has no recipe number"))?;
+
+        static_selector!(PORTION_SELECTOR <- ".longmenucolportions");
+        let portion = element
+            .select(&PORTION_SELECTOR)
+            .next()
+            .and_then(|x| x.text().next())
+            .map(|x| x.replace('\u{00A0}', " ").trim().to_string()) // looks like "2&nbsp;ea"
+            .filter(|x| !x.is_empty())
+            .map(Cow::Owned);
+
+        Ok(Self {
+            name,
+            allergen_info,
+            price: None,
+            recipe_id: Some(Cow::Borrowed(recipe_id)),
+            portion,
+            label_url,
+            label: None,
+        })
+    }
+
+    /// Copies the fields which only the long menu has from `item`.
+    /// `base` is the url of the long menu page, which the label url is relative to.
+    pub fn merge_long_menu_item(&mut self, item: &FoodItem<'_>, base: &Url) {
+        let owned = |x: &Option<Cow<'_, str>>| x.as_deref().map(|x| Cow::Owned(x.to_string()));
+        self.recipe_id = owned(&item.recipe_id);
+        self.portion = owned(&item.portion);
+        self.label_url = item
+            .label_url
+            .as_deref()
+            .and_then(|href| base.join(href).ok())
+            .map(|url| Cow::Owned(url.into()));
+    }
+
+    /// The recipe number, if the item came from or was merged with the long menu
+    pub fn recipe_id(&self) -> Option<&str> {
+        self.recipe_id.as_deref()
+    }
+
+    /// An item with only a name, ex. for the items a test expects
+    #[cfg(test)]
+    pub fn with_name(name: impl Into<Cow<'a, str>>) -> Self {
+        Self {
+            name: name.into(),
+            allergen_info: AllergenInfo::default(),
+            price: None,
+            recipe_id: None,
+            portion: None,
+            label_url: None,
+            label: None,
+        }
+    }
+
+    /// The allergen icons of the item, as flags
     pub fn get_allergen_mask(&self) -> AllergenFlags {
         self.allergen_info.into()
     }
+
+    /// Whether the item's nutrition label lists `allergen`, or `None` if the label has not been fetched
+    pub fn declares_label_allergen(&self, allergen: &str) -> Option<bool> {
+        self.label
+            .as_ref()
+            .map(|label| label.allergens.contains(allergen))
+    }
+
+    /// The nutrition label, if it was fetched
+    pub const fn label(&self) -> Option<&Label> {
+        self.label.as_ref()
+    }
+
+    /// Attaches the nutrition label, which is a separate page from the menu, so it is only known
+    /// after the menu is parsed
+    pub fn set_label(&mut self, label: Label) {
+        self.label = Some(label);
+    }
 }
 
 impl From<Vec<Allergens>> for AllergenFlags {
@@ -73,22 +200,78 @@ impl From<Vec<Allergens>> for AllergenFlags {
     }
 }
 
-#[graphql_object]
-impl<'a> FoodItem<'a> {
+#[cfg_attr(feature = "graphql", graphql_object)]
+impl FoodItem<'_> {
+    /// The item's recipe number. Unlike the name, it stays the same across days
+    /// and spelling edits.
+    #[cfg(feature = "graphql")]
+    pub fn id(&self) -> Option<juniper::ID> {
+        self.recipe_id
+            .as_ref()
+            .map(|id| juniper::ID::from(id.to_string()))
+    }
+
+    /// The allergen icons next to the item on the menu
     pub fn allergens(&self) -> Vec<Allergens> {
         self.allergen_info.into()
     }
 
+    /// The name of the item on the menu, ex. "Cheese Pizza"
     pub fn name(&self) -> &str {
         &self.name
     }
+    /// The price as the menu shows it, ex. "$5.00". Only the markets and cafes have prices.
     pub fn price(&self) -> Option<String> {
         self.price.as_ref().map(Usd::to_string)
     }
+
+    /// The price in the smallest unit of `currency`, ex. 500 for $5.00
+    pub fn price_cents(&self) -> Option<i32> {
+        self.price
+            .as_ref()
+            .and_then(|x| i32::try_from(x.cents()).ok())
+    }
+
+    /// The ISO 4217 code of the currency of the price, ex. "USD"
+    pub fn currency(&self) -> Option<&str> {
+        self.price.as_ref().map(Usd::currency)
+    }
+
+    /// Serving size from the long menu, ex. "2 ea"
+    pub fn portion(&self) -> Option<&str> {
+        self.portion.as_deref()
+    }
+
+    /// Url of the item's nutrition label page
+    pub fn label_url(&self) -> Option<&str> {
+        self.label_url.as_deref()
+    }
+
+    /// Nutrition facts from the item's nutrition label, if it has been fetched
+    pub fn nutrition(&self) -> Option<&NutritionFacts> {
+        self.label.as_ref().map(|label| &label.nutrition)
+    }
+
+    /// Ingredients from the item's nutrition label, if it has been fetched.
+    /// Parenthesized sub-ingredients are nested under the ingredient they belong to.
+    pub fn ingredients(&self) -> Option<&[Ingredient]> {
+        self.label
+            .as_ref()
+            .map(|label| label.ingredients.as_slice())
+    }
+
+    /// Allergens declared in the text of the item's nutrition label, if it has been fetched.
+    /// Unlike `allergens` this includes allergens without an icon, such as wheat.
+    pub fn label_allergens(&self) -> Option<Vec<&str>> {
+        self.label
+            .as_ref()
+            .map(|label| label.allergens.iter().collect())
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "graphql")]
     use juniper::{EmptyMutation, EmptySubscription, RootNode};
 
     use super::*;
@@ -100,10 +283,21 @@ mod tests {
             name: "yummy meat".into(),
             allergen_info: AllergenInfo(AllergenFlags::Egg | AllergenFlags::Sesame),
             price: Usd::from_str("5.00").ok(),
+            recipe_id: Some("217008".into()),
+            portion: Some("2 ea".into()),
+            label_url: None,
+            label: None,
         };
         let serialized = serde_json::to_string(&x).unwrap();
         let deserialized: FoodItem = serde_json::from_str(&serialized).unwrap();
         assert_eq!(x, deserialized);
+        // equality ignores the price, but it should survive the round trip too
+        assert_eq!(deserialized.price, x.price);
+        assert_eq!(deserialized.price_cents(), Some(500));
+        // items cached before prices were kept don't have one
+        let deserialized: FoodItem =
+            serde_json::from_str(&serialized.replace(r#""price":"$5.00","#, "")).unwrap();
+        assert_eq!(deserialized.price, None);
     }
 
     #[test]
@@ -124,16 +318,62 @@ mod tests {
 
         // make sure price is Some(Money::from_str("1.00", iso::USD).unwrap())
         assert_eq!(food_item.price, Some(Usd::from_str("1.00").unwrap()));
+        assert_eq!(food_item.price_cents(), Some(100));
+        assert_eq!(food_item.currency(), Some("USD"));
 
         // double check meal type and category
     }
 
+    #[test]
+    fn test_food_item_from_long_menu_html_element() {
+        let html =
+            std::fs::read_to_string("./src/parse/html_examples/nutrition/item_row.html").unwrap();
+        let doc = scraper::Html::parse_document(&html);
+        let food_item = FoodItem::from_long_menu_html_element(doc.root_element())
+            .expect("The example html should be valid");
+        assert_eq!(food_item.name(), "Blueberry Whole Wheat Pancakes");
+        assert_eq!(food_item.recipe_id.as_deref(), Some("217008"));
+        assert_eq!(food_item.portion.as_deref(), Some("2 ea"));
+        assert_eq!(
+            food_item.label_url.as_deref(),
+            Some("label.aspx?locationNum=40&locationName=John+R.+Lewis+%26+College+Nine+Dining+Hall&dtdate=01%2f21%2f2025&RecNumAndPort=217008*2")
+        );
+        assert!(food_item.allergen_info.contains(AllergenFlags::Vegetarian));
+        assert!(food_item.allergen_info.contains(AllergenFlags::Soy));
+        assert!(food_item.allergen_info.contains(AllergenFlags::Egg));
+        assert!(food_item.allergen_info.contains(AllergenFlags::Milk));
+    }
+
+    #[test]
+    fn test_eq_prefers_recipe_id() {
+        let item = |name: &'static str, recipe_id: Option<&'static str>| FoodItem {
+            name: name.into(),
+            allergen_info: AllergenInfo(AllergenFlags::Egg),
+            price: None,
+            recipe_id: recipe_id.map(Cow::Borrowed),
+            portion: None,
+            label_url: None,
+            label: None,
+        };
+        assert_eq!(item("Pancakes", Some("1")), item("Pancake", Some("1")));
+        assert_ne!(item("Pancakes", Some("1")), item("Pancakes", Some("2")));
+        assert_ne!(item("Pancakes", Some("1")), item("Pancakes", None));
+        assert_ne!(item("Pancakes", None), item("Pancakes", Some("2")));
+        assert_eq!(item("Pancakes", None), item("Pancakes", None));
+        assert_ne!(item("Pancakes", None), item("Pancake", None));
+    }
+
+    #[cfg(feature = "graphql")]
     #[tokio::test]
     async fn test_schema() {
         let x = FoodItem {
             name: "yummy meat".into(),
             allergen_info: AllergenInfo(AllergenFlags::Egg | AllergenFlags::Sesame),
             price: None,
+            recipe_id: None,
+            portion: None,
+            label_url: None,
+            label: None,
         };
         let rn = RootNode::new(
             x,
@@ -144,6 +384,7 @@ mod tests {
         println!("{}", rn.as_sdl());
         let query = r"
             {
+                id
                 allergens
                 name
                 price
@@ -157,4 +398,72 @@ mod tests {
         // assert!(false); // just to see the output
         // TODO: delete the above line test
     }
+
+    #[cfg(feature = "graphql")]
+    #[tokio::test]
+    async fn test_label_schema() {
+        let html =
+            std::fs::read_to_string("./src/parse/html_examples/nutrition/item.html").unwrap();
+        let doc = scraper::Html::parse_document(&html);
+        let mut x = FoodItem {
+            name: "Blueberry Whole Wheat Pancakes".into(),
+            allergen_info: AllergenInfo(AllergenFlags::Vegetarian),
+            price: None,
+            recipe_id: None,
+            portion: None,
+            label_url: None,
+            label: None,
+        };
+        x.set_label(Label::from_html_element(doc.root_element()).unwrap());
+        let rn = RootNode::new(
+            x,
+            EmptyMutation::<()>::new(),
+            EmptySubscription::<()>::new(),
+        );
+        let query = r"
+            {
+                nutrition {
+                    servingSize
+                    calories
+                    totalFat {
+                        amount
+                        unit
+                        dailyValue
+                    }
+                    iron
+                }
+                ingredients {
+                    name
+                }
+                labelAllergens
+            }
+        ";
+        let binding = juniper::Variables::default();
+        let (res, _errors) = juniper::execute(query, None, &rn, &binding, &())
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(res).unwrap(),
+            serde_json::json!({
+                "nutrition": {
+                    "servingSize": "2 ea",
+                    "calories": 202,
+                    "totalFat": {
+                        "amount": 7.8,
+                        "unit": "g",
+                        "dailyValue": 10
+                    },
+                    "iron": 8
+                },
+                "ingredients": [
+                    { "name": "Whole Milk" },
+                    { "name": "Whole Wheat and Honey Pancake Mix" },
+                    { "name": "Blueberries" },
+                    { "name": "Eggs" },
+                    { "name": "Unsalted Butter" }
+                ],
+                "labelAllergens": ["Egg", "Gluten", "Milk", "Soy", "Wheat"]
+            })
+        );
+    }
 }
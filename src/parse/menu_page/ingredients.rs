@@ -0,0 +1,117 @@
+#[cfg(feature = "graphql")]
+use juniper::GraphQLObject;
+
+/// An entry of an ingredient list, ex. "Eggs (Cage Free Whole Eggs, Citric Acid)"
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLObject))]
+pub struct Ingredient {
+    /// The name without the sub-ingredients, ex. "Eggs"
+    pub name: String,
+    /// The parenthesized sub-ingredients, ex. "Cage Free Whole Eggs" and "Citric Acid"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ingredients: Vec<Self>,
+}
+
+impl Ingredient {
+    /// Parses a comma separated ingredient list, keeping the nesting of parenthesized
+    /// sub-ingredients. Unbalanced parentheses are tolerated since the labels are hand written.
+    pub fn parse_list(s: &str) -> Vec<Self> {
+        Self::parse_nested(&mut s.chars(), false)
+    }
+
+    // consumes characters until the end of the string or until the closing paren of the current list
+    fn parse_nested(chars: &mut std::str::Chars, nested: bool) -> Vec<Self> {
+        let mut out = vec![];
+        let mut name = String::new();
+        let mut ingredients = vec![];
+        while let Some(c) = chars.next() {
+            match c {
+                '(' | '[' => ingredients.append(&mut Self::parse_nested(chars, true)),
+                ')' | ']' if nested => break,
+                ')' | ']' => {} // stray closing paren at the top level
+                ',' => {
+                    Self::push(&mut out, &name, std::mem::take(&mut ingredients));
+                    name.clear();
+                }
+                c => name.push(c),
+            }
+        }
+        Self::push(&mut out, &name, ingredients);
+        out
+    }
+
+    fn push(out: &mut Vec<Self>, name: &str, ingredients: Vec<Self>) {
+        let name = name.trim().trim_end_matches('.').trim_end();
+        if name.is_empty() && ingredients.is_empty() {
+            return;
+        }
+        out.push(Self {
+            name: name.to_string(),
+            ingredients,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ingredient(name: &str, ingredients: Vec<Ingredient>) -> Ingredient {
+        Ingredient {
+            name: name.to_string(),
+            ingredients,
+        }
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let parsed = Ingredient::parse_list(
+            "Whole Milk, Mix (Flour (wheat flour, niacin), Salt), Eggs (Cage Free Whole Eggs, Citric Acid)",
+        );
+        assert_eq!(
+            parsed,
+            vec![
+                ingredient("Whole Milk", vec![]),
+                ingredient(
+                    "Mix",
+                    vec![
+                        ingredient(
+                            "Flour",
+                            vec![
+                                ingredient("wheat flour", vec![]),
+                                ingredient("niacin", vec![])
+                            ]
+                        ),
+                        ingredient("Salt", vec![]),
+                    ]
+                ),
+                ingredient(
+                    "Eggs",
+                    vec![
+                        ingredient("Cage Free Whole Eggs", vec![]),
+                        ingredient("Citric Acid", vec![])
+                    ]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_unbalanced() {
+        assert_eq!(
+            Ingredient::parse_list("Butter (Cream (milk), Salt"),
+            vec![ingredient(
+                "Butter",
+                vec![
+                    ingredient("Cream", vec![ingredient("milk", vec![])]),
+                    ingredient("Salt", vec![])
+                ]
+            )]
+        );
+        assert_eq!(
+            Ingredient::parse_list("Water), Salt."),
+            vec![ingredient("Water", vec![]), ingredient("Salt", vec![])]
+        );
+        assert!(Ingredient::parse_list("  ").is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+use super::allergens::AllergenStatement;
+use super::ingredients::Ingredient;
+use super::nutrition::NutritionFacts;
+use crate::parse::{remove_excess_whitespace, Error};
+use crate::static_selector;
+
+/// Everything on an item's "Nutrition Label" page (`label.aspx`)
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub struct Label {
+    /// The nutrition facts table
+    pub nutrition: NutritionFacts,
+    /// The ingredient list, in the order of the label
+    #[serde(default)]
+    pub ingredients: Vec<Ingredient>,
+    /// The "Allergens:" line, which can list allergens without an icon on the menu
+    #[serde(default)]
+    pub allergens: AllergenStatement,
+}
+
+impl Label {
+    /// Parses the nutrition label page of an item
+    // example html at ../html_examples/nutrition/item.html
+    pub fn from_html_element(element: scraper::ElementRef) -> Result<Self, Error> {
+        static_selector!(INGREDIENTS_SELECTOR <- ".labelingredientsvalue");
+        static_selector!(ALLERGENS_SELECTOR <- ".labelallergensvalue");
+        let nutrition = NutritionFacts::from_html_element(element)?;
+        // both the ingredients and the allergens are left off of the label for some items
+        let text_of = |selector: &scraper::Selector| {
+            element
+                .select(selector)
+                .next()
+                .map(|x| remove_excess_whitespace(&x.text().collect::<String>()).into_owned())
+        };
+        let ingredients = text_of(&INGREDIENTS_SELECTOR)
+            .map(|x| Ingredient::parse_list(&x))
+            .unwrap_or_default();
+        let allergens = text_of(&ALLERGENS_SELECTOR)
+            .map(|x| AllergenStatement::from_text(&x))
+            .unwrap_or_default();
+        Ok(Self {
+            nutrition,
+            ingredients,
+            allergens,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_from_html_element() {
+        let html = fs::read_to_string("./src/parse/html_examples/nutrition/item.html").unwrap();
+        let document = scraper::Html::parse_document(&html);
+        let label = Label::from_html_element(document.root_element())
+            .expect("The example html should be valid");
+        assert_eq!(label.nutrition.calories, Some(202));
+
+        let names: Vec<&str> = label.ingredients.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "Whole Milk",
+                "Whole Wheat and Honey Pancake Mix",
+                "Blueberries",
+                "Eggs",
+                "Unsalted Butter"
+            ]
+        );
+        let mix = &label.ingredients[1];
+        assert_eq!(mix.ingredients[0].name, "Whole Wheat Flour");
+        assert_eq!(mix.ingredients[1].name, "Enriched Bleached Flour");
+        assert_eq!(mix.ingredients[1].ingredients[0].name, "wheat flour");
+        assert_eq!(mix.ingredients[2].name, "Soy Flour");
+        assert_eq!(label.ingredients[4].ingredients[0].name, "Cream");
+
+        assert_eq!(
+            label.allergens.iter().collect::<Vec<_>>(),
+            vec!["Egg", "Gluten", "Milk", "Soy", "Wheat"]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use url::Url;
+
+use super::food_item::FoodItem;
+use super::meal::{Meal, Section};
+use crate::parse::text_from_selection::get_inner_text;
+use crate::parse::{remove_excess_whitespace, Error};
+use crate::static_selector;
+
+/// A meal's long menu page (`longmenu.aspx`). It lists the same items as the short menu
+/// along with their recipe numbers, portions and links to their nutrition labels.
+#[derive(Debug, Clone)]
+pub struct LongMenu<'a> {
+    sections: Vec<Section<'a>>,
+}
+
+impl<'a> LongMenu<'a> {
+    /// Parses the long menu page of a meal
+    pub fn from_html_element(element: scraper::ElementRef<'a>) -> Result<Self, Error> {
+        // example html at ../html_examples/nutrition/items.html
+        static_selector!(ROW_SELECTOR <- r##"table[bordercolor="#C0C0C0"] > tbody > tr"##);
+        static_selector!(SECTION_NAME_SELECTOR <- ".longmenucolmenucat");
+        static_selector!(FOOD_ITEM_SELECTOR <- ".longmenucoldispname");
+        let mut sections: Vec<Section> = vec![];
+        for row in element.select(&ROW_SELECTOR) {
+            if let Some(section_name) = row.select(&SECTION_NAME_SELECTOR).next() {
+                // looks like -- name --
+                let name = get_inner_text(section_name, "section name")?
+                    .trim()
+                    .trim_start_matches("--")
+                    .trim_end_matches("--")
+                    .trim();
+                sections.push(Section {
+                    name: remove_excess_whitespace(name),
+                    food_items: vec![],
+                });
+            } else if row.select(&FOOD_ITEM_SELECTOR).next().is_some() {
+                let food_item = FoodItem::from_long_menu_html_element(row)?;
+                sections
+                    .last_mut()
+                    .ok_or_else(|| {
+                        Error::html_parse_error("Long menu food item comes before any section")
+                    })?
+                    .food_items
+                    .push(food_item);
+            }
+            // everything else is a header or the buttons at the bottom of the form
+        }
+        Ok(Self { sections })
+    }
+
+    /// Adds the long menu's extra fields to the matching food items of `meal`.
+    /// `base` is the url of the long menu page.
+    pub fn merge_into(&self, meal: &mut Meal<'_>, base: &Url) {
+        // items are matched by name since that is all the short menu has to go off of
+        let items: HashMap<String, &FoodItem> = self
+            .sections
+            .iter()
+            .flat_map(|section| section.food_items.iter())
+            .map(|item| (match_key(item.name()), item))
+            .collect();
+        for food_item in meal
+            .sections
+            .iter_mut()
+            .flat_map(|section| section.food_items.iter_mut())
+        {
+            if let Some(item) = items.get(&match_key(food_item.name())) {
+                food_item.merge_long_menu_item(item, base);
+            }
+        }
+    }
+}
+
+fn match_key(name: &str) -> String {
+    remove_excess_whitespace(name.trim()).to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::menu_page::meal::Type;
+    use std::fs;
+
+    #[test]
+    fn test_from_html_element() {
+        let html = fs::read_to_string("./src/parse/html_examples/nutrition/items.html").unwrap();
+        let document = scraper::Html::parse_document(&html);
+        let long_menu = LongMenu::from_html_element(document.root_element())
+            .expect("The example html should be valid");
+        let names: Vec<&str> = long_menu.sections.iter().map(|x| x.name.as_ref()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "Breakfast",
+                "Clean Plate",
+                "Bakery",
+                "Cereal",
+                "All Day",
+                "Condiments",
+                "Bread & Bagels",
+                "Beverages"
+            ]
+        );
+        let count: usize = long_menu.sections.iter().map(|x| x.food_items.len()).sum();
+        assert_eq!(count, 183);
+        let first = &long_menu.sections[0].food_items[0];
+        assert_eq!(first.name(), "Blueberry Whole Wheat Pancakes");
+        assert_eq!(first.recipe_id(), Some("217008"));
+        assert_eq!(first.portion(), Some("2 ea"));
+    }
+
+    #[test]
+    fn test_merge_into() {
+        let html = fs::read_to_string("./src/parse/html_examples/nutrition/items.html").unwrap();
+        let document = scraper::Html::parse_document(&html);
+        let long_menu = LongMenu::from_html_element(document.root_element()).unwrap();
+
+        // the short menu version of the same meal
+        let mut meal = Meal {
+            meal_type: Type::Breakfast,
+            sections: long_menu
+                .sections
+                .iter()
+                .map(|section| Section {
+                    name: section.name.clone(),
+                    food_items: section
+                        .food_items
+                        .iter()
+                        .map(|item| FoodItem::with_name(item.name().to_string()))
+                        .collect(),
+                })
+                .collect(),
+        };
+        let base: Url = "https://nutrition.sa.ucsc.edu/longmenu.aspx?locationNum=40"
+            .parse()
+            .unwrap();
+        long_menu.merge_into(&mut meal, &base);
+        let first = &meal.sections[0].food_items[0];
+        assert_eq!(first.recipe_id(), Some("217008"));
+        assert_eq!(first.portion(), Some("2 ea"));
+        assert_eq!(
+            first.label_url(),
+            Some("https://nutrition.sa.ucsc.edu/label.aspx?locationNum=40&locationName=John+R.+Lewis+%26+College+Nine+Dining+Hall&dtdate=01%2f21%2f2025&RecNumAndPort=217008*2")
+        );
+        assert!(meal
+            .sections
+            .iter()
+            .flat_map(|x| x.food_items.iter())
+            .all(|x| x.recipe_id().is_some()));
+    }
+}
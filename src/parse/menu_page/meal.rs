@@ -1,5 +1,6 @@
 use std::{borrow::Cow, iter::Peekable, vec};
 
+#[cfg(feature = "graphql")]
 use juniper::{graphql_object, GraphQLEnum, GraphQLObject};
 use regex::RegexBuilder;
 use scraper::{element_ref::Select, selectable::Selectable};
@@ -10,29 +11,75 @@ use crate::{
 };
 
 use super::{
-    allergens::{AllergenFlags, Allergens},
+    allergens::{AllergenFilter, Allergens},
+    dietary_profile::DietaryProfile,
     food_item::FoodItem,
 };
 use crate::parse::Error;
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, GraphQLEnum, serde::Serialize, serde::Deserialize)]
+/// Which meal of the day a meal is, going by its name on the menu
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLEnum))]
 pub enum Type {
+    /// Breakfast
     Breakfast,
+    /// Lunch
     Lunch,
+    /// Dinner
     Dinner,
+    /// Late Night
     LateNight,
-    Menu,       // used for menus that are not specific to a meal time. Ex: Global Cafe
-    Unknown, // used for when the meal type is not known (ex. when the food item is detached from a meal)
-    AllDay,  // default if the above don't match
-    BananaJoes, // Late Night @ Banana Joes - only for crown
+    /// A menu which isn't for a specific meal time, ex. at Global Cafe
+    Menu,
+    /// The meal type isn't known, ex. when the food item is detached from a meal
+    Unknown,
+    /// Served all day
+    AllDay,
+    /// Late Night @ Banana Joe's, only at Crown
+    BananaJoes,
 }
-#[derive(Debug, GraphQLObject, Clone, serde::Serialize, serde::Deserialize)]
+impl Type {
+    fn from_page_name(name: &str) -> Self {
+        match name {
+            "Breakfast" => Self::Breakfast,
+            "Lunch" => Self::Lunch,
+            "Dinner" => Self::Dinner,
+            "Late Night" => Self::LateNight,
+            "Late Night @ Banana Joe's" => Self::BananaJoes,
+            "Menu" => Self::Menu,
+            "All Day" => Self::AllDay,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// The name of the meal as written on the menu pages, which is also the
+    /// `mealName` of its long menu. `None` for meals which were not named on the page.
+    pub const fn page_name(self) -> Option<&'static str> {
+        match self {
+            Self::Breakfast => Some("Breakfast"),
+            Self::Lunch => Some("Lunch"),
+            Self::Dinner => Some("Dinner"),
+            Self::LateNight => Some("Late Night"),
+            Self::BananaJoes => Some("Late Night @ Banana Joe's"),
+            Self::Menu => Some("Menu"),
+            Self::AllDay => Some("All Day"),
+            Self::Unknown => None,
+        }
+    }
+}
+
+/// One meal of a daily menu, made up of sections like "Entrees" and "Soups"
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLObject))]
 pub struct Meal<'a> {
+    /// Which meal of the day it is
     pub meal_type: Type,
+    /// The sections in the order of the menu
     pub sections: Vec<Section<'a>>,
 }
 
 impl<'a> Meal<'a> {
+    /// Parses one meal of the short menu page
     pub fn from_html_element(element: scraper::ElementRef<'a>) -> Result<Self, Error> {
         // example html div element at ./html_examples/meal.html
         static_selector!(ROW_SELECTOR <- r##"table[bordercolor="#FFFF00"] > tbody > tr"##);
@@ -46,17 +93,7 @@ impl<'a> Meal<'a> {
         static_selector!(MEAL_TYPE_SELECTOR <- ".shortmenumeals");
         let meal_type =
             text_from_selection(&MEAL_TYPE_SELECTOR, meal_name_row, "meal", "meal type")?;
-        // print out meal type
-        let meal_type = match meal_type {
-            "Breakfast" => Type::Breakfast,
-            "Lunch" => Type::Lunch,
-            "Dinner" => Type::Dinner,
-            "Late Night" => Type::LateNight,
-            "Late Night @ Banana Joe's" => Type::BananaJoes,
-            "Menu" => Type::Menu,
-            "All Day" => Type::AllDay,
-            _ => Type::Unknown,
-        };
+        let meal_type = Type::from_page_name(meal_type);
 
         static_selector!(SECTION_NAME_SELECTOR <- "table > tbody > tr");
         let section_elements = meal_item_row.select(&SECTION_NAME_SELECTOR);
@@ -79,6 +116,14 @@ impl<'a> Meal<'a> {
             sections: sections_vec,
         })
     }
+
+    /// Removes the food items which don't fit `profile`, and the sections left empty
+    pub fn retain_diet(&mut self, profile: &DietaryProfile) {
+        self.sections.retain_mut(|section| {
+            section.retain_diet(profile);
+            !section.food_items.is_empty()
+        });
+    }
 }
 
 pub struct SectionIterator<'a> {
@@ -103,39 +148,42 @@ impl<'a> Iterator for SectionIterator<'a> {
     }
 }
 
+/// A group of food items on a menu, ex. "Entrees"
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Section<'a> {
+    /// The name of the section, without the dashes around it on the menu
     pub name: Cow<'a, str>,
+    /// The items in the order of the menu
     pub food_items: Vec<FoodItem<'a>>,
 }
 
-#[graphql_object]
+#[cfg_attr(feature = "graphql", graphql_object)]
 impl<'a> Section<'a> {
+    /// The name of the section, ex. "Entrees"
     pub fn name(&self) -> &str {
         &self.name
     }
 
+    /// `minPrice` and `maxPrice` are in cents, like `FoodItem.priceCents`, and leave out the
+    /// items without a price.
+    #[allow(clippy::needless_pass_by_value)] // ignored because graphql doesn't support pass by reference
+    #[allow(clippy::too_many_arguments)] // every filter is its own argument
     pub fn food_items(
         &self,
         contains_all_allergens: Option<Vec<Allergens>>,
         excludes_all_allergens: Option<Vec<Allergens>>,
         contains_any_allergens: Option<Vec<Allergens>>,
         name_contains: Option<String>,
+        excludes_label_allergens: Option<Vec<String>>,
+        dietary_profile: Option<DietaryProfile>,
+        min_price: Option<i32>,
+        max_price: Option<i32>,
     ) -> Vec<FoodItem<'a>> {
-        let contains_all_mask: Option<AllergenFlags> =
-            contains_all_allergens.map(std::convert::Into::into);
-        let excludes_all_mask: Option<AllergenFlags> =
-            excludes_all_allergens.map(std::convert::Into::into);
-        let contains_any_mask: Option<AllergenFlags> =
-            contains_any_allergens.map(std::convert::Into::into);
-        let allergen_filter = |food_item: &&FoodItem<'a>| {
-            let mask = food_item.get_allergen_mask();
-            let mut out = true;
-            out &= contains_all_mask.map_or(true, |contains_all| mask.contains(contains_all));
-            out &= contains_any_mask.map_or(true, |contains_any| mask.intersects(contains_any));
-            out &= excludes_all_mask.map_or(true, |excludes_all| !mask.intersects(excludes_all));
-            out
-        };
+        let allergen_filter = AllergenFilter::new(
+            contains_all_allergens,
+            excludes_all_allergens,
+            contains_any_allergens,
+        );
 
         let name_contains = name_contains.map(|s| {
             RegexBuilder::new(&regex::escape(&s))
@@ -146,11 +194,33 @@ impl<'a> Section<'a> {
 
         self.food_items
             .iter()
-            .filter(allergen_filter)
+            .filter(|food_item| allergen_filter.matches(food_item.get_allergen_mask()))
+            .filter(|food_item| {
+                dietary_profile
+                    .as_ref()
+                    .is_none_or(|profile| profile.allows(food_item.get_allergen_mask()))
+            })
             .filter(|food_item| {
                 name_contains
                     .as_ref()
-                    .map_or(true, |pat| pat.is_match(food_item.name()))
+                    .is_none_or(|pat| pat.is_match(food_item.name()))
+            })
+            .filter(|food_item| {
+                // items without a fetched label are excluded since their allergens are unknown
+                excludes_label_allergens.as_ref().is_none_or(|allergens| {
+                    allergens
+                        .iter()
+                        .all(|allergen| food_item.declares_label_allergen(allergen) == Some(false))
+                })
+            })
+            .filter(|food_item| {
+                if min_price.is_none() && max_price.is_none() {
+                    return true;
+                }
+                food_item.price_cents().is_some_and(|cents| {
+                    min_price.is_none_or(|min| cents >= min)
+                        && max_price.is_none_or(|max| cents <= max)
+                })
             })
             .cloned()
             .collect()
@@ -158,7 +228,13 @@ impl<'a> Section<'a> {
 }
 
 impl<'a> Section<'a> {
-    // takes in an iterator of tr elements of a specific meal and consumes the elements to create a MealSection
+    /// Removes the food items which don't fit `profile`
+    pub fn retain_diet(&mut self, profile: &DietaryProfile) {
+        self.food_items
+            .retain(|food_item| profile.allows(food_item.get_allergen_mask()));
+    }
+
+    /// Parses a section of a meal from the rows of the meal, consuming the rows of the section
     pub fn from_html_elements(elements: &mut Peekable<Select<'a, 'a>>) -> Result<Self, Error> {
         static_selector!(SECTION_NAME_SELECTOR <- ".shortmenucats > span");
 
@@ -201,10 +277,13 @@ impl<'a> Section<'a> {
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "graphql")]
     use juniper::{EmptyMutation, EmptySubscription, RootNode};
+    #[cfg(feature = "graphql")]
     use serde_json::json;
 
     use super::*;
+    use crate::parse::{DietaryPreset, DietaryRule};
     use std::fs;
 
     #[test]
@@ -220,6 +299,61 @@ mod tests {
         println!("{:#?}", meal.sections);
     }
 
+    #[test]
+    fn test_retain_diet() {
+        let html = fs::read_to_string("./src/parse/html_examples/daily_menu/meal.html").unwrap();
+        let document = scraper::Html::parse_document(&html);
+        let mut meal = Meal::from_html_element(document.root_element())
+            .expect("The example html should be valid");
+        let profile = DietaryProfile::new(vec![DietaryPreset::Vegan, DietaryPreset::NutFree]);
+        meal.retain_diet(&profile);
+        assert!(!meal.sections.is_empty());
+        for section in &meal.sections {
+            assert!(!section.food_items.is_empty());
+            assert!(section
+                .food_items
+                .iter()
+                .all(|x| x.allergens().contains(&Allergens::Vegan)));
+        }
+        // nothing is both vegan and has milk, so everything is pruned
+        meal.retain_diet(&DietaryProfile {
+            rules: Some(vec![DietaryRule {
+                contains_all_allergens: Some(vec![Allergens::Milk]),
+                ..DietaryRule::default()
+            }]),
+            ..DietaryProfile::default()
+        });
+        assert!(meal.sections.is_empty());
+    }
+
+    #[test]
+    fn test_price_filtering() {
+        let html =
+            fs::read_to_string("./src/parse/html_examples/daily_menu/food_item.html").unwrap();
+        let document = scraper::Html::parse_document(&html);
+        // $1.00
+        let cream_cheese = FoodItem::from_html_element(document.root_element())
+            .expect("The example html should be valid");
+        let section = Section {
+            name: "Bakery".into(),
+            food_items: vec![cream_cheese, FoodItem::with_name("Bagel")],
+        };
+        let names = |min_price, max_price| {
+            section
+                .food_items(None, None, None, None, None, None, min_price, max_price)
+                .iter()
+                .map(|x| x.name().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(None, None), ["Cream Cheese pck", "Bagel"]);
+        // the bagel doesn't have a price, so any price filter leaves it out
+        assert_eq!(names(Some(100), Some(100)), ["Cream Cheese pck"]);
+        assert_eq!(names(None, Some(500)), ["Cream Cheese pck"]);
+        assert!(names(Some(101), None).is_empty());
+        assert!(names(None, Some(99)).is_empty());
+    }
+
+    #[cfg(feature = "graphql")]
     #[tokio::test]
     async fn test_graphql_allergen_filtering() {
         let html = fs::read_to_string("./src/parse/html_examples/daily_menu/meal.html").unwrap();
@@ -254,6 +388,7 @@ mod tests {
         // panic!();
     }
 
+    #[cfg(feature = "graphql")]
     #[tokio::test]
     async fn test_graphql_name_filtering() {
         let html = fs::read_to_string("./src/parse/html_examples/daily_menu/meal.html").unwrap();
@@ -311,4 +446,43 @@ mod tests {
             })
         );
     }
+
+    #[cfg(feature = "graphql")]
+    #[tokio::test]
+    async fn test_graphql_label_allergen_filtering() {
+        let html = fs::read_to_string("./src/parse/html_examples/daily_menu/meal.html").unwrap();
+        let document = scraper::Html::parse_document(&html);
+        let meal = Meal::from_html_element(document.root_element())
+            .expect("The example html should be valid");
+        let schema = RootNode::new(
+            meal,
+            EmptyMutation::<()>::new(),
+            EmptySubscription::<()>::new(),
+        );
+        let query = r#"
+            {
+                sections {
+                    foodItems(excludesLabelAllergens: ["Wheat"]) {
+                        name
+                    }
+                }
+            }
+        "#;
+        let binding = juniper::Variables::default();
+        let res = juniper::execute(query, None, &schema, &binding, &())
+            .await
+            .unwrap()
+            .0;
+        // none of the labels have been fetched, so none of the items are known to be wheat free
+        assert_eq!(
+            serde_json::to_value(res).expect("json should be valid"),
+            json!({
+                "sections": [
+                    { "foodItems": [] },
+                    { "foodItems": [] },
+                    { "foodItems": [] }
+                ]
+            })
+        );
+    }
 }
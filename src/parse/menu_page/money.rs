@@ -10,6 +10,19 @@ impl Usd<'_> {
             rusty_money::iso::USD,
         )?))
     }
+
+    /// The amount in cents, ex. 500 for $5.00
+    pub fn cents(&self) -> i64 {
+        // the menus only have whole cents, so this rounds nothing away
+        let amount = self.0.amount().round_dp(2);
+        let cents = amount.mantissa() * 10_i128.pow(2 - amount.scale());
+        i64::try_from(cents).expect("a menu price should fit in an i64 of cents")
+    }
+
+    /// The ISO 4217 code of the currency, ex. "USD"
+    pub fn currency(&self) -> &'static str {
+        self.0.currency().iso_alpha_code
+    }
 }
 
 impl Display for Usd<'_> {
@@ -30,7 +43,9 @@ impl<'de> serde::Deserialize<'de> for Usd<'_> {
         // remove quotes
         let s = s.trim_matches('"');
         // remove dollar sign
-        let s = &s[1..];
+        let s = s
+            .strip_prefix('$')
+            .ok_or_else(|| serde::de::Error::custom(format!("{s:?} is not a USD amount")))?;
         Self::from_str(s).map_err(serde::de::Error::custom)
     }
 }
@@ -58,11 +73,27 @@ mod tests {
         assert_eq!(deserialized.to_string(), "$5.00");
     }
 
+    #[test]
+    fn test_usd_cents() {
+        let cents = |s| Usd::from_str(s).unwrap().cents();
+        assert_eq!(cents("5.00"), 500);
+        assert_eq!(cents("0.5"), 50);
+        assert_eq!(cents("12"), 1200);
+        assert_eq!(cents("1,250.99"), 125_099);
+        assert_eq!(Usd::from_str("5.00").unwrap().currency(), "USD");
+    }
+
     #[test]
     fn test_serde() {
         let usd = Usd::from_str("5.00").unwrap();
         let serialized = serde_json::to_string(&usd).unwrap();
         let deserialized: Usd = serde_json::from_str(&serialized).unwrap();
         assert_eq!(usd, deserialized);
+        let usd = Usd::from_str("1,250.99").unwrap();
+        let deserialized: Usd =
+            serde_json::from_str(&serde_json::to_string(&usd).unwrap()).unwrap();
+        assert_eq!(usd, deserialized);
+        assert!(serde_json::from_str::<Usd>("\"\"").is_err());
+        assert!(serde_json::from_str::<Usd>("\"5.00\"").is_err());
     }
 }
@@ -0,0 +1,242 @@
+use std::sync::OnceLock;
+
+#[cfg(feature = "graphql")]
+use juniper::GraphQLObject;
+use regex::Regex;
+
+use crate::parse::{remove_excess_whitespace, Error};
+use crate::static_selector;
+
+/// A single line of the nutrition label, ex. "Total Fat 7.8g 10%"
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLObject))]
+pub struct Nutrient {
+    /// Amount per serving, measured in `unit`
+    pub amount: f64,
+    /// Unit of `amount`, ex. "g" or "mg"
+    pub unit: String,
+    /// Percent of the daily value, if the label lists one
+    pub daily_value: Option<i32>,
+}
+
+/// The contents of a "Nutrition Label" page (`label.aspx`)
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "graphql", derive(GraphQLObject))]
+pub struct NutritionFacts {
+    /// Serving size as written on the label, ex. "2 ea"
+    pub serving_size: Option<String>,
+    /// Calories per serving
+    pub calories: Option<i32>,
+    /// The "Total Fat" line
+    pub total_fat: Option<Nutrient>,
+    /// The "Sat. Fat" line
+    pub saturated_fat: Option<Nutrient>,
+    /// The "Trans Fat" line
+    pub trans_fat: Option<Nutrient>,
+    /// The "Cholesterol" line
+    pub cholesterol: Option<Nutrient>,
+    /// The "Sodium" line
+    pub sodium: Option<Nutrient>,
+    /// The "Tot. Carb." line
+    pub total_carbohydrate: Option<Nutrient>,
+    /// The "Dietary Fiber" line
+    pub dietary_fiber: Option<Nutrient>,
+    /// The "Sugars" line
+    pub sugars: Option<Nutrient>,
+    /// The "Protein" line
+    pub protein: Option<Nutrient>,
+    /// Percent of the daily value of vitamin D
+    pub vitamin_d: Option<i32>,
+    /// Percent of the daily value of calcium
+    pub calcium: Option<i32>,
+    /// Percent of the daily value of iron
+    pub iron: Option<i32>,
+    /// Percent of the daily value of potassium
+    pub potassium: Option<i32>,
+}
+
+// labels as they are written on the page, in the same order as `Regexes::nutrients`
+const NUTRIENT_LABELS: [&str; 9] = [
+    "Total Fat",
+    "Sat. Fat",
+    "Trans Fat",
+    "Cholesterol",
+    "Sodium",
+    "Tot. Carb.",
+    "Dietary Fiber",
+    "Sugars",
+    "Protein",
+];
+const MICRONUTRIENT_LABELS: [&str; 4] = ["Vitamin D", "Calcium", "Iron", "Potassium"];
+
+struct Regexes {
+    serving_size: Regex,
+    calories: Regex,
+    nutrients: Vec<Regex>,
+    micronutrients: Vec<Regex>,
+}
+
+fn regexes() -> &'static Regexes {
+    static RE: OnceLock<Regexes> = OnceLock::new();
+    RE.get_or_init(|| {
+        let build = |pattern: &str| Regex::new(pattern).expect("regex should be valid");
+        Regexes {
+            serving_size: build(r"Serving Size\s*(.+?)\s*Calories"),
+            calories: build(r"Calories\s*(\d+)"),
+            // ex. "Total Fat 7.8g 10 %" or "Trans Fat 0.3g"
+            nutrients: NUTRIENT_LABELS
+                .iter()
+                .map(|label| {
+                    build(&format!(
+                        r"{}\s*(\d+(?:\.\d+)?)\s*(mcg|mg|g)(?:\s*(\d+)\s*%)?",
+                        regex::escape(label)
+                    ))
+                })
+                .collect(),
+            // ex. "Vitamin D - mcg 4%" or "Calcium 9%"
+            micronutrients: MICRONUTRIENT_LABELS
+                .iter()
+                .map(|label| build(&format!(r"{}[^%\d]*(\d+)\s*%", regex::escape(label))))
+                .collect(),
+        }
+    })
+}
+
+impl NutritionFacts {
+    /// Parses the nutrition facts table of the nutrition label page
+    // example html at ../html_examples/nutrition/item.html
+    pub fn from_html_element(element: scraper::ElementRef) -> Result<Self, Error> {
+        // the label markup is too malformed to select individual cells reliably,
+        // so the facts are read out of the text of the outermost label table instead
+        static_selector!(LABEL_TABLE_SELECTOR <- "table");
+        let table = element
+            .select(&LABEL_TABLE_SELECTOR)
+            .next()
+            .ok_or_else(|| Error::html_parse_error("Nutrition label table not found"))?;
+        let text = table.text().collect::<Vec<_>>().join(" ");
+        let text = remove_excess_whitespace(&text);
+        if !text.contains("Nutrition Facts") {
+            return Err(Error::html_parse_error(
+                "Nutrition label table does not contain nutrition facts",
+            ));
+        }
+
+        let re = regexes();
+        let serving_size = re
+            .serving_size
+            .captures(&text)
+            .map(|c| c[1].trim().to_string())
+            .filter(|s| !s.is_empty());
+        let calories = re
+            .calories
+            .captures(&text)
+            .map(|c| parse_number(&c[1]))
+            .transpose()?;
+        let mut nutrients = re
+            .nutrients
+            .iter()
+            .map(|re| Nutrient::from_captures(re.captures(&text)))
+            .collect::<Result<Vec<_>, Error>>()?
+            .into_iter();
+        let mut micronutrients = re
+            .micronutrients
+            .iter()
+            .map(|re| re.captures(&text).map(|c| parse_number(&c[1])).transpose())
+            .collect::<Result<Vec<_>, Error>>()?
+            .into_iter();
+        // the iterators yield in the order of `NUTRIENT_LABELS` and `MICRONUTRIENT_LABELS`
+        let mut next_nutrient = || nutrients.next().flatten();
+        let mut next_micronutrient = || micronutrients.next().flatten();
+
+        Ok(Self {
+            serving_size,
+            calories,
+            total_fat: next_nutrient(),
+            saturated_fat: next_nutrient(),
+            trans_fat: next_nutrient(),
+            cholesterol: next_nutrient(),
+            sodium: next_nutrient(),
+            total_carbohydrate: next_nutrient(),
+            dietary_fiber: next_nutrient(),
+            sugars: next_nutrient(),
+            protein: next_nutrient(),
+            vitamin_d: next_micronutrient(),
+            calcium: next_micronutrient(),
+            iron: next_micronutrient(),
+            potassium: next_micronutrient(),
+        })
+    }
+}
+
+impl Nutrient {
+    fn from_captures(captures: Option<regex::Captures>) -> Result<Option<Self>, Error> {
+        let Some(captures) = captures else {
+            return Ok(None);
+        };
+        let amount = captures[1].parse().map_err(|_| {
+            Error::TextNodeParse(format!("Nutrient amount is not a number: {}", &captures[1]))
+        })?;
+        let daily_value = captures
+            .get(3)
+            .map(|dv| parse_number(dv.as_str()))
+            .transpose()?;
+        Ok(Some(Self {
+            amount,
+            unit: captures[2].to_string(),
+            daily_value,
+        }))
+    }
+}
+
+fn parse_number(s: &str) -> Result<i32, Error> {
+    s.parse()
+        .map_err(|_| Error::TextNodeParse(format!("Expected a whole number, found {s}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_from_html_element() {
+        let html = fs::read_to_string("./src/parse/html_examples/nutrition/item.html").unwrap();
+        let document = scraper::Html::parse_document(&html);
+        let facts = NutritionFacts::from_html_element(document.root_element())
+            .expect("The example html should be valid");
+        assert_eq!(facts.serving_size.as_deref(), Some("2 ea"));
+        assert_eq!(facts.calories, Some(202));
+        assert_eq!(
+            facts.total_fat,
+            Some(Nutrient {
+                amount: 7.8,
+                unit: "g".into(),
+                daily_value: Some(10)
+            })
+        );
+        assert_eq!(facts.saturated_fat.unwrap().daily_value, Some(22));
+        let trans_fat = facts.trans_fat.unwrap();
+        assert!((trans_fat.amount - 0.3).abs() < f64::EPSILON);
+        assert_eq!(trans_fat.daily_value, None);
+        let cholesterol = facts.cholesterol.unwrap();
+        assert_eq!(cholesterol.unit, "mg");
+        assert_eq!(cholesterol.daily_value, Some(6));
+        assert_eq!(facts.sodium.unwrap().daily_value, Some(12));
+        assert_eq!(facts.total_carbohydrate.unwrap().daily_value, Some(9));
+        assert_eq!(facts.dietary_fiber.unwrap().daily_value, Some(8));
+        assert_eq!(facts.sugars.unwrap().daily_value, None);
+        assert!((facts.protein.unwrap().amount - 6.5).abs() < f64::EPSILON);
+        assert_eq!(facts.vitamin_d, Some(4));
+        assert_eq!(facts.calcium, Some(9));
+        assert_eq!(facts.iron, Some(8));
+        assert_eq!(facts.potassium, Some(2));
+    }
+
+    #[test]
+    fn test_not_a_label() {
+        let html =
+            fs::read_to_string("./src/parse/html_examples/daily_menu/food_item.html").unwrap();
+        let document = scraper::Html::parse_document(&html);
+        assert!(NutritionFacts::from_html_element(document.root_element()).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use std::{borrow::Cow, ops::Deref};
+
+#[cfg(feature = "graphql")]
+use juniper::{
+    macros::reflect::{BaseSubTypes, BaseType, Type, Types, WrappedType, WrappedValue},
+    marker::{GraphQLObject, IsOutputType},
+    meta::MetaType,
+    Arguments, BoxFuture, ExecutionResult, Executor, GraphQLType, GraphQLValue, GraphQLValueAsync,
+    Registry, ScalarValue, Selection,
+};
+
+use super::{DailyMenu, Location};
+
+/// A location borrowed from `Locations`, or a filtered copy of one
+pub type LocationCow<'r, 'a> = ObjectCow<'r, Location<'a>>;
+
+/// A menu borrowed from a `Location`, or a filtered or archived copy of one
+pub type DailyMenuCow<'r, 'a> = ObjectCow<'r, DailyMenu<'a>>;
+
+/// Part of the menu model borrowed from the cache, or a copy of it which was changed. Juniper
+/// can't return a `Cow`, so this resolves to the same GraphQL type as `T` by delegating to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectCow<'r, T: Clone>(Cow<'r, T>);
+
+impl<T: Clone> ObjectCow<'_, T> {
+    /// The value, cloned if it was borrowed
+    pub fn into_owned(self) -> T {
+        self.0.into_owned()
+    }
+
+    /// The value to change, which is cloned first if it was borrowed
+    pub fn to_mut(&mut self) -> &mut T {
+        self.0.to_mut()
+    }
+}
+
+impl<'r, T: Clone> From<&'r T> for ObjectCow<'r, T> {
+    fn from(value: &'r T) -> Self {
+        Self(Cow::Borrowed(value))
+    }
+}
+
+impl<T: Clone> From<T> for ObjectCow<'_, T> {
+    fn from(value: T) -> Self {
+        Self(Cow::Owned(value))
+    }
+}
+
+impl<T: Clone> Deref for ObjectCow<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(feature = "graphql")]
+impl<S: ScalarValue, T> GraphQLType<S> for ObjectCow<'_, T>
+where
+    T: Clone + GraphQLType<S>,
+{
+    fn name(info: &Self::TypeInfo) -> Option<&str> {
+        <T as GraphQLType<S>>::name(info)
+    }
+
+    fn meta<'r>(info: &Self::TypeInfo, registry: &mut Registry<'r, S>) -> MetaType<'r, S>
+    where
+        S: 'r,
+    {
+        <T as GraphQLType<S>>::meta(info, registry)
+    }
+}
+
+#[cfg(feature = "graphql")]
+impl<S: ScalarValue, T> GraphQLValue<S> for ObjectCow<'_, T>
+where
+    T: Clone + GraphQLValue<S>,
+{
+    type Context = <T as GraphQLValue<S>>::Context;
+    type TypeInfo = <T as GraphQLValue<S>>::TypeInfo;
+
+    fn type_name<'i>(&self, info: &'i Self::TypeInfo) -> Option<&'i str> {
+        (**self).type_name(info)
+    }
+
+    fn resolve_into_type(
+        &self,
+        info: &Self::TypeInfo,
+        name: &str,
+        selection_set: Option<&[Selection<S>]>,
+        executor: &Executor<Self::Context, S>,
+    ) -> ExecutionResult<S> {
+        (**self).resolve_into_type(info, name, selection_set, executor)
+    }
+
+    fn resolve_field(
+        &self,
+        info: &Self::TypeInfo,
+        field: &str,
+        args: &Arguments<S>,
+        executor: &Executor<Self::Context, S>,
+    ) -> ExecutionResult<S> {
+        (**self).resolve_field(info, field, args, executor)
+    }
+
+    fn resolve(
+        &self,
+        info: &Self::TypeInfo,
+        selection_set: Option<&[Selection<S>]>,
+        executor: &Executor<Self::Context, S>,
+    ) -> ExecutionResult<S> {
+        (**self).resolve(info, selection_set, executor)
+    }
+}
+
+#[cfg(feature = "graphql")]
+impl<S, T> GraphQLValueAsync<S> for ObjectCow<'_, T>
+where
+    T: Clone + Sync + GraphQLValueAsync<S>,
+    <T as GraphQLValue<S>>::TypeInfo: Sync,
+    <T as GraphQLValue<S>>::Context: Sync,
+    S: ScalarValue + Send + Sync,
+{
+    fn resolve_field_async<'b>(
+        &'b self,
+        info: &'b Self::TypeInfo,
+        field_name: &'b str,
+        arguments: &'b Arguments<S>,
+        executor: &'b Executor<Self::Context, S>,
+    ) -> BoxFuture<'b, ExecutionResult<S>> {
+        (**self).resolve_field_async(info, field_name, arguments, executor)
+    }
+
+    fn resolve_async<'b>(
+        &'b self,
+        info: &'b Self::TypeInfo,
+        selection_set: Option<&'b [Selection<S>]>,
+        executor: &'b Executor<Self::Context, S>,
+    ) -> BoxFuture<'b, ExecutionResult<S>> {
+        (**self).resolve_async(info, selection_set, executor)
+    }
+}
+
+#[cfg(feature = "graphql")]
+impl<S: ScalarValue, T> GraphQLObject<S> for ObjectCow<'_, T>
+where
+    T: Clone + GraphQLObject<S>,
+{
+    fn mark() {
+        <T as GraphQLObject<S>>::mark();
+    }
+}
+
+#[cfg(feature = "graphql")]
+impl<S: ScalarValue, T> IsOutputType<S> for ObjectCow<'_, T>
+where
+    T: Clone + IsOutputType<S>,
+{
+    fn mark() {
+        <T as IsOutputType<S>>::mark();
+    }
+}
+
+#[cfg(feature = "graphql")]
+impl<S, T> BaseType<S> for ObjectCow<'_, T>
+where
+    T: Clone + BaseType<S>,
+{
+    const NAME: Type = <T as BaseType<S>>::NAME;
+}
+
+#[cfg(feature = "graphql")]
+impl<S, T> BaseSubTypes<S> for ObjectCow<'_, T>
+where
+    T: Clone + BaseSubTypes<S>,
+{
+    const NAMES: Types = <T as BaseSubTypes<S>>::NAMES;
+}
+
+#[cfg(feature = "graphql")]
+impl<S, T> WrappedType<S> for ObjectCow<'_, T>
+where
+    T: Clone + WrappedType<S>,
+{
+    const VALUE: WrappedValue = <T as WrappedType<S>>::VALUE;
+}
@@ -17,7 +17,7 @@ impl<'a> StaticSelector<'a> {
     }
 }
 
-impl<'a> core::ops::Deref for StaticSelector<'a> {
+impl core::ops::Deref for StaticSelector<'_> {
     type Target = Selector;
 
     fn deref(&self) -> &Self::Target {
@@ -29,6 +29,8 @@ impl<'a> core::ops::Deref for StaticSelector<'a> {
     }
 }
 
+// exported so that the modules can `use crate::static_selector`, it isn't part of the API
+#[doc(hidden)]
 #[macro_export]
 macro_rules! static_selector {
     ($x: ident <- $sel: literal) => {
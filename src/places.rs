@@ -0,0 +1,162 @@
+use std::{collections::HashMap, sync::Arc};
+
+use juniper::GraphQLObject;
+
+use crate::{
+    bundled::{Bundled, Overridable},
+    error::Error,
+    location_view::LocationView,
+    parse::{Coordinates, Locations, Place},
+};
+
+/// The places used by `Location.place` and `Query.nearest`, with `places.file` on top of the
+/// bundled `places.toml`. See the comments in it for the format.
+pub static PLACES: Bundled<Places> = Bundled::new("places", include_str!("places.toml"));
+
+/// What kind of location each location is and where it is, by location id
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Places {
+    locations: HashMap<String, Place>,
+}
+
+/// A location near the point `nearest` was asked about
+#[derive(Debug, Clone, GraphQLObject)]
+pub struct NearbyLocation<'a> {
+    pub location: LocationView<'a, 'a>,
+    pub place: Place,
+    /// As the crow flies
+    pub distance_meters: f64,
+}
+
+impl Overridable for Places {
+    type File = Self;
+
+    /// The locations in `file` replace the ones with the same ids
+    fn extend(&mut self, file: Self, name: &str) -> Result<(), Error> {
+        if let Some((id, _)) = file
+            .locations
+            .iter()
+            .find(|(_, place)| !place.coordinates.is_valid())
+        {
+            return Err(Error::Config(format!(
+                "{name} is invalid: the coordinates of location {id} are out of range"
+            )));
+        }
+        self.locations.extend(file.locations);
+        Ok(())
+    }
+}
+
+impl Places {
+    pub fn get(&self, location_id: &str) -> Option<&Place> {
+        self.locations.get(location_id)
+    }
+}
+
+impl<'a> NearbyLocation<'a> {
+    /// The `limit` locations closest to `point`, closest first. Locations without a place are
+    /// left out. The views share `locations` rather than copying each location.
+    pub fn nearest(
+        places: &Places,
+        locations: &Arc<Locations<'a>>,
+        point: Coordinates,
+        limit: usize,
+    ) -> Vec<Self> {
+        let mut nearby: Vec<_> = locations
+            .iter()
+            .enumerate()
+            .filter_map(|(index, location)| {
+                let place = places.get(location.id())?;
+                Some((point.distance_meters(place.coordinates), index, place))
+            })
+            .collect();
+        nearby.sort_by(|a, b| a.0.total_cmp(&b.0));
+        nearby.truncate(limit);
+        nearby
+            .into_iter()
+            .map(|(distance_meters, index, place)| NearbyLocation {
+                location: LocationView::shared(Arc::clone(locations), index),
+                place: place.clone(),
+                distance_meters,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{Location, LocationKind, LocationMeta};
+
+    fn locations(ids: &[&str]) -> Arc<Locations<'static>> {
+        ids.iter()
+            .map(|id| {
+                let url = format!(
+                    "https://nutrition.sa.ucsc.edu/shortmenu.aspx?locationNum={id}&locationName=Location+{id}"
+                );
+                Location::new(LocationMeta::from_url(url.parse().unwrap()).unwrap())
+            })
+            .collect::<Locations>()
+            .into()
+    }
+
+    #[test]
+    fn test_bundled() {
+        let places = PLACES.bundled().unwrap();
+        let nine_lewis = places.get("40").unwrap();
+        assert_eq!(nine_lewis.kind, LocationKind::DiningHall);
+        assert_eq!(nine_lewis.colleges, ["College Nine", "John R. Lewis"]);
+        assert!(places.get("22").unwrap().colleges.is_empty());
+        assert_eq!(places.get("50").unwrap().kind, LocationKind::Market);
+        assert!(places.get("unknown").is_none());
+    }
+
+    #[test]
+    fn test_override() {
+        let places = PLACES
+            .with_contents(
+                r#"
+                [locations.60]
+                kind = "FoodTruck"
+                coordinates = { latitude = 36.9776, longitude = -122.0537 }
+                address = "Quarry Plaza"
+                "#,
+            )
+            .unwrap();
+        assert_eq!(places.get("60").unwrap().kind, LocationKind::FoodTruck);
+        assert!(places.get("40").is_some());
+
+        assert!(PLACES
+            .with_contents(
+                "[locations.60]\nkind = \"Cafe\"\ncoordinates = { latitude = 100.0, longitude = 0.0 }\naddress = \"\""
+            )
+            .is_err());
+        assert!(PLACES
+            .with_contents("[locations.60]\nkind = \"Restaurant\"")
+            .is_err());
+    }
+
+    #[test]
+    fn test_nearest() {
+        let places = PLACES.bundled().unwrap();
+        let locations = locations(&["05", "25", "40", "50", "unknown"]);
+        // the Porter bus stop
+        let point = Coordinates::new(36.9943, -122.0650);
+        let nearest = NearbyLocation::nearest(&places, &locations, point, 3);
+        assert_eq!(
+            nearest.iter().map(|x| x.location.id()).collect::<Vec<_>>(),
+            ["50", "25", "40"]
+        );
+        // only the locations kept have a view of the locations
+        assert_eq!(Arc::strong_count(&locations), 4);
+        assert!(nearest[0].distance_meters < 100.0);
+        assert!(nearest
+            .windows(2)
+            .all(|x| x[0].distance_meters <= x[1].distance_meters));
+        assert_eq!(
+            NearbyLocation::nearest(&places, &locations, point, 10).len(),
+            4
+        );
+    }
+}
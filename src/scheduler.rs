@@ -0,0 +1,295 @@
+use std::{
+    future::Future,
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use rand::Rng;
+use tokio::sync::watch;
+
+use crate::{config::Config, error::Error};
+
+/// When the menus are refreshed: every `interval`, and also at the times of any cron expressions,
+/// ex. more often around meals. Each run is pushed back by a random delay of up to `jitter`, so
+/// that instances which were started together don't all request the dining site at once.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    interval: Duration,
+    jitter: Duration,
+    crons: Vec<cron::Schedule>,
+    timezone: Tz,
+}
+
+impl Schedule {
+    pub const fn new(interval: Duration, jitter: Duration) -> Self {
+        Self {
+            interval,
+            jitter,
+            crons: Vec::new(),
+            timezone: Tz::UTC,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        let interval = config
+            .cache
+            .refresh_interval()
+            .to_std()
+            .map_err(|e| Error::Config(format!("the refresh interval is invalid: {e}")))?;
+        let timezone = Tz::from_str(&config.scheduler.timezone).map_err(|e| {
+            Error::Config(format!(
+                "scheduler.timezone {:?} is invalid: {e}",
+                config.scheduler.timezone
+            ))
+        })?;
+        config.scheduler.cron.iter().try_fold(
+            Self::new(interval, Duration::from_secs(config.scheduler.jitter_secs))
+                .with_timezone(timezone),
+            |schedule, expression| schedule.with_cron(expression),
+        )
+    }
+
+    /// Also runs at the times of `expression`, which is either a standard five field cron
+    /// expression (minute, hour, day of month, month, day of week) or one with a leading seconds
+    /// field. The times are in the schedule's timezone.
+    pub fn with_cron(mut self, expression: &str) -> Result<Self, Error> {
+        let expression = if expression.split_whitespace().count() == 5 {
+            format!("0 {expression}")
+        } else {
+            expression.to_string()
+        };
+        let cron = cron::Schedule::from_str(&expression).map_err(|e| {
+            Error::Config(format!("cron expression {expression:?} is invalid: {e}"))
+        })?;
+        self.crons.push(cron);
+        Ok(self)
+    }
+
+    pub const fn with_timezone(mut self, timezone: Tz) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    /// The first time after `after` that a run is due, before jitter
+    pub fn next_after(&self, after: DateTime<Utc>) -> DateTime<Utc> {
+        let next_interval = TimeDelta::from_std(self.interval)
+            .ok()
+            .and_then(|interval| after.checked_add_signed(interval))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        let local = after.with_timezone(&self.timezone);
+        self.crons
+            .iter()
+            .filter_map(|cron| cron.after(&local).next())
+            .map(|next| next.with_timezone(&Utc))
+            .fold(next_interval, DateTime::min)
+    }
+
+    fn jitter(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return Duration::ZERO;
+        }
+        rand::thread_rng().gen_range(Duration::ZERO..=self.jitter)
+    }
+}
+
+/// Whether scheduled runs are skipped, ex. while the dining site is known to be down
+#[derive(Debug, Default)]
+pub struct Pause(AtomicBool);
+
+impl Pause {
+    pub const fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    pub fn set(&self, paused: bool) {
+        self.0.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Runs `job` right away and then on `schedule`, until `shutdown` is set or its sender is dropped.
+/// A run in progress is finished before returning, and runs which come due while one is still
+/// going are skipped rather than queued up, as are runs which come due while `pause` is set.
+pub async fn run<F, Fut>(
+    schedule: &Schedule,
+    pause: &Pause,
+    mut shutdown: watch::Receiver<bool>,
+    mut job: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    while !*shutdown.borrow() {
+        let started = Utc::now();
+        if pause.is_paused() {
+            tracing::info!("Skipped scheduled refresh, the scheduler is paused");
+        } else {
+            job().await;
+        }
+        let finished = Utc::now();
+
+        let mut next = schedule.next_after(started);
+        let mut skipped = 0;
+        while next <= finished {
+            skipped += 1;
+            next = schedule.next_after(next);
+        }
+        if skipped > 0 {
+            tracing::warn!(
+                "A scheduled refresh took {}s, skipping the {skipped} runs which came due during it",
+                (finished - started).num_seconds()
+            );
+        }
+
+        let wait = (next - finished).to_std().unwrap_or_default() + schedule.jitter();
+        tracing::debug!("Next scheduled refresh in {wait:?}");
+        tokio::select! {
+            () = tokio::time::sleep(wait) => {}
+            changed = shutdown.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    tracing::info!("The refresh scheduler stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_next_after_interval() {
+        let schedule = Schedule::new(Duration::from_mins(15), Duration::ZERO);
+        let now = Utc.with_ymd_and_hms(2024, 4, 5, 12, 0, 0).unwrap();
+        assert_eq!(
+            schedule.next_after(now),
+            Utc.with_ymd_and_hms(2024, 4, 5, 12, 15, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_next_after_cron() {
+        // every 5 minutes through breakfast, in pacific time
+        let schedule = Schedule::new(Duration::from_hours(1), Duration::ZERO)
+            .with_timezone(chrono_tz::America::Los_Angeles)
+            .with_cron("*/5 7-9 * * *")
+            .unwrap();
+        // 7:02 in los angeles
+        let breakfast = Utc.with_ymd_and_hms(2024, 4, 5, 14, 2, 0).unwrap();
+        assert_eq!(
+            schedule.next_after(breakfast),
+            Utc.with_ymd_and_hms(2024, 4, 5, 14, 5, 0).unwrap()
+        );
+        // the interval is sooner than the next cron time in the afternoon
+        let afternoon = Utc.with_ymd_and_hms(2024, 4, 5, 22, 0, 0).unwrap();
+        assert_eq!(
+            schedule.next_after(afternoon),
+            Utc.with_ymd_and_hms(2024, 4, 5, 23, 0, 0).unwrap()
+        );
+
+        assert!(Schedule::new(Duration::from_hours(1), Duration::ZERO)
+            .with_cron("not a cron")
+            .is_err());
+    }
+
+    #[test]
+    fn test_jitter() {
+        let schedule = Schedule::new(Duration::from_mins(15), Duration::from_secs(2));
+        for _ in 0..100 {
+            assert!(schedule.jitter() <= Duration::from_secs(2));
+        }
+        assert_eq!(
+            Schedule::new(Duration::from_mins(15), Duration::ZERO).jitter(),
+            Duration::ZERO
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_until_shutdown() {
+        let schedule = Schedule::new(Duration::from_millis(20), Duration::ZERO);
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        let scheduler = tokio::spawn(async move {
+            run(&schedule, &Pause::new(), shutdown, || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .await;
+        });
+        tokio::time::sleep(Duration::from_millis(110)).await;
+        shutdown_tx.send(true).unwrap();
+        scheduler.await.unwrap();
+        let after_shutdown = runs.load(Ordering::SeqCst);
+        assert!(after_shutdown >= 3, "only ran {after_shutdown} times");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), after_shutdown);
+    }
+
+    #[tokio::test]
+    async fn test_skips_overlapping_runs() {
+        // each run takes as long as five intervals, so runs never start back to back
+        let schedule = Schedule::new(Duration::from_millis(10), Duration::ZERO);
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        let scheduler = tokio::spawn(async move {
+            run(&schedule, &Pause::new(), shutdown, || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await;
+        });
+        tokio::time::sleep(Duration::from_millis(125)).await;
+        shutdown_tx.send(true).unwrap();
+        // the run in progress is finished rather than dropped
+        scheduler.await.unwrap();
+        assert!(runs.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[tokio::test]
+    async fn test_paused() {
+        let schedule = Schedule::new(Duration::from_millis(10), Duration::ZERO);
+        let pause = Arc::new(Pause::new());
+        pause.set(true);
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let runs = Arc::new(AtomicU32::new(0));
+        let (counter, paused) = (runs.clone(), pause.clone());
+        let scheduler = tokio::spawn(async move {
+            run(&schedule, &paused, shutdown, || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .await;
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+        pause.set(false);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown_tx.send(true).unwrap();
+        scheduler.await.unwrap();
+        assert!(runs.load(Ordering::SeqCst) > 0);
+    }
+}
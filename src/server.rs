@@ -0,0 +1,486 @@
+//! The GraphQL server which the `ucsc_menu` binary runs, along with its subcommands
+
+use axum_server as _; // to use rustls over openssl bc alpine linux
+
+use std::{
+    env,
+    sync::{Arc, OnceLock},
+    time::Instant,
+};
+
+use axum::{
+    body::Body,
+    http::{
+        header::{HeaderName, CONTENT_TYPE},
+        Method, StatusCode,
+    },
+    middleware::{from_fn, from_fn_with_state},
+    response::Response,
+    routing::{get, on, MethodFilter},
+    Extension, Router,
+};
+use chrono::{DateTime, Utc};
+
+use crate::{
+    admin::{self, Admin},
+    archive,
+    cache::{AnyStore, FoodSearchHit, Multithreaded, RefreshReport, SearchFilter},
+    cli,
+    config::Config,
+    fetch::Fetcher,
+    hours::{OpenLocation, HOURS},
+    location_view::LocationsView,
+    metrics,
+    parse::{
+        AllergenFilter, Allergens, ChangeSet, Coordinates, DateRange, ItemAppearance, MealType,
+        RefreshScope,
+    },
+    places::{NearbyLocation, PLACES},
+    scheduler::{self, Pause, Schedule},
+    telemetry,
+};
+use futures::{
+    future,
+    stream::{self, BoxStream},
+    Stream, StreamExt,
+};
+use juniper::{graphql_object, graphql_subscription, FieldError, FieldResult, RootNode};
+use juniper_axum::{extract::JuniperRequest, graphiql, playground, response::JuniperResponse, ws};
+use juniper_graphql_ws::ConnectionConfig;
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, watch, OnceCell},
+};
+use tower_http::cors::CorsLayer;
+use tower_http::{compression::CompressionLayer, cors::Any};
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Query;
+
+/// Whether the request carried admin credentials, which the mutations need
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Context {
+    admin: bool,
+}
+
+impl juniper::Context for Context {}
+
+impl Context {
+    fn require_admin(&self) -> FieldResult<()> {
+        if self.admin {
+            Ok(())
+        } else {
+            Err(FieldError::from(
+                "Unauthorized, admin credentials are required",
+            ))
+        }
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+pub(crate) static CACHE: OnceCell<Multithreaded<'static, AnyStore>> = OnceCell::const_new();
+pub(crate) static PAUSE: Pause = Pause::new();
+#[graphql_object(context = Context)]
+impl Query {
+    /// Adds two `a` and `b` numbers.
+    async fn query(&self) -> LocationsView<'static> {
+        let c = CACHE.get_or_init(new_cache);
+        LocationsView(c.await.get().await.locations().to_owned())
+    }
+    /// What changed in each refresh after `since`, which should be the `refreshedAt` of the last
+    /// change set the client synced. Null if the kept history doesn't reach back that far, in
+    /// which case the client should fetch all of the menus again.
+    async fn changes(&self, since: DateTime<Utc>) -> Option<Vec<ChangeSet>> {
+        let c = CACHE.get_or_init(new_cache);
+        c.await.get().await.changes_since(since)
+    }
+    /// The pages which failed to be fetched or parsed in the last refresh. The menus of the
+    /// failed locations and dates are served from the refresh before.
+    async fn refresh_report(&self) -> Option<RefreshReport> {
+        let c = CACHE.get_or_init(new_cache);
+        c.await.get().await.report().cloned()
+    }
+    /// The food items whose name matches `query` at every location and date, best matches
+    /// first. Each word of the query has to match a word of the name, allowing typos in longer
+    /// words and matching the start of words, ex. "chick sandwhich" finds "Chicken Sandwich".
+    #[allow(clippy::too_many_arguments)] // every filter is its own argument, like `foodItems`
+    async fn search_food(
+        query: String,
+        date_range: Option<DateRange>,
+        meal_types: Option<Vec<MealType>>,
+        location_ids: Option<Vec<String>>,
+        contains_all_allergens: Option<Vec<Allergens>>,
+        excludes_all_allergens: Option<Vec<Allergens>>,
+        contains_any_allergens: Option<Vec<Allergens>>,
+        #[graphql(default = 100)] limit: i32,
+    ) -> Vec<FoodSearchHit<'static>> {
+        let filter = SearchFilter {
+            date_range,
+            meal_types,
+            location_ids,
+            allergens: AllergenFilter::new(
+                contains_all_allergens,
+                excludes_all_allergens,
+                contains_any_allergens,
+            ),
+        };
+        let limit = usize::try_from(limit)
+            .unwrap_or_default()
+            .min(MAX_SEARCH_HITS);
+        let c = CACHE.get_or_init(new_cache);
+        c.await.get().await.search(&query, &filter, limit)
+    }
+    /// The locations serving a meal at `at`, now if omitted, with what is on the menu for it
+    async fn open_now(at: Option<DateTime<Utc>>) -> Vec<OpenLocation<'static>> {
+        let c = CACHE.get_or_init(new_cache);
+        let locations = c.await.get().await.shared_locations();
+        OpenLocation::at(HOURS.get(), &locations, at.unwrap_or_else(Utc::now))
+    }
+    /// The locations closest to the point at `lat` and `lon`, in degrees, closest first
+    async fn nearest(
+        lat: f64,
+        lon: f64,
+        #[graphql(default = 5)] limit: i32,
+    ) -> FieldResult<Vec<NearbyLocation<'static>>> {
+        let point = Coordinates::new(lat, lon);
+        if !point.is_valid() {
+            return Err(FieldError::from(
+                "lat must be between -90 and 90, and lon between -180 and 180",
+            ));
+        }
+        let limit = usize::try_from(limit).unwrap_or_default();
+        let c = CACHE.get_or_init(new_cache);
+        let locations = c.await.get().await.shared_locations();
+        Ok(NearbyLocation::nearest(
+            PLACES.get(),
+            &locations,
+            point,
+            limit,
+        ))
+    }
+}
+
+const MAX_SEARCH_HITS: usize = 1000;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Mutation;
+
+/// The same actions as the `/admin` routes, which also need admin credentials
+#[graphql_object(context = Context)]
+impl Mutation {
+    /// Refreshes the menus in `scope` right away, even if they were refreshed recently, and
+    /// returns the report of the refresh. Refreshes everything if `scope` is omitted.
+    async fn refresh_menus(
+        context: &Context,
+        scope: Option<RefreshScope>,
+    ) -> FieldResult<Option<RefreshReport>> {
+        context.require_admin()?;
+        Ok(admin::force_refresh(&scope.unwrap_or_default()).await?)
+    }
+    /// Throws away the cached menus and fetches all of them again
+    async fn invalidate_cache(context: &Context) -> FieldResult<Option<RefreshReport>> {
+        context.require_admin()?;
+        Ok(admin::invalidate_cache().await?)
+    }
+    /// Stops scheduled refreshes until `resumeScheduler`. Returns whether the scheduler is paused.
+    fn pause_scheduler(context: &Context) -> FieldResult<bool> {
+        context.require_admin()?;
+        admin::set_paused(true);
+        Ok(PAUSE.is_paused())
+    }
+    fn resume_scheduler(context: &Context) -> FieldResult<bool> {
+        context.require_admin()?;
+        admin::set_paused(false);
+        Ok(PAUSE.is_paused())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Subscription;
+
+#[graphql_subscription(context = Context)]
+impl Subscription {
+    /// Fires after each refresh which changed the menus of `locationIds`, or of any location if
+    /// it is omitted, with only the changes to those locations
+    async fn menu_updated(location_ids: Option<Vec<String>>) -> BoxStream<'static, ChangeSet> {
+        let updates = CACHE.get_or_init(new_cache).await.subscribe();
+        change_sets(updates)
+            .filter_map(move |changes| {
+                let changes = match &location_ids {
+                    Some(ids) => changes.for_locations(ids),
+                    None => changes,
+                };
+                future::ready((!changes.menus.is_empty()).then_some(changes))
+            })
+            .boxed()
+    }
+
+    /// Fires for each food item which shows up on an upcoming menu, if its name contains
+    /// `nameContains` (ignoring case) and it has all of `allergens`
+    async fn item_appeared(
+        name_contains: Option<String>,
+        allergens: Option<Vec<Allergens>>,
+    ) -> BoxStream<'static, ItemAppearance<'static>> {
+        let cache = CACHE.get_or_init(new_cache).await;
+        change_sets(cache.subscribe())
+            .then(move |changes| async move {
+                let today = cache.today();
+                cache
+                    .get()
+                    .await
+                    .locations()
+                    .appeared_items(&changes, today)
+            })
+            .flat_map(stream::iter)
+            .filter(move |item| {
+                future::ready(item.matches(
+                    name_contains.as_deref(),
+                    allergens.as_deref().unwrap_or_default(),
+                ))
+            })
+            .boxed()
+    }
+}
+
+fn change_sets(updates: broadcast::Receiver<ChangeSet>) -> impl Stream<Item = ChangeSet> {
+    stream::unfold(updates, |mut updates| async move {
+        loop {
+            match updates.recv().await {
+                Ok(changes) => return Some((changes, updates)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("A subscriber fell behind and missed {skipped} refreshes");
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+type Schema = RootNode<'static, Query, Mutation, Subscription>;
+
+async fn graphql(
+    Extension(schema): Extension<Arc<Schema>>,
+    admin: Option<Extension<Admin>>,
+    JuniperRequest(request): JuniperRequest,
+) -> JuniperResponse {
+    let context = Context {
+        admin: admin.is_some(),
+    };
+    let start = Instant::now();
+    let response = request.execute(&*schema, &context).await;
+    metrics::get().graphql_request(start.elapsed(), response.is_ok());
+    JuniperResponse(response)
+}
+
+fn config() -> &'static Config {
+    CONFIG
+        .get_or_init(|| Config::load().unwrap_or_else(|e| panic!("failed to load the config: {e}")))
+}
+
+pub(crate) async fn new_cache() -> Multithreaded<'static, AnyStore> {
+    let config = config();
+    let store = AnyStore::from_config(&config.cache)
+        .await
+        .unwrap_or_else(|e| panic!("failed to open the cache store: {e}"));
+    let fetcher = Fetcher::from_config(&config.fetch)
+        .unwrap_or_else(|e| panic!("failed to set up fetching: {e}"));
+    Multithreaded::new(store, fetcher, config.cache.clone())
+        .await
+        .unwrap_or_else(|e| panic!("failed to open the menu cache: {e}"))
+}
+
+#[allow(clippy::significant_drop_tightening)] // false positive, `cache` is a static reference
+async fn refresh() -> Response {
+    let cache = CACHE.get_or_init(new_cache).await;
+    let _res = cache.refresh().await;
+    let c = cache.get().await;
+    Response::builder()
+        .status(201)
+        .body(Body::from(format!(
+            "Last refresh: {}\nNext refresh: {}\nUpstream circuit breaker: {}",
+            c.get_time_since_refresh(),
+            c.get_time_until_refresh(cache.config()),
+            cache.fetcher().breaker_state(),
+        )))
+        .unwrap()
+}
+
+/// The process is up, whether or not the menus are loaded yet or the dining site is down. Also
+/// says whether requests to the dining site are paused, once the menus are loaded.
+async fn healthz() -> String {
+    CACHE.get().map_or_else(
+        || "ok".to_string(),
+        |cache| {
+            format!(
+                "ok\nUpstream circuit breaker: {}",
+                cache.fetcher().breaker_state()
+            )
+        },
+    )
+}
+
+/// Fails until the menus are first loaded from the store, so no traffic is sent before then
+async fn readyz() -> (StatusCode, &'static str) {
+    if CACHE.initialized() {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "loading the menus")
+    }
+}
+
+async fn metrics() -> ([(HeaderName, &'static str); 1], String) {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::get().render(),
+    )
+}
+
+async fn scheduled_refresh(min_age: chrono::Duration) {
+    let cache = CACHE.get_or_init(new_cache).await;
+    let start = Instant::now();
+    match cache.refresh_if_older(min_age).await {
+        Ok(true) => tracing::info!("Scheduled refresh done, took {:?}", start.elapsed()),
+        Ok(false) => tracing::info!("Skipped scheduled refresh, the menus are already fresh"),
+        Err(e) => tracing::error!("Scheduled refresh failed: {e}"),
+    }
+}
+
+/// Loads the hours and places with the files of the config on top
+fn init_bundled(config: &Config) {
+    let hours = HOURS
+        .load(&config.hours)
+        .expect("hours should have been validated");
+    if let Some(last) = hours.holidays_ended(Utc::now()) {
+        tracing::warn!(
+            "The last holiday in the service hours is {last}, so the locations show as open on \
+             the holidays after it. Add them to `hours.file`."
+        );
+    }
+    HOURS.init(hours);
+    PLACES.init(
+        PLACES
+            .load(&config.places)
+            .expect("places should have been validated"),
+    );
+}
+
+/// Runs the subcommand given on the command line, or else the server until it is asked to shut
+/// down. This is the whole of the `ucsc_menu` binary.
+pub async fn main() {
+    let config = config();
+    let _telemetry = telemetry::init(&config.log);
+    let addr = config.addr();
+    match cli::Command::from_args(env::args().skip(1)) {
+        Ok(None) => {}
+        Ok(Some((command, options))) => {
+            if let Err(e) = cli::run(command, options, config).await {
+                eprintln!("error: {e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {e}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    }
+    if let Some(path) = &config.archive.path {
+        let archive = archive::Archive::open(path).unwrap_or_else(|e| {
+            panic!("failed to open the menu archive at {}: {e}", path.display())
+        });
+        archive::init(archive);
+    }
+    init_bundled(config);
+    let schema = Schema::new(Query, Mutation, Subscription);
+    let comression_layer: CompressionLayer = CompressionLayer::new()
+        .br(true)
+        .deflate(true)
+        .gzip(true)
+        .zstd(true);
+    let cors_layer = CorsLayer::new()
+        .allow_methods([Method::GET, Method::POST]) // intentionally excludes request-refresh/PUT
+        .allow_origin(Any);
+    if config.admin.token.is_none() && config.admin.hmac_secret.is_none() {
+        tracing::warn!(
+            "Neither admin.token nor admin.hmac_secret is set, so the admin API is disabled"
+        );
+    }
+
+    let app = Router::new()
+        .route(
+            "/graphql",
+            on(MethodFilter::GET.or(MethodFilter::POST), graphql),
+        )
+        .route(
+            "/subscriptions",
+            get(ws::<Arc<Schema>>(ConnectionConfig::new(Context::default()))),
+        )
+        // refreshes if the menus are stale, which is a scrape, so it needs admin credentials too
+        .route(
+            "/request-refresh",
+            on(MethodFilter::PUT, refresh).route_layer(from_fn(admin::require_admin)),
+        )
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(metrics))
+        .nest("/admin", admin::routes())
+        .route("/graphiql", get(graphiql("/graphql", "/subscriptions")))
+        .route("/playground", get(playground("/graphql", "/subscriptions")))
+        .layer(from_fn_with_state(&config.admin, admin::authenticate))
+        .layer(cors_layer)
+        .layer(Extension(Arc::new(schema)))
+        .layer(comression_layer);
+    let schedule = Schedule::from_config(config).expect("the config should have been validated");
+    let (shutdown_tx, shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!("Shutting down, waiting for requests and any refresh in progress to finish");
+        let _ = shutdown_tx.send(true);
+    });
+    let listener = TcpListener::bind(addr)
+        .await
+        .unwrap_or_else(|e| panic!("failed to listen on {addr}: {e}"));
+    tracing::info!("listening on http://{addr}");
+    let mut stopped = shutdown.clone();
+    let server = tokio::spawn(async move {
+        axum::serve(listener, app)
+            .with_graceful_shutdown(async move {
+                let _ = stopped.wait_for(|x| *x).await;
+            })
+            .await
+            .unwrap_or_else(|e| panic!("failed to run `axum::serve`: {e}"));
+    });
+    // the server is already up so that `/healthz` answers, while `/readyz` waits for this
+    CACHE.get_or_init(new_cache).await;
+    tracing::info!("The menus are loaded, ready for requests");
+    let refreshes = tokio::spawn(async move {
+        let min_age = config.scheduler.min_age();
+        scheduler::run(&schedule, &PAUSE, shutdown, || scheduled_refresh(min_age)).await;
+    });
+    if let Err(e) = server.await {
+        std::panic::resume_unwind(e.into_panic());
+    }
+    let _ = refreshes.await;
+}
+
+/// Resolves on SIGTERM, which is how cloud run and docker stop the server, or on ctrl-c
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .unwrap_or_else(|e| panic!("failed to listen for ctrl-c: {e}"));
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .unwrap_or_else(|e| panic!("failed to listen for SIGTERM: {e}"))
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = future::pending::<()>();
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
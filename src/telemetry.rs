@@ -0,0 +1,122 @@
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+use crate::config::{LogConfig, LogFormat};
+
+/// Flushes the spans which haven't been exported yet when dropped, so it should be held until
+/// the process exits
+#[derive(Debug)]
+#[must_use = "spans may not be exported if this is dropped early"]
+pub struct Telemetry {
+    #[cfg(feature = "otlp")]
+    exporting: bool,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if self.exporting {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+/// Sends events and spans to stderr in `config.format`, and to the OTLP endpoint if one is set.
+/// Records of the `log` crate, which some dependencies still use, are sent along with them.
+pub fn init(config: &LogConfig) -> Telemetry {
+    let filter = EnvFilter::try_new(&config.filter).expect("the filter should have been validated");
+    let output = match config.format {
+        LogFormat::Pretty => fmt::layer().with_writer(std::io::stderr).boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_writer(std::io::stderr)
+            .boxed(),
+    };
+    let registry = tracing_subscriber::registry().with(filter).with(output);
+
+    #[cfg(feature = "otlp")]
+    {
+        let tracer = config.otlp_endpoint.as_ref().map(|endpoint| {
+            otlp_tracer(endpoint, &config.service_name)
+                .unwrap_or_else(|e| panic!("failed to set up exporting to {endpoint}: {e}"))
+        });
+        let exporting = tracer.is_some();
+        registry
+            .with(tracer.map(|x| tracing_opentelemetry::layer().with_tracer(x)))
+            .init();
+        Telemetry { exporting }
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        registry.init();
+        if config.otlp_endpoint.is_some() {
+            tracing::warn!(
+                "log.otlp_endpoint is set, but spans are only exported with the `otlp` feature"
+            );
+        }
+        Telemetry {}
+    }
+}
+
+#[cfg(feature = "otlp")]
+fn otlp_tracer(
+    endpoint: &str,
+    service_name: &str,
+) -> Result<opentelemetry_sdk::trace::Tracer, opentelemetry::trace::TraceError> {
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{runtime, trace, Resource};
+
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(traces_url(endpoint)),
+        )
+        .with_trace_config(trace::config().with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            service_name.to_string(),
+        )])))
+        .install_batch(runtime::TokioCurrentThread)
+}
+
+/// The url spans are posted to. An endpoint set in code is used as is by the exporter, unlike
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` which the spec says is a base url, so the path is added here.
+#[cfg(any(feature = "otlp", test))]
+fn traces_url(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    if endpoint.ends_with("/v1/traces") {
+        endpoint.to_string()
+    } else {
+        format!("{endpoint}/v1/traces")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traces_url() {
+        assert_eq!(
+            traces_url("http://localhost:4318"),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_url("http://localhost:4318/"),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_url("https://collector.example.com/otlp"),
+            "https://collector.example.com/otlp/v1/traces"
+        );
+        // already the full url
+        assert_eq!(
+            traces_url("http://localhost:4318/v1/traces"),
+            "http://localhost:4318/v1/traces"
+        );
+    }
+}
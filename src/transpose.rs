@@ -1,7 +1,8 @@
 pub fn transposed<T>(initial: Vec<Vec<T>>) -> Vec<Vec<T>> {
     // uses mem::swap to avoid cloning
-    let mut transposed = Vec::with_capacity(initial[0].len());
-    for _ in 0..initial[0].len() {
+    let width = initial.first().map_or(0, Vec::len);
+    let mut transposed = Vec::with_capacity(width);
+    for _ in 0..width {
         transposed.push(Vec::with_capacity(initial.len()));
     }
     for row in initial {
@@ -22,4 +23,9 @@ mod tests {
         let after = transposed(initial);
         assert_eq!(after, vec![vec![1, 4, 7], vec![2, 5, 8], vec![3, 6, 9]]);
     }
+
+    #[test]
+    fn test_transposed_empty() {
+        assert!(transposed(Vec::<Vec<i32>>::new()).is_empty());
+    }
 }